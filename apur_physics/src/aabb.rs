@@ -0,0 +1,79 @@
+use glam::Vec3;
+
+use super::shape::Pose;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_center(center: Vec3, half_extents: Vec3) -> Self {
+        Self { min: center - half_extents, max: center + half_extents }
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let mut aabb = Self::empty();
+        for &p in points {
+            aabb.min = aabb.min.min(p);
+            aabb.max = aabb.max.max(p);
+        }
+        aabb
+    }
+
+    /// An inverted box that any `union` will replace.
+    pub fn empty() -> Self {
+        Self { min: Vec3::splat(f32::MAX), max: Vec3::splat(-f32::MAX) }
+    }
+
+    pub fn get_center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn get_half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    pub fn expanded(&self, margin: f32) -> Aabb {
+        Aabb { min: self.min - Vec3::splat(margin), max: self.max + Vec3::splat(margin) }
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x() <= other.max.x() && self.max.x() >= other.min.x()
+            && self.min.y() <= other.max.y() && self.max.y() >= other.min.y()
+            && self.min.z() <= other.max.z() && self.max.z() >= other.min.z()
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.x() <= other.min.x() && self.max.x() >= other.max.x()
+            && self.min.y() <= other.min.y() && self.max.y() >= other.max.y()
+            && self.min.z() <= other.min.z() && self.max.z() >= other.max.z()
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    /// Bounds of this box after it's moved by `pose`.
+    pub fn transformed(&self, pose: &Pose) -> Aabb {
+        let center = pose.transform_point(self.get_center());
+        let half = self.get_half_extents();
+        let rot = glam::Mat3::from_quat(pose.orientation);
+        let abs_half = vec_abs(rot.x_axis()) * half.x() + vec_abs(rot.y_axis()) * half.y() + vec_abs(rot.z_axis()) * half.z();
+        Aabb::from_center(center, abs_half)
+    }
+}
+
+pub(crate) fn vec_abs(v: Vec3) -> Vec3 {
+    Vec3::new(v.x().abs(), v.y().abs(), v.z().abs())
+}
@@ -0,0 +1,274 @@
+use glam::{Mat3, Mat4, Quat, Vec3};
+
+use super::aabb::Aabb;
+use super::shape::{Pose, Shape};
+
+pub struct RigidBody {
+    position: Vec3,
+    orientation: Quat,
+    linear_velocity: Vec3,
+    angular_velocity: Vec3,
+    inv_mass: f32,
+    local_inertia: Mat3,
+    local_inv_inertia: Mat3,
+    force: Vec3,
+    torque: Vec3,
+    linear_damping: f32,
+    angular_damping: f32,
+    shape: Option<Shape>,
+    friction: f32,
+    restitution: f32,
+}
+
+impl RigidBody {
+    /// A body with `mass <= 0.0` is treated as static (infinite mass).
+    pub fn new(mass: f32, inertia: Mat3) -> Self {
+        if mass <= 0.0 {
+            return Self::new_static();
+        }
+
+        Self {
+            inv_mass: 1.0 / mass,
+            local_inertia: inertia,
+            local_inv_inertia: inertia.inverse(),
+            ..Self::new_static()
+        }
+    }
+
+    /// Mass and inertia come from the shape, a `density` of zero makes the body static.
+    pub fn from_shape(shape: Shape, density: f32) -> Self {
+        let (mass, inertia) = shape.mass_properties(density);
+        let mut body = Self::new(mass, inertia);
+        body.shape = Some(shape);
+        body
+    }
+
+    pub fn new_static() -> Self {
+        Self {
+            position: Vec3::zero(),
+            orientation: Quat::identity(),
+            linear_velocity: Vec3::zero(),
+            angular_velocity: Vec3::zero(),
+            inv_mass: 0.0,
+            local_inertia: Mat3::zero(),
+            local_inv_inertia: Mat3::zero(),
+            force: Vec3::zero(),
+            torque: Vec3::zero(),
+            linear_damping: 0.01,
+            angular_damping: 0.05,
+            shape: None,
+            friction: 0.5,
+            restitution: 0.0,
+        }
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.inv_mass == 0.0
+    }
+
+    pub fn get_mass(&self) -> f32 {
+        if self.is_static() { 0.0 } else { 1.0 / self.inv_mass }
+    }
+
+    pub fn get_inv_mass(&self) -> f32 {
+        self.inv_mass
+    }
+
+    pub fn get_position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position;
+    }
+
+    pub fn get_orientation(&self) -> Quat {
+        self.orientation
+    }
+
+    pub fn set_orientation(&mut self, orientation: Quat) {
+        self.orientation = orientation.normalize();
+    }
+
+    pub fn get_linear_velocity(&self) -> Vec3 {
+        self.linear_velocity
+    }
+
+    pub fn set_linear_velocity(&mut self, velocity: Vec3) {
+        self.linear_velocity = velocity;
+    }
+
+    pub fn get_angular_velocity(&self) -> Vec3 {
+        self.angular_velocity
+    }
+
+    pub fn set_angular_velocity(&mut self, velocity: Vec3) {
+        self.angular_velocity = velocity;
+    }
+
+    pub fn get_shape(&self) -> Option<&Shape> {
+        self.shape.as_ref()
+    }
+
+    pub fn set_shape(&mut self, shape: Option<Shape>) {
+        self.shape = shape;
+    }
+
+    pub fn get_pose(&self) -> Pose {
+        Pose::new(self.position, self.orientation)
+    }
+
+    pub fn get_aabb(&self) -> Option<Aabb> {
+        self.shape.as_ref().map(|s| s.get_aabb(&self.get_pose()))
+    }
+
+    pub fn get_friction(&self) -> f32 {
+        self.friction
+    }
+
+    pub fn set_friction(&mut self, friction: f32) {
+        self.friction = friction;
+    }
+
+    pub fn get_restitution(&self) -> f32 {
+        self.restitution
+    }
+
+    pub fn set_restitution(&mut self, restitution: f32) {
+        self.restitution = restitution;
+    }
+
+    pub fn set_damping(&mut self, linear: f32, angular: f32) {
+        self.linear_damping = linear;
+        self.angular_damping = angular;
+    }
+
+    /// Model matrix placing the body in the world, ready to be fed to the renderer.
+    pub fn get_transform(&self) -> Mat4 {
+        Mat4::from_rotation_translation(self.orientation, self.position)
+    }
+
+    pub fn get_world_inv_inertia(&self) -> Mat3 {
+        let rot = Mat3::from_quat(self.orientation);
+        rot * self.local_inv_inertia * rot.transpose()
+    }
+
+    fn get_world_inertia(&self) -> Mat3 {
+        let rot = Mat3::from_quat(self.orientation);
+        rot * self.local_inertia * rot.transpose()
+    }
+
+    /// Velocity of a point fixed to the body, given in world space.
+    pub fn get_point_velocity(&self, world_point: Vec3) -> Vec3 {
+        self.linear_velocity + self.angular_velocity.cross(world_point - self.position)
+    }
+
+    pub fn apply_force(&mut self, force: Vec3) {
+        self.force += force;
+    }
+
+    pub fn apply_torque(&mut self, torque: Vec3) {
+        self.torque += torque;
+    }
+
+    pub fn apply_force_at_point(&mut self, force: Vec3, world_point: Vec3) {
+        self.force += force;
+        self.torque += (world_point - self.position).cross(force);
+    }
+
+    pub fn apply_impulse(&mut self, impulse: Vec3, world_point: Vec3) {
+        if self.is_static() {
+            return;
+        }
+        self.linear_velocity += impulse * self.inv_mass;
+        self.angular_velocity += self.get_world_inv_inertia() * (world_point - self.position).cross(impulse);
+    }
+
+    pub(crate) fn integrate_velocity(&mut self, dt: f32, gravity: Vec3) {
+        if self.is_static() {
+            return;
+        }
+
+        self.linear_velocity += (gravity + self.force * self.inv_mass) * dt;
+
+        // gyroscopic term keeps spinning bodies with uneven inertia from gaining energy
+        let w = self.angular_velocity;
+        let gyro = w.cross(self.get_world_inertia() * w);
+        self.angular_velocity += self.get_world_inv_inertia() * (self.torque - gyro) * dt;
+
+        self.linear_velocity *= 1.0 / (1.0 + dt * self.linear_damping);
+        self.angular_velocity *= 1.0 / (1.0 + dt * self.angular_damping);
+    }
+
+    pub(crate) fn integrate_position(&mut self, dt: f32) {
+        if self.is_static() {
+            return;
+        }
+
+        self.position += self.linear_velocity * dt;
+
+        // dq/dt = 0.5 * w * q, glam doesn't have quaternion addition so do it per component
+        let w = self.angular_velocity;
+        let spin = Quat::from_xyzw(w.x(), w.y(), w.z(), 0.0) * self.orientation;
+        let q = self.orientation;
+        let h = 0.5 * dt;
+        self.orientation = Quat::from_xyzw(
+            q.x() + spin.x() * h,
+            q.y() + spin.y() * h,
+            q.z() + spin.z() * h,
+            q.w() + spin.w() * h,
+        ).normalize();
+    }
+
+    pub(crate) fn clear_accumulators(&mut self) {
+        self.force = Vec3::zero();
+        self.torque = Vec3::zero();
+    }
+}
+
+pub fn sphere_inertia(mass: f32, radius: f32) -> Mat3 {
+    let i = 0.4 * mass * radius * radius;
+    Mat3::from_cols(Vec3::new(i, 0.0, 0.0), Vec3::new(0.0, i, 0.0), Vec3::new(0.0, 0.0, i))
+}
+
+pub fn box_inertia(mass: f32, half_extents: Vec3) -> Mat3 {
+    let s = 4.0 * half_extents * half_extents;
+    let k = mass / 12.0;
+    Mat3::from_cols(
+        Vec3::new(k * (s.y() + s.z()), 0.0, 0.0),
+        Vec3::new(0.0, k * (s.x() + s.z()), 0.0),
+        Vec3::new(0.0, 0.0, k * (s.x() + s.y())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_body_ignores_forces() {
+        let mut body = RigidBody::new_static();
+        body.apply_force(Vec3::new(10.0, 0.0, 0.0));
+        body.integrate_velocity(0.1, Vec3::new(0.0, -9.81, 0.0));
+        body.integrate_position(0.1);
+        assert_eq!(body.get_position(), Vec3::zero());
+    }
+
+    #[test]
+    fn impulse_off_center_spins_body() {
+        let mut body = RigidBody::new(1.0, box_inertia(1.0, Vec3::one()));
+        body.apply_impulse(Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!((body.get_linear_velocity().z() - 1.0).abs() < 1e-6);
+        assert!(body.get_angular_velocity().y() < 0.0);
+    }
+
+    #[test]
+    fn orientation_stays_normalized() {
+        let mut body = RigidBody::new(2.0, sphere_inertia(2.0, 0.5));
+        body.set_angular_velocity(Vec3::new(3.0, -7.0, 11.0));
+        for _ in 0..1000 {
+            body.integrate_position(1.0 / 60.0);
+        }
+        assert!((body.get_orientation().length() - 1.0).abs() < 1e-4);
+    }
+}
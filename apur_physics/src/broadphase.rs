@@ -0,0 +1,521 @@
+use glam::{Vec3, Vec4};
+
+use super::aabb::Aabb;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct ProxyId(usize);
+
+enum NodeKind<T> {
+    Leaf(T),
+    Inner { left: usize, right: usize },
+    Free,
+}
+
+struct Node<T> {
+    aabb: Aabb,
+    parent: Option<usize>,
+    height: i32,
+    kind: NodeKind<T>,
+}
+
+impl<T> Node<T> {
+    fn children(&self) -> Option<(usize, usize)> {
+        match self.kind {
+            NodeKind::Inner { left, right } => Some((left, right)),
+            _ => None,
+        }
+    }
+}
+
+/// Dynamic bounding volume tree in the spirit of Box2D's: leaves hold fattened
+/// boxes so small movements don't need a reinsert, and the tree is kept
+/// balanced with rotations.
+pub struct DynamicTree<T> {
+    nodes: Vec<Node<T>>,
+    root: Option<usize>,
+    free_list: Vec<usize>,
+    margin: f32,
+}
+
+impl<T> DynamicTree<T> {
+
+    pub const DEFAULT_MARGIN: f32 = 0.1;
+
+    pub fn new(margin: f32) -> Self {
+        Self { nodes: vec![], root: None, free_list: vec![], margin }
+    }
+
+    pub fn insert(&mut self, aabb: Aabb, data: T) -> ProxyId {
+        let leaf = self.allocate(Node {
+            aabb: aabb.expanded(self.margin),
+            parent: None,
+            height: 0,
+            kind: NodeKind::Leaf(data),
+        });
+        self.insert_leaf(leaf);
+        ProxyId(leaf)
+    }
+
+    pub fn remove(&mut self, id: ProxyId) -> T {
+        self.remove_leaf(id.0);
+        let node = std::mem::replace(&mut self.nodes[id.0].kind, NodeKind::Free);
+        self.free_list.push(id.0);
+        match node {
+            NodeKind::Leaf(data) => data,
+            _ => panic!("proxy {:?} is not a leaf", id),
+        }
+    }
+
+    /// Moves a proxy, returns true if it had to be reinserted because it left its fat box.
+    pub fn update(&mut self, id: ProxyId, aabb: Aabb) -> bool {
+        if self.nodes[id.0].aabb.contains(&aabb) {
+            return false;
+        }
+        self.remove_leaf(id.0);
+        self.nodes[id.0].aabb = aabb.expanded(self.margin);
+        self.insert_leaf(id.0);
+        true
+    }
+
+    pub fn get_data(&self, id: ProxyId) -> &T {
+        match &self.nodes[id.0].kind {
+            NodeKind::Leaf(data) => data,
+            _ => panic!("proxy {:?} is not a leaf", id),
+        }
+    }
+
+    pub fn get_fat_aabb(&self, id: ProxyId) -> &Aabb {
+        &self.nodes[id.0].aabb
+    }
+
+    pub fn get_height(&self) -> i32 {
+        self.root.map_or(0, |r| self.nodes[r].height)
+    }
+
+    /// Visits every leaf for which `overlaps` accepts all the boxes on the way down.
+    /// Returning false from `callback` stops the query.
+    pub fn query(&self, overlaps: impl Fn(&Aabb) -> bool, mut callback: impl FnMut(ProxyId) -> bool) {
+        let mut stack = match self.root {
+            Some(root) => vec![root],
+            None => return,
+        };
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if !overlaps(&node.aabb) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf(_) => {
+                    if !callback(ProxyId(idx)) {
+                        return;
+                    }
+                },
+                NodeKind::Inner { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                },
+                NodeKind::Free => unreachable!("free node in tree"),
+            }
+        }
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb, callback: impl FnMut(ProxyId) -> bool) {
+        self.query(|b| b.intersects(aabb), callback);
+    }
+
+    /// Visits leaves whose box the ray `origin + t * dir` hits for `t` in `[0, max_t]`.
+    pub fn query_ray(&self, origin: Vec3, dir: Vec3, max_t: f32, callback: impl FnMut(ProxyId) -> bool) {
+        self.query(|b| matches!(ray_aabb(origin, dir, b), Some(t) if t <= max_t), callback);
+    }
+
+    /// Visits leaves inside or crossing all `planes`, given as `(normal, d)` with
+    /// `dot(normal, p) + d >= 0` on the inner side like the ones extracted from a
+    /// view projection matrix.
+    pub fn query_frustum(&self, planes: &[Vec4], callback: impl FnMut(ProxyId) -> bool) {
+        self.query(|b| planes.iter().all(|p| {
+            // test the corner furthest along the plane normal
+            let n = p.truncate();
+            let corner = Vec3::new(
+                if n.x() >= 0.0 { b.max.x() } else { b.min.x() },
+                if n.y() >= 0.0 { b.max.y() } else { b.min.y() },
+                if n.z() >= 0.0 { b.max.z() } else { b.min.z() },
+            );
+            n.dot(corner) + p.w() >= 0.0
+        }), callback);
+    }
+
+    /// All pairs of leaves with overlapping fat boxes, each reported once with the smaller id first.
+    pub fn query_pairs(&self) -> Vec<(ProxyId, ProxyId)> {
+        let mut pairs = vec![];
+        if let Some(root) = self.root {
+            self.pairs_within(root, &mut pairs);
+        }
+        pairs
+    }
+
+    fn pairs_within(&self, idx: usize, pairs: &mut Vec<(ProxyId, ProxyId)>) {
+        if let Some((left, right)) = self.nodes[idx].children() {
+            self.pairs_within(left, pairs);
+            self.pairs_within(right, pairs);
+            self.pairs_between(left, right, pairs);
+        }
+    }
+
+    fn pairs_between(&self, a: usize, b: usize, pairs: &mut Vec<(ProxyId, ProxyId)>) {
+        if !self.nodes[a].aabb.intersects(&self.nodes[b].aabb) {
+            return;
+        }
+        match (self.nodes[a].children(), self.nodes[b].children()) {
+            (None, None) => pairs.push((ProxyId(a.min(b)), ProxyId(a.max(b)))),
+            (Some((l, r)), None) => {
+                self.pairs_between(l, b, pairs);
+                self.pairs_between(r, b, pairs);
+            },
+            (None, Some((l, r))) => {
+                self.pairs_between(a, l, pairs);
+                self.pairs_between(a, r, pairs);
+            },
+            (Some((al, ar)), Some((bl, br))) => {
+                // split the bigger node so both sides of the test stay similar in size
+                if self.nodes[a].aabb.surface_area() > self.nodes[b].aabb.surface_area() {
+                    self.pairs_between(al, b, pairs);
+                    self.pairs_between(ar, b, pairs);
+                } else {
+                    self.pairs_between(a, bl, pairs);
+                    self.pairs_between(a, br, pairs);
+                }
+            },
+        }
+    }
+
+    fn allocate(&mut self, node: Node<T>) -> usize {
+        if let Some(idx) = self.free_list.pop() {
+            self.nodes[idx] = node;
+            idx
+        } else {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        }
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        let root = match self.root {
+            Some(root) => root,
+            None => {
+                self.root = Some(leaf);
+                self.nodes[leaf].parent = None;
+                return;
+            },
+        };
+
+        // walk down picking the child that costs the least surface area
+        let leaf_aabb = self.nodes[leaf].aabb;
+        let mut sibling = root;
+        while let Some((left, right)) = self.nodes[sibling].children() {
+            let area = self.nodes[sibling].aabb.surface_area();
+            let combined_area = self.nodes[sibling].aabb.union(&leaf_aabb).surface_area();
+            let cost = 2.0 * combined_area;
+            let inheritance = 2.0 * (combined_area - area);
+
+            let child_cost = |child: usize| {
+                let merged = leaf_aabb.union(&self.nodes[child].aabb).surface_area();
+                match self.nodes[child].kind {
+                    NodeKind::Leaf(_) => merged + inheritance,
+                    _ => merged - self.nodes[child].aabb.surface_area() + inheritance,
+                }
+            };
+            let cost_left = child_cost(left);
+            let cost_right = child_cost(right);
+
+            if cost < cost_left && cost < cost_right {
+                break;
+            }
+            sibling = if cost_left < cost_right { left } else { right };
+        }
+
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.allocate(Node {
+            aabb: leaf_aabb.union(&self.nodes[sibling].aabb),
+            parent: old_parent,
+            height: self.nodes[sibling].height + 1,
+            kind: NodeKind::Inner { left: sibling, right: leaf },
+        });
+        self.nodes[sibling].parent = Some(new_parent);
+        self.nodes[leaf].parent = Some(new_parent);
+
+        match old_parent {
+            Some(p) => self.replace_child(p, sibling, new_parent),
+            None => self.root = Some(new_parent),
+        }
+
+        self.refit_from(Some(new_parent));
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        if self.root == Some(leaf) {
+            self.root = None;
+            return;
+        }
+
+        let parent = self.nodes[leaf].parent.expect("non-root leaf without parent");
+        let (left, right) = self.nodes[parent].children().expect("parent is not an inner node");
+        let sibling = if left == leaf { right } else { left };
+        let grand_parent = self.nodes[parent].parent;
+
+        match grand_parent {
+            Some(gp) => {
+                self.replace_child(gp, parent, sibling);
+                self.nodes[sibling].parent = Some(gp);
+                self.refit_from(Some(gp));
+            },
+            None => {
+                self.root = Some(sibling);
+                self.nodes[sibling].parent = None;
+            },
+        }
+
+        self.nodes[parent].kind = NodeKind::Free;
+        self.free_list.push(parent);
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if let NodeKind::Inner { left, right } = &mut self.nodes[parent].kind {
+            if *left == old {
+                *left = new;
+            } else {
+                *right = new;
+            }
+        }
+    }
+
+    fn refit_from(&mut self, mut idx: Option<usize>) {
+        while let Some(i) = idx {
+            let i = self.balance(i);
+            let (left, right) = self.nodes[i].children().expect("refitting a leaf");
+            self.nodes[i].height = 1 + self.nodes[left].height.max(self.nodes[right].height);
+            self.nodes[i].aabb = self.nodes[left].aabb.union(&self.nodes[right].aabb);
+            idx = self.nodes[i].parent;
+        }
+    }
+
+    /// AVL style rotation, returns the index of the node now at this position.
+    fn balance(&mut self, a: usize) -> usize {
+        let (b, c) = match self.nodes[a].children() {
+            Some(children) if self.nodes[a].height >= 2 => children,
+            _ => return a,
+        };
+
+        let balance = self.nodes[c].height - self.nodes[b].height;
+        if balance > 1 {
+            self.rotate_up(a, c, b)
+        } else if balance < -1 {
+            self.rotate_up(a, b, c)
+        } else {
+            a
+        }
+    }
+
+    /// Promotes the taller child `up` in place of `a`, `other` stays below `a`.
+    fn rotate_up(&mut self, a: usize, up: usize, other: usize) -> usize {
+        let (f, g) = self.nodes[up].children().expect("rotating a leaf up");
+
+        self.nodes[up].parent = self.nodes[a].parent;
+        self.nodes[a].parent = Some(up);
+        match self.nodes[up].parent {
+            Some(p) => self.replace_child(p, a, up),
+            None => self.root = Some(up),
+        }
+
+        // the taller grandchild stays under `up`, the shorter one moves under `a`
+        let (keep, moved) = if self.nodes[f].height > self.nodes[g].height { (f, g) } else { (g, f) };
+        self.nodes[up].kind = NodeKind::Inner { left: a, right: keep };
+        self.nodes[a].kind = NodeKind::Inner { left: other, right: moved };
+        self.nodes[moved].parent = Some(a);
+
+        self.nodes[a].aabb = self.nodes[other].aabb.union(&self.nodes[moved].aabb);
+        self.nodes[a].height = 1 + self.nodes[other].height.max(self.nodes[moved].height);
+        self.nodes[up].aabb = self.nodes[a].aabb.union(&self.nodes[keep].aabb);
+        self.nodes[up].height = 1 + self.nodes[a].height.max(self.nodes[keep].height);
+        up
+    }
+}
+
+impl<T> Default for DynamicTree<T> {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MARGIN)
+    }
+}
+
+/// Entry distance of a ray into a box, `dir` doesn't need to be normalized.
+pub(crate) fn ray_aabb(origin: Vec3, dir: Vec3, aabb: &Aabb) -> Option<f32> {
+    let mut t_min = 0.0f32;
+    let mut t_max = f32::MAX;
+    for axis in 0..3 {
+        let (o, d) = (origin[axis], dir[axis]);
+        if d.abs() < 1e-12 {
+            if o < aabb.min[axis] || o > aabb.max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let inv = 1.0 / d;
+        let mut t0 = (aabb.min[axis] - o) * inv;
+        let mut t1 = (aabb.max[axis] - o) * inv;
+        if t0 > t1 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+        t_min = t_min.max(t0);
+        t_max = t_max.min(t1);
+        if t_min > t_max {
+            return None;
+        }
+    }
+    Some(t_min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    // xorshift, good enough to scatter boxes around without pulling in rand
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_f32(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 40) as f32 / (1u64 << 24) as f32
+        }
+
+        fn range(&mut self, lo: f32, hi: f32) -> f32 {
+            lo + (hi - lo) * self.next_f32()
+        }
+
+        fn aabb(&mut self, extent: f32, max_size: f32) -> Aabb {
+            let center = Vec3::new(self.range(-extent, extent), self.range(-extent, extent), self.range(-extent, extent));
+            let half = Vec3::new(self.range(0.05, max_size), self.range(0.05, max_size), self.range(0.05, max_size));
+            Aabb::from_center(center, half)
+        }
+    }
+
+    fn brute_force_pairs(boxes: &[(ProxyId, Aabb)]) -> HashSet<(ProxyId, ProxyId)> {
+        let mut pairs = HashSet::new();
+        for (i, (a, box_a)) in boxes.iter().enumerate() {
+            for (b, box_b) in &boxes[i + 1..] {
+                if box_a.intersects(box_b) {
+                    pairs.insert((*a.min(b), *a.max(b)));
+                }
+            }
+        }
+        pairs
+    }
+
+    fn fat_boxes(tree: &DynamicTree<usize>, ids: &[ProxyId]) -> Vec<(ProxyId, Aabb)> {
+        ids.iter().map(|&id| (id, *tree.get_fat_aabb(id))).collect()
+    }
+
+    fn check_structure(tree: &DynamicTree<usize>) {
+        fn walk(tree: &DynamicTree<usize>, idx: usize) -> i32 {
+            match tree.nodes[idx].children() {
+                Some((l, r)) => {
+                    assert_eq!(tree.nodes[l].parent, Some(idx));
+                    assert_eq!(tree.nodes[r].parent, Some(idx));
+                    assert!(tree.nodes[idx].aabb.contains(&tree.nodes[l].aabb));
+                    assert!(tree.nodes[idx].aabb.contains(&tree.nodes[r].aabb));
+                    let (hl, hr) = (walk(tree, l), walk(tree, r));
+                    assert!((hl - hr).abs() <= 1, "tree is unbalanced");
+                    assert_eq!(tree.nodes[idx].height, 1 + hl.max(hr));
+                    1 + hl.max(hr)
+                },
+                None => 0,
+            }
+        }
+        if let Some(root) = tree.root {
+            walk(tree, root);
+        }
+    }
+
+    #[test]
+    fn pairs_match_brute_force() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut tree = DynamicTree::new(0.05);
+        let ids = (0..3000).map(|i| tree.insert(rng.aabb(50.0, 1.5), i)).collect::<Vec<_>>();
+        check_structure(&tree);
+
+        let expected = brute_force_pairs(&fat_boxes(&tree, &ids));
+        let found = tree.query_pairs();
+        assert_eq!(found.len(), expected.len(), "pairs reported more than once or missed");
+        assert_eq!(found.into_iter().collect::<HashSet<_>>(), expected);
+    }
+
+    #[test]
+    fn pairs_match_after_moves_and_removals() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut tree = DynamicTree::new(0.2);
+        let mut ids = (0..2000).map(|i| tree.insert(rng.aabb(40.0, 1.0), i)).collect::<Vec<_>>();
+
+        for step in 0..5 {
+            for id in ids.iter() {
+                let moved = Aabb::from_center(
+                    tree.get_fat_aabb(*id).get_center() + Vec3::new(rng.range(-1.0, 1.0), rng.range(-1.0, 1.0), rng.range(-1.0, 1.0)),
+                    Vec3::splat(rng.range(0.1, 1.0)),
+                );
+                tree.update(*id, moved);
+            }
+            // drop every seventh proxy and add new ones so the free lists get exercised
+            let removed = ids.iter().enumerate().filter(|(i, _)| i % 7 == step).map(|(_, id)| *id).collect::<Vec<_>>();
+            for id in &removed {
+                tree.remove(*id);
+            }
+            ids.retain(|id| !removed.contains(id));
+            for i in 0..removed.len() {
+                ids.push(tree.insert(rng.aabb(40.0, 1.0), 10_000 + i));
+            }
+            check_structure(&tree);
+
+            let expected = brute_force_pairs(&fat_boxes(&tree, &ids));
+            assert_eq!(tree.query_pairs().into_iter().collect::<HashSet<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn aabb_and_ray_queries_match_brute_force() {
+        let mut rng = Rng(0xdead_beef_cafe_f00d);
+        let mut tree = DynamicTree::new(0.0);
+        let ids = (0..4000).map(|i| tree.insert(rng.aabb(30.0, 2.0), i)).collect::<Vec<_>>();
+        let boxes = fat_boxes(&tree, &ids);
+
+        for _ in 0..50 {
+            let query = rng.aabb(30.0, 5.0);
+            let mut found = HashSet::new();
+            tree.query_aabb(&query, |id| found.insert(id));
+            let expected = boxes.iter().filter(|(_, b)| b.intersects(&query)).map(|(id, _)| *id).collect();
+            assert_eq!(found, expected);
+
+            let origin = Vec3::new(rng.range(-40.0, 40.0), rng.range(-40.0, 40.0), rng.range(-40.0, 40.0));
+            let dir = Vec3::new(rng.range(-1.0, 1.0), rng.range(-1.0, 1.0), rng.range(-1.0, 1.0)).normalize();
+            let mut found = HashSet::new();
+            tree.query_ray(origin, dir, 60.0, |id| found.insert(id));
+            let expected = boxes
+                .iter()
+                .filter(|(_, b)| matches!(ray_aabb(origin, dir, b), Some(t) if t <= 60.0))
+                .map(|(id, _)| *id)
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn frustum_query_culls_boxes_behind_planes() {
+        let mut tree = DynamicTree::new(0.0);
+        let front = tree.insert(Aabb::from_center(Vec3::new(0.0, 0.0, -5.0), Vec3::one()), 0);
+        let _behind = tree.insert(Aabb::from_center(Vec3::new(0.0, 0.0, 5.0), Vec3::one()), 1);
+        // a single "near plane" looking down -z
+        let planes = [Vec4::new(0.0, 0.0, -1.0, 0.0)];
+        let mut found = vec![];
+        tree.query_frustum(&planes, |id| { found.push(id); true });
+        assert_eq!(found, vec![front]);
+    }
+}
@@ -0,0 +1,339 @@
+use glam::Vec3;
+
+use super::collision;
+use super::shape::{Pose, Shape};
+use super::world::{BodyHandle, World};
+
+/// Upright capsule moved by sweeping it through the world instead of simulating it,
+/// so it never gets pushed around by the bodies it walks into.
+pub struct CharacterController {
+    position: Vec3,
+    velocity: Vec3,
+    half_height: f32,
+    radius: f32,
+    step_height: f32,
+    /// Cosine of the steepest slope that still counts as ground.
+    min_ground_normal_y: f32,
+    jump_speed: f32,
+    on_ground: bool,
+}
+
+impl CharacterController {
+
+    // gap kept between the capsule and everything else so casts don't start touching
+    const SKIN: f32 = 0.01;
+    const MAX_SLIDES: usize = 4;
+    const MAX_DEPENETRATION: usize = 4;
+
+    /// `position` is the center of a capsule with its axis along y.
+    pub fn new(position: Vec3, half_height: f32, radius: f32) -> Self {
+        Self {
+            position,
+            velocity: Vec3::zero(),
+            half_height,
+            radius,
+            step_height: 0.3,
+            min_ground_normal_y: 45f32.to_radians().cos(),
+            jump_speed: 5.0,
+            on_ground: false,
+        }
+    }
+
+    pub fn get_position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position;
+        self.velocity = Vec3::zero();
+        self.on_ground = false;
+    }
+
+    /// Lowest point of the capsule.
+    pub fn get_feet_position(&self) -> Vec3 {
+        self.position - Vec3::new(0.0, self.half_height + self.radius, 0.0)
+    }
+
+    pub fn get_velocity(&self) -> Vec3 {
+        self.velocity
+    }
+
+    pub fn get_shape(&self) -> Shape {
+        Shape::Capsule { half_height: self.half_height, radius: self.radius }
+    }
+
+    pub fn is_on_ground(&self) -> bool {
+        self.on_ground
+    }
+
+    pub fn set_step_height(&mut self, step_height: f32) {
+        self.step_height = step_height;
+    }
+
+    /// Slopes steeper than `angle` radians can't be walked up and are slid down instead.
+    pub fn set_max_slope(&mut self, angle: f32) {
+        self.min_ground_normal_y = angle.cos();
+    }
+
+    pub fn set_jump_speed(&mut self, jump_speed: f32) {
+        self.jump_speed = jump_speed;
+    }
+
+    /// Jumps if standing on the ground, returns whether it did.
+    pub fn jump(&mut self) -> bool {
+        if !self.on_ground {
+            return false;
+        }
+        self.velocity.set_y(self.jump_speed);
+        self.on_ground = false;
+        true
+    }
+
+    /// Moves the character for `dt` seconds. `walk_velocity` is the wanted horizontal
+    /// velocity, gravity comes from the world. Bodies in `exclude` are walked through.
+    pub fn update(&mut self, world: &World, walk_velocity: Vec3, dt: f32, exclude: &[BodyHandle]) {
+        self.depenetrate(world, exclude);
+
+        let was_on_ground = self.on_ground;
+        self.velocity += Vec3::new(0.0, world.get_gravity().y(), 0.0) * dt;
+        let walk = Vec3::new(walk_velocity.x(), 0.0, walk_velocity.z());
+        self.velocity = Vec3::new(walk.x(), self.velocity.y(), walk.z());
+
+        // step up, walk, then come back down so small ledges are climbed on the way
+        let mut stepped = 0.0;
+        if was_on_ground && walk.length_squared() > 0.0 {
+            let (pos, _) = self.slide(world, self.position, Vec3::new(0.0, self.step_height, 0.0), false, exclude);
+            stepped = pos.y() - self.position.y();
+            self.position = pos;
+        }
+
+        let (pos, _) = self.slide(world, self.position, walk * dt, true, exclude);
+        self.position = pos;
+
+        let vertical = self.velocity.y() * dt - stepped;
+        let (pos, hit) = self.slide(world, self.position, Vec3::new(0.0, vertical, 0.0), false, exclude);
+        self.position = pos;
+        if let Some(normal) = hit {
+            if normal.y() < 0.0 && self.velocity.y() > 0.0 {
+                // bumped the head
+                self.velocity.set_y(0.0);
+            }
+        }
+
+        // stick to the ground when walking down stairs and slopes, but not after a jump
+        let snap = if was_on_ground && self.velocity.y() <= 0.0 { self.step_height } else { 0.0 };
+        self.on_ground = false;
+        let probe = world.shape_cast(&self.get_shape(), &Pose::from_position(self.position), -Vec3::unit_y(), snap + 2.0 * Self::SKIN, exclude);
+        if let Some(hit) = probe {
+            if self.is_walkable(world, hit.point, hit.normal, exclude) {
+                self.on_ground = true;
+                self.position -= Vec3::new(0.0, (hit.distance - Self::SKIN).max(0.0), 0.0);
+                if self.velocity.y() < 0.0 {
+                    self.velocity.set_y(0.0);
+                }
+            }
+        }
+    }
+
+    /// The capsule touches ledges with its rounded bottom, which gives a steep
+    /// normal even on flat ground. A short ray just past the touching point finds
+    /// the surface actually stood on.
+    fn is_walkable(&self, world: &World, point: Vec3, normal: Vec3, exclude: &[BodyHandle]) -> bool {
+        if normal.y() >= self.min_ground_normal_y {
+            return true;
+        }
+        let inward = Vec3::new(-normal.x(), 0.0, -normal.z());
+        if inward.length_squared() < 1e-6 {
+            return false;
+        }
+        // start above the steepest walkable slope, a ray starting inside the surface means it's steeper
+        let c = self.min_ground_normal_y;
+        let rise = Self::SKIN * (1.0 - c * c).sqrt() / c + Self::SKIN;
+        let origin = point + inward.normalize() * Self::SKIN + Vec3::new(0.0, rise, 0.0);
+        match world.raycast(origin, -Vec3::unit_y(), rise + 2.0 * Self::SKIN, exclude) {
+            Some(hit) => hit.distance > 0.0 && hit.normal.y() >= self.min_ground_normal_y,
+            None => false,
+        }
+    }
+
+    /// Sweeps the capsule along `displacement`, sliding along whatever it hits.
+    /// With `walking` set, slopes too steep to stand on act as vertical walls so
+    /// they can't be climbed. Returns the new position and the first hit normal.
+    fn slide(&self, world: &World, start: Vec3, displacement: Vec3, walking: bool, exclude: &[BodyHandle]) -> (Vec3, Option<Vec3>) {
+        let shape = self.get_shape();
+        let mut position = start;
+        let mut remaining = displacement;
+        let mut first_normal = None;
+        let mut previous_normal: Option<Vec3> = None;
+
+        for _ in 0..Self::MAX_SLIDES {
+            let length = remaining.length();
+            if length < 1e-6 {
+                break;
+            }
+            let dir = remaining / length;
+            let hit = match world.shape_cast(&shape, &Pose::from_position(position), dir, length + Self::SKIN, exclude) {
+                Some(hit) => hit,
+                None => {
+                    position += remaining;
+                    break;
+                },
+            };
+
+            let travel = (hit.distance - Self::SKIN).max(0.0);
+            position += dir * travel;
+            remaining = dir * (length - travel);
+            first_normal = first_normal.or(Some(hit.normal));
+
+            let mut normal = hit.normal;
+            if walking && normal.y() < self.min_ground_normal_y {
+                let flat = Vec3::new(normal.x(), 0.0, normal.z());
+                if flat.length_squared() > 1e-6 {
+                    normal = flat.normalize();
+                }
+            }
+
+            remaining -= normal * remaining.dot(normal);
+            // in a corner between two surfaces only the crease between them is free
+            if let Some(previous) = previous_normal {
+                if remaining.dot(previous) < 0.0 {
+                    let crease = previous.cross(normal);
+                    remaining = if crease.length_squared() > 1e-6 {
+                        let crease = crease.normalize();
+                        crease * remaining.dot(crease)
+                    } else {
+                        Vec3::zero()
+                    };
+                }
+            }
+            previous_normal = Some(normal);
+        }
+
+        (position, first_normal)
+    }
+
+    /// Pushes the capsule out of anything it ended up inside, e.g. after being teleported.
+    fn depenetrate(&mut self, world: &World, exclude: &[BodyHandle]) {
+        let shape = self.get_shape();
+        for _ in 0..Self::MAX_DEPENETRATION {
+            let pose = Pose::from_position(self.position);
+            let mut push = Vec3::zero();
+            for handle in world.overlap(&shape, &pose, exclude) {
+                let body = match world.get_body(handle) {
+                    Some(body) => body,
+                    None => continue,
+                };
+                let other = match body.get_shape() {
+                    Some(other) => other,
+                    None => continue,
+                };
+                for manifold in collision::collide(&shape, &pose, other, &body.get_pose()) {
+                    // normals point from the capsule into the other body
+                    let depth = manifold.get_max_depth();
+                    if depth > 0.0 && push.dot(-manifold.normal) < depth {
+                        push -= manifold.normal * (depth + Self::SKIN - push.dot(-manifold.normal).max(0.0));
+                    }
+                }
+            }
+            if push.length_squared() < 1e-12 {
+                break;
+            }
+            self.position += push;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::RigidBody;
+
+    fn add_box(world: &mut World, center: Vec3, half_extents: Vec3) -> BodyHandle {
+        let mut body = RigidBody::from_shape(Shape::Box { half_extents }, 0.0);
+        body.set_position(center);
+        world.add_body(body)
+    }
+
+    fn level() -> World {
+        let mut world = World::default();
+        // floor with its top at y = 0
+        add_box(&mut world, Vec3::new(0.0, -0.5, 0.0), Vec3::new(50.0, 0.5, 50.0));
+        world
+    }
+
+    fn run(controller: &mut CharacterController, world: &World, walk: Vec3, frames: usize) {
+        for _ in 0..frames {
+            controller.update(world, walk, 1.0 / 60.0, &[]);
+        }
+    }
+
+    #[test]
+    fn falls_and_lands_on_the_floor() {
+        let world = level();
+        let mut controller = CharacterController::new(Vec3::new(0.0, 3.0, 0.0), 0.6, 0.3);
+        run(&mut controller, &world, Vec3::zero(), 120);
+        assert!(controller.is_on_ground());
+        assert!(controller.get_feet_position().y().abs() < 0.02, "feet at {:?}", controller.get_feet_position());
+
+        assert!(controller.jump());
+        assert!(!controller.jump());
+        run(&mut controller, &world, Vec3::zero(), 10);
+        assert!(controller.get_feet_position().y() > 0.3);
+        run(&mut controller, &world, Vec3::zero(), 120);
+        assert!(controller.is_on_ground());
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let mut world = level();
+        add_box(&mut world, Vec3::new(2.0, 1.0, 0.0), Vec3::new(0.5, 1.0, 10.0));
+        let mut controller = CharacterController::new(Vec3::new(0.0, 0.91, 0.0), 0.6, 0.3);
+
+        // walk diagonally into the wall, the x part is blocked and z keeps going
+        run(&mut controller, &world, Vec3::new(2.0, 0.0, 2.0), 120);
+        let pos = controller.get_position();
+        assert!(pos.x() < 1.5 - 0.3 + 1e-3 && pos.x() > 1.1, "stopped at {:?}", pos);
+        assert!(pos.z() > 3.5);
+        assert!(controller.is_on_ground());
+    }
+
+    #[test]
+    fn climbs_steps_but_not_walls() {
+        let mut world = level();
+        add_box(&mut world, Vec3::new(2.0, 0.1, 0.0), Vec3::new(0.5, 0.1, 5.0));
+        add_box(&mut world, Vec3::new(2.0, 0.5, 8.0), Vec3::new(0.5, 0.5, 2.0));
+
+        let mut low = CharacterController::new(Vec3::new(0.0, 0.91, 0.0), 0.6, 0.3);
+        run(&mut low, &world, Vec3::new(1.5, 0.0, 0.0), 90);
+        assert!(low.get_position().x() > 1.8);
+        assert!((low.get_feet_position().y() - 0.2).abs() < 0.03, "feet at {:?}", low.get_feet_position());
+
+        let mut high = CharacterController::new(Vec3::new(0.0, 0.91, 8.0), 0.6, 0.3);
+        run(&mut high, &world, Vec3::new(1.5, 0.0, 0.0), 90);
+        assert!(high.get_position().x() < 1.5);
+        assert!(high.get_feet_position().y().abs() < 0.02);
+    }
+
+    #[test]
+    fn steep_slopes_block_walking() {
+        use glam::Quat;
+
+        let mut world = level();
+        let ramp = |world: &mut World, z: f32, angle: f32| {
+            let mut body = RigidBody::from_shape(Shape::Box { half_extents: Vec3::new(5.0, 0.1, 1.0) }, 0.0);
+            body.set_orientation(Quat::from_rotation_z(angle));
+            body.set_position(Vec3::new(5.0 + 5.0 * angle.cos(), 5.0 * angle.sin() - 0.1, z));
+            world.add_body(body)
+        };
+        ramp(&mut world, 0.0, 20f32.to_radians());
+        ramp(&mut world, 5.0, 60f32.to_radians());
+
+        let mut gentle = CharacterController::new(Vec3::new(4.0, 0.91, 0.0), 0.6, 0.3);
+        run(&mut gentle, &world, Vec3::new(2.0, 0.0, 0.0), 120);
+        assert!(gentle.get_feet_position().y() > 0.8, "stuck at {:?}", gentle.get_position());
+
+        let mut steep = CharacterController::new(Vec3::new(4.0, 0.91, 5.0), 0.6, 0.3);
+        run(&mut steep, &world, Vec3::new(2.0, 0.0, 0.0), 120);
+        assert!(steep.get_feet_position().y() < 0.4, "climbed to {:?}", steep.get_position());
+    }
+}
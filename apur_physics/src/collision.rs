@@ -0,0 +1,690 @@
+use std::borrow::Cow;
+
+use glam::Vec3;
+
+use super::gjk::{self, GjkResult};
+use super::shape::{ConvexHull, Pose, Shape, TriMesh};
+
+#[derive(Clone, Copy, Debug)]
+pub struct ContactPoint {
+    /// World space point halfway between the two surfaces.
+    pub position: Vec3,
+    pub depth: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ContactManifold {
+    /// Unit normal pointing from the first shape towards the second.
+    pub normal: Vec3,
+    pub points: Vec<ContactPoint>,
+}
+
+impl ContactManifold {
+    fn flipped(mut self) -> Self {
+        self.normal = -self.normal;
+        self
+    }
+
+    fn into_world(mut self, pose: &Pose) -> Self {
+        self.normal = pose.transform_vector(self.normal);
+        for p in &mut self.points {
+            p.position = pose.transform_point(p.position);
+        }
+        self
+    }
+
+    pub fn get_max_depth(&self) -> f32 {
+        self.points.iter().fold(0.0, |acc, p| acc.max(p.depth))
+    }
+}
+
+/// Generates contacts between any two shapes. Meshes produce one manifold per
+/// distinct surface normal touched, mesh against mesh is not supported and never collides.
+pub fn collide(a: &Shape, pose_a: &Pose, b: &Shape, pose_b: &Pose) -> Vec<ContactManifold> {
+    match (a, b) {
+        (Shape::TriMesh(_), Shape::TriMesh(_)) => vec![],
+        (Shape::TriMesh(mesh), _) => collide_mesh(mesh, pose_a, &Convex::from_shape(b), pose_b),
+        (_, Shape::TriMesh(mesh)) => collide_mesh(mesh, pose_b, &Convex::from_shape(a), pose_a)
+            .into_iter()
+            .map(ContactManifold::flipped)
+            .collect(),
+        _ => collide_convex(&Convex::from_shape(a), pose_a, &Convex::from_shape(b), pose_b)
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// Convex shapes are a core (point, segment or polytope) inflated by a radius.
+pub(crate) struct Convex<'a> {
+    pub core: Core<'a>,
+    pub radius: f32,
+}
+
+pub(crate) enum Core<'a> {
+    Point,
+    Segment(Vec3, Vec3),
+    Polytope(Cow<'a, ConvexHull>),
+}
+
+impl<'a> Convex<'a> {
+    pub fn from_shape(shape: &'a Shape) -> Self {
+        match shape {
+            Shape::Sphere { radius } => Convex { core: Core::Point, radius: *radius },
+            Shape::Capsule { half_height, radius } => Convex {
+                core: Core::Segment(Vec3::new(0.0, -half_height, 0.0), Vec3::new(0.0, *half_height, 0.0)),
+                radius: *radius,
+            },
+            Shape::Box { half_extents } => Convex {
+                core: Core::Polytope(Cow::Owned(ConvexHull::cuboid(*half_extents))),
+                radius: 0.0,
+            },
+            Shape::ConvexHull(hull) => Convex { core: Core::Polytope(Cow::Borrowed(hull)), radius: 0.0 },
+            Shape::TriMesh(_) => panic!("triangle meshes aren't convex"),
+        }
+    }
+
+    /// Support point of the core alone in world space, the radius is left to the caller.
+    pub fn support(&self, pose: &Pose, dir: Vec3) -> Vec3 {
+        let local_dir = pose.inverse_transform_vector(dir);
+        let local = match &self.core {
+            Core::Point => Vec3::zero(),
+            Core::Segment(p, q) => if p.dot(local_dir) >= q.dot(local_dir) { *p } else { *q },
+            Core::Polytope(hull) => hull.support(local_dir),
+        };
+        pose.transform_point(local)
+    }
+
+    fn is_round(&self) -> bool {
+        !matches!(self.core, Core::Polytope(_))
+    }
+
+    fn segment(&self) -> (Vec3, Vec3) {
+        match self.core {
+            Core::Point => (Vec3::zero(), Vec3::zero()),
+            Core::Segment(p, q) => (p, q),
+            Core::Polytope(_) => unreachable!("polytopes have no segment core"),
+        }
+    }
+
+    fn hull(&self) -> &ConvexHull {
+        match &self.core {
+            Core::Polytope(hull) => hull,
+            _ => unreachable!("round shapes have no hull"),
+        }
+    }
+}
+
+pub(crate) fn collide_convex(a: &Convex, pose_a: &Pose, b: &Convex, pose_b: &Pose) -> Option<ContactManifold> {
+    // everything is computed in the frame of `a`
+    let rel = pose_a.relative(pose_b);
+    let manifold = match (a.is_round(), b.is_round()) {
+        (true, true) => {
+            let (p, q) = a.segment();
+            let (r, s) = b.segment();
+            collide_round_round(p, q, a.radius, rel.transform_point(r), rel.transform_point(s), b.radius)
+        },
+        (false, true) => {
+            let (r, s) = b.segment();
+            collide_poly_round(a.hull(), rel.transform_point(r), rel.transform_point(s), b.radius)
+        },
+        (true, false) => {
+            // flip so the polytope is first, and work in its frame
+            let inv = pose_b.relative(pose_a);
+            let (p, q) = a.segment();
+            return collide_poly_round(b.hull(), inv.transform_point(p), inv.transform_point(q), a.radius)
+                .map(|m| m.into_world(pose_b).flipped());
+        },
+        (false, false) => collide_poly_poly(a.hull(), b.hull(), &rel),
+    };
+    manifold.map(|m| m.into_world(pose_a))
+}
+
+fn collide_round_round(p: Vec3, q: Vec3, ra: f32, r: Vec3, s: Vec3, rb: f32) -> Option<ContactManifold> {
+    let (ca, cb) = closest_segment_segment(p, q, r, s);
+    let delta = cb - ca;
+    let dist = delta.length();
+    let radii = ra + rb;
+    if dist >= radii {
+        return None;
+    }
+
+    let normal = if dist > 1e-6 {
+        delta / dist
+    } else {
+        // centers coincide, any direction perpendicular to the cores works
+        let axis = if (q - p).length_squared() > 1e-12 { (q - p).normalize() } else { Vec3::unit_x() };
+        any_perpendicular(axis)
+    };
+
+    let surface_mid = |pa: Vec3, pb: Vec3| {
+        let depth = radii - (pb - pa).dot(normal);
+        ContactPoint { position: (pa + normal * ra + pb - normal * rb) * 0.5, depth }
+    };
+
+    let dir_a = q - p;
+    let dir_b = s - r;
+    let mut points = vec![];
+    if dir_a.length_squared() > 1e-12 && dir_b.length_squared() > 1e-12 {
+        let axis = dir_a.normalize();
+        if axis.dot(dir_b.normalize()).abs() > 0.995 {
+            // parallel capsules touch along a line, keep both ends of the overlap
+            let len = dir_a.length();
+            let t0 = (r - p).dot(axis).clamp(0.0, len);
+            let t1 = (s - p).dot(axis).clamp(0.0, len);
+            if (t1 - t0).abs() > 1e-4 {
+                for &t in &[t0, t1] {
+                    let pa = p + axis * t;
+                    let pb = closest_point_segment(r, s, pa);
+                    let point = surface_mid(pa, pb);
+                    if point.depth > 0.0 {
+                        points.push(point);
+                    }
+                }
+            }
+        }
+    }
+    if points.is_empty() {
+        points.push(ContactPoint {
+            position: (ca + normal * ra + cb - normal * rb) * 0.5,
+            depth: radii - dist,
+        });
+    }
+
+    Some(ContactManifold { normal, points })
+}
+
+fn collide_poly_round(hull: &ConvexHull, p: Vec3, q: Vec3, radius: f32) -> Option<ContactManifold> {
+    let closest = gjk::closest_points(
+        |d| hull.support(d),
+        |d| if p.dot(d) > q.dot(d) { p } else { q },
+    );
+
+    match closest {
+        GjkResult::Separated { point_a, point_b, distance } => {
+            if distance >= radius {
+                return None;
+            }
+            let normal = (point_b - point_a) / distance;
+
+            // a segment resting flat on a face gets clipped to it for a stable two point contact
+            if (q - p).length_squared() > 1e-12 && (q - p).normalize().dot(normal).abs() < 0.05 {
+                if let Some(face) = hull.get_faces().iter().find(|f| f.normal.dot(normal) > 0.999) {
+                    let points = clip_segment_to_face(hull, face, p, q)
+                        .into_iter()
+                        .filter_map(|e| {
+                            let height = face.normal.dot(e) - face.offset;
+                            let depth = radius - height;
+                            if depth > 0.0 {
+                                let on_face = e - face.normal * height;
+                                let on_round = e - face.normal * radius;
+                                Some(ContactPoint { position: (on_face + on_round) * 0.5, depth })
+                            } else {
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    if !points.is_empty() {
+                        return Some(ContactManifold { normal: face.normal, points });
+                    }
+                }
+            }
+
+            let on_round = point_b - normal * radius;
+            Some(ContactManifold {
+                normal,
+                points: vec![ContactPoint { position: (point_a + on_round) * 0.5, depth: radius - distance }],
+            })
+        },
+        GjkResult::Overlapping => collide_poly_round_deep(hull, p, q, radius),
+    }
+}
+
+/// The core is inside the polytope, find the axis of least penetration.
+fn collide_poly_round_deep(hull: &ConvexHull, p: Vec3, q: Vec3, radius: f32) -> Option<ContactManifold> {
+    let mut best_depth = f32::MAX;
+    let mut best_normal = Vec3::unit_y();
+    let mut best_face = None;
+
+    for (i, face) in hull.get_faces().iter().enumerate() {
+        let lowest = face.normal.dot(p).min(face.normal.dot(q));
+        let depth = face.offset - lowest + radius;
+        if depth < best_depth {
+            best_depth = depth;
+            best_normal = face.normal;
+            best_face = Some(i);
+        }
+    }
+
+    let seg = q - p;
+    if seg.length_squared() > 1e-12 {
+        let centroid = hull.get_centroid();
+        for &(i, j) in hull.get_edges() {
+            let edge = hull.get_vertices()[j] - hull.get_vertices()[i];
+            let axis = seg.cross(edge);
+            if axis.length_squared() < 1e-10 {
+                continue;
+            }
+            let mut axis = axis.normalize();
+            if axis.dot((p + q) * 0.5 - centroid) < 0.0 {
+                axis = -axis;
+            }
+            let depth = axis.dot(hull.support(axis)) - axis.dot(p).min(axis.dot(q)) + radius;
+            // faces win ties so resting contacts don't flicker between axes
+            if depth < best_depth - 1e-3 {
+                best_depth = depth;
+                best_normal = axis;
+                best_face = None;
+            }
+        }
+    }
+
+    let mut points = vec![];
+    if let Some(face) = best_face.map(|i| &hull.get_faces()[i]) {
+        for e in clip_segment_to_face(hull, face, p, q) {
+            let depth = face.offset - face.normal.dot(e) + radius;
+            if depth > 0.0 {
+                points.push(ContactPoint { position: e + face.normal * (0.5 * depth - radius), depth });
+            }
+        }
+    }
+    if points.is_empty() {
+        let deepest = if best_normal.dot(p) < best_normal.dot(q) { p } else { q };
+        points.push(ContactPoint {
+            position: deepest + best_normal * (0.5 * best_depth - radius),
+            depth: best_depth,
+        });
+    }
+
+    Some(ContactManifold { normal: best_normal, points })
+}
+
+fn clip_segment_to_face(hull: &ConvexHull, face: &super::shape::Face, p: Vec3, q: Vec3) -> Vec<Vec3> {
+    let mut polygon = vec![p, q];
+    let verts = hull.get_vertices();
+    for (i, &a) in face.vertices.iter().enumerate() {
+        let b = face.vertices[(i + 1) % face.vertices.len()];
+        let side = (verts[b] - verts[a]).cross(face.normal);
+        if side.length_squared() < 1e-12 {
+            continue;
+        }
+        let side = side.normalize();
+        polygon = clip_polygon(&polygon, side, side.dot(verts[a]));
+        if polygon.is_empty() {
+            break;
+        }
+    }
+    polygon.dedup_by(|a, b| (*a - *b).length_squared() < 1e-12);
+    polygon
+}
+
+type Edge = (usize, usize);
+
+fn collide_poly_poly(a: &ConvexHull, b: &ConvexHull, rel: &Pose) -> Option<ContactManifold> {
+    // bring b into a's frame once
+    let b_verts = b.get_vertices().iter().map(|&v| rel.transform_point(v)).collect::<Vec<_>>();
+    let b_normals = b.get_faces().iter().map(|f| rel.transform_vector(f.normal)).collect::<Vec<_>>();
+    let b_offsets = b.get_faces()
+        .iter()
+        .zip(b_normals.iter())
+        .map(|(f, n)| n.dot(b_verts[f.vertices[0]]))
+        .collect::<Vec<_>>();
+    let support_b = |d: Vec3| support_of(&b_verts, d);
+
+    // separation is positive when apart, the largest one is the axis of least penetration
+    let mut face_a = (f32::MIN, 0);
+    for (i, face) in a.get_faces().iter().enumerate() {
+        let sep = face.normal.dot(support_b(-face.normal)) - face.offset;
+        if sep > 0.0 {
+            return None;
+        }
+        if sep > face_a.0 {
+            face_a = (sep, i);
+        }
+    }
+
+    let mut face_b = (f32::MIN, 0);
+    for (i, (n, off)) in b_normals.iter().zip(b_offsets.iter()).enumerate() {
+        let sep = n.dot(a.support(-*n)) - off;
+        if sep > 0.0 {
+            return None;
+        }
+        if sep > face_b.0 {
+            face_b = (sep, i);
+        }
+    }
+
+    let b_centroid = rel.transform_point(b.get_centroid());
+    // separation, axis and the two edges
+    let mut edge_best: Option<(f32, Vec3, Edge, Edge)> = None;
+    for &(ai, aj) in a.get_edges() {
+        let ea = a.get_vertices()[aj] - a.get_vertices()[ai];
+        for &(bi, bj) in b.get_edges() {
+            let eb = b_verts[bj] - b_verts[bi];
+            let axis = ea.cross(eb);
+            if axis.length_squared() < 1e-8 * ea.length_squared() * eb.length_squared() {
+                continue;
+            }
+            let mut axis = axis.normalize();
+            if axis.dot(a.get_vertices()[ai] - a.get_centroid()) < 0.0 {
+                axis = -axis;
+            }
+            // an edge pair can only be the contact feature if both edges sit on the supporting planes
+            let sep = axis.dot(b_verts[bi]) - axis.dot(a.get_vertices()[ai]);
+            if axis.dot(b_centroid - b_verts[bi]) < 0.0 {
+                continue;
+            }
+            let full_sep = axis.dot(support_b(-axis)) - axis.dot(a.support(axis));
+            if full_sep > 0.0 {
+                return None;
+            }
+            if (sep - full_sep).abs() < 1e-4 {
+                match edge_best {
+                    Some((s, ..)) if s >= full_sep => {},
+                    _ => edge_best = Some((full_sep, axis, (ai, aj), (bi, bj))),
+                }
+            }
+        }
+    }
+
+    const REL_TOL: f32 = 0.95;
+    const ABS_TOL: f32 = 0.01;
+    let face_sep = face_a.0.max(face_b.0);
+
+    if let Some((sep, axis, (ai, aj), (bi, bj))) = edge_best {
+        if sep * REL_TOL > face_sep + ABS_TOL {
+            let (pa, pb) = closest_segment_segment(a.get_vertices()[ai], a.get_vertices()[aj], b_verts[bi], b_verts[bj]);
+            return Some(ContactManifold {
+                normal: axis,
+                points: vec![ContactPoint { position: (pa + pb) * 0.5, depth: -sep }],
+            });
+        }
+    }
+
+    // prefer a's faces on ties so the reference face doesn't flip every frame
+    let (normal, ref_verts, ref_face_verts, ref_offset, inc_verts, inc_normals, inc_faces, flip) =
+        if face_b.0 * REL_TOL > face_a.0 + ABS_TOL {
+            let f = &b.get_faces()[face_b.1];
+            (b_normals[face_b.1], &b_verts[..], &f.vertices, b_offsets[face_b.1],
+                a.get_vertices(), a.get_faces().iter().map(|f| f.normal).collect::<Vec<_>>(), a.get_faces(), true)
+        } else {
+            let f = &a.get_faces()[face_a.1];
+            (f.normal, a.get_vertices(), &f.vertices, f.offset,
+                &b_verts[..], b_normals.clone(), b.get_faces(), false)
+        };
+
+    // incident face is the one most opposed to the reference normal
+    let incident = inc_normals
+        .iter()
+        .enumerate()
+        .min_by(|(_, x), (_, y)| x.dot(normal).partial_cmp(&y.dot(normal)).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(i, _)| i)?;
+    let mut polygon = inc_faces[incident].vertices.iter().map(|&i| inc_verts[i]).collect::<Vec<_>>();
+
+    for (i, &va) in ref_face_verts.iter().enumerate() {
+        let vb = ref_face_verts[(i + 1) % ref_face_verts.len()];
+        let side = (ref_verts[vb] - ref_verts[va]).cross(normal);
+        if side.length_squared() < 1e-12 {
+            continue;
+        }
+        let side = side.normalize();
+        polygon = clip_polygon(&polygon, side, side.dot(ref_verts[va]));
+        if polygon.is_empty() {
+            return None;
+        }
+    }
+
+    let points = polygon
+        .into_iter()
+        .filter_map(|p| {
+            let depth = ref_offset - normal.dot(p);
+            if depth >= 0.0 {
+                Some(ContactPoint { position: p + normal * (0.5 * depth), depth })
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if points.is_empty() {
+        return None;
+    }
+
+    let manifold = ContactManifold { normal: if flip { -normal } else { normal }, points: reduce_points(points) };
+    Some(manifold)
+}
+
+fn collide_mesh(mesh: &TriMesh, mesh_pose: &Pose, convex: &Convex, convex_pose: &Pose) -> Vec<ContactManifold> {
+    let rel = mesh_pose.relative(convex_pose);
+    let local_aabb = match &convex.core {
+        Core::Polytope(hull) => super::aabb::Aabb::from_points(hull.get_vertices()),
+        _ => {
+            let (p, q) = convex.segment();
+            super::aabb::Aabb::from_points(&[p, q])
+        },
+    };
+    let query = local_aabb.transformed(&rel).expanded(convex.radius);
+
+    let mut manifolds: Vec<ContactManifold> = vec![];
+    mesh.query_aabb(&query, |tri| {
+        let [a, b, c] = mesh.get_triangle(tri);
+        if (b - a).cross(c - a).length_squared() < 1e-12 {
+            return;
+        }
+        let triangle = Convex { core: Core::Polytope(Cow::Owned(ConvexHull::triangle(a, b, c))), radius: 0.0 };
+        if let Some(m) = collide_convex(&triangle, &Pose::identity(), convex, &rel) {
+            // merge triangles that share a surface, e.g. the two halves of a floor quad
+            match manifolds.iter_mut().find(|other| other.normal.dot(m.normal) > 0.999) {
+                Some(other) => other.points.extend(m.points),
+                None => manifolds.push(m),
+            }
+        }
+    });
+
+    manifolds
+        .into_iter()
+        .map(|mut m| {
+            m.points = reduce_points(m.points);
+            m.into_world(mesh_pose)
+        })
+        .collect()
+}
+
+/// Sutherland-Hodgman against the plane `dot(n, x) = d`, keeping the side behind it.
+fn clip_polygon(polygon: &[Vec3], n: Vec3, d: f32) -> Vec<Vec3> {
+    let mut out = vec![];
+    if polygon.len() == 2 {
+        // segments don't wrap around
+        let (p, q) = (polygon[0], polygon[1]);
+        let (dp, dq) = (n.dot(p) - d, n.dot(q) - d);
+        if dp <= 0.0 && dq <= 0.0 {
+            return vec![p, q];
+        }
+        if dp > 0.0 && dq > 0.0 {
+            return vec![];
+        }
+        let hit = p + (q - p) * (dp / (dp - dq));
+        return if dp <= 0.0 { vec![p, hit] } else { vec![hit, q] };
+    }
+
+    for (i, &p) in polygon.iter().enumerate() {
+        let q = polygon[(i + 1) % polygon.len()];
+        let (dp, dq) = (n.dot(p) - d, n.dot(q) - d);
+        if dp <= 0.0 {
+            out.push(p);
+        }
+        if (dp <= 0.0) != (dq <= 0.0) {
+            out.push(p + (q - p) * (dp / (dp - dq)));
+        }
+    }
+    out
+}
+
+/// Keeps at most four points spanning the largest area, always including the deepest.
+fn reduce_points(points: Vec<ContactPoint>) -> Vec<ContactPoint> {
+    if points.len() <= 4 {
+        return points;
+    }
+
+    let max_by = |f: &dyn Fn(&ContactPoint) -> f32| {
+        points
+            .iter()
+            .max_by(|x, y| f(x).partial_cmp(&f(y)).unwrap_or(std::cmp::Ordering::Equal))
+            .copied()
+            .unwrap()
+    };
+
+    let a = max_by(&|p| p.depth);
+    let b = max_by(&|p| (p.position - a.position).length_squared());
+    let c = max_by(&|p| (p.position - a.position).cross(p.position - b.position).length_squared());
+    let d = max_by(&|p| {
+        (p.position - a.position).length()
+            + (p.position - b.position).length()
+            + (p.position - c.position).length()
+    });
+
+    let mut reduced = vec![a, b, c, d];
+    reduced.dedup_by(|x, y| (x.position - y.position).length_squared() < 1e-12);
+    reduced
+}
+
+fn support_of(points: &[Vec3], dir: Vec3) -> Vec3 {
+    let mut best = points[0];
+    for &p in &points[1..] {
+        if p.dot(dir) > best.dot(dir) {
+            best = p;
+        }
+    }
+    best
+}
+
+pub(crate) fn any_perpendicular(v: Vec3) -> Vec3 {
+    let other = if v.x().abs() < 0.9 { Vec3::unit_x() } else { Vec3::unit_y() };
+    v.cross(other).normalize()
+}
+
+pub(crate) fn closest_point_segment(p: Vec3, q: Vec3, x: Vec3) -> Vec3 {
+    let d = q - p;
+    let len_sq = d.length_squared();
+    if len_sq < 1e-12 {
+        return p;
+    }
+    p + d * ((x - p).dot(d) / len_sq).clamp(0.0, 1.0)
+}
+
+// Ericson, Real-Time Collision Detection, 5.1.9
+pub(crate) fn closest_segment_segment(p1: Vec3, q1: Vec3, p2: Vec3, q2: Vec3) -> (Vec3, Vec3) {
+    let d1 = q1 - p1;
+    let d2 = q2 - p2;
+    let r = p1 - p2;
+    let a = d1.length_squared();
+    let e = d2.length_squared();
+    let f = d2.dot(r);
+
+    let (s, t) = if a <= 1e-12 && e <= 1e-12 {
+        (0.0, 0.0)
+    } else if a <= 1e-12 {
+        (0.0, (f / e).clamp(0.0, 1.0))
+    } else {
+        let c = d1.dot(r);
+        if e <= 1e-12 {
+            ((-c / a).clamp(0.0, 1.0), 0.0)
+        } else {
+            let b = d1.dot(d2);
+            let denom = a * e - b * b;
+            let mut s = if denom > 1e-12 { ((b * f - c * e) / denom).clamp(0.0, 1.0) } else { 0.0 };
+            let mut t = (b * s + f) / e;
+            if t < 0.0 {
+                t = 0.0;
+                s = (-c / a).clamp(0.0, 1.0);
+            } else if t > 1.0 {
+                t = 1.0;
+                s = ((b - c) / a).clamp(0.0, 1.0);
+            }
+            (s, t)
+        }
+    };
+
+    (p1 + d1 * s, p2 + d2 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Quat;
+
+    fn single(manifolds: Vec<ContactManifold>) -> ContactManifold {
+        assert_eq!(manifolds.len(), 1, "expected exactly one manifold");
+        manifolds.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn sphere_sphere() {
+        let s = Shape::Sphere { radius: 1.0 };
+        let m = single(collide(&s, &Pose::identity(), &s, &Pose::from_position(Vec3::new(1.5, 0.0, 0.0))));
+        assert!((m.normal - Vec3::unit_x()).length() < 1e-5);
+        assert!((m.points[0].depth - 0.5).abs() < 1e-5);
+        assert!((m.points[0].position - Vec3::new(0.75, 0.0, 0.0)).length() < 1e-5);
+
+        assert!(collide(&s, &Pose::identity(), &s, &Pose::from_position(Vec3::new(2.5, 0.0, 0.0))).is_empty());
+    }
+
+    #[test]
+    fn box_resting_on_box_has_four_points() {
+        let ground = Shape::Box { half_extents: Vec3::new(10.0, 1.0, 10.0) };
+        let crate_ = Shape::Box { half_extents: Vec3::splat(0.5) };
+        let m = single(collide(&ground, &Pose::identity(), &crate_, &Pose::from_position(Vec3::new(0.0, 1.45, 0.0))));
+        assert!((m.normal - Vec3::unit_y()).length() < 1e-4);
+        assert_eq!(m.points.len(), 4);
+        for p in &m.points {
+            assert!((p.depth - 0.05).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn rotated_box_edge_on_box() {
+        let b = Shape::Box { half_extents: Vec3::splat(0.5) };
+        let pose = Pose::new(Vec3::new(0.0, 1.2, 0.0), Quat::from_rotation_z(std::f32::consts::FRAC_PI_4));
+        let m = single(collide(&b, &Pose::identity(), &b, &pose));
+        assert!(m.normal.y() > 0.99);
+        let expected = 0.5 + 0.5 * 2f32.sqrt() - 1.2;
+        assert!((m.get_max_depth() - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn capsule_lying_on_box_has_two_points() {
+        let ground = Shape::Box { half_extents: Vec3::new(10.0, 1.0, 10.0) };
+        let capsule = Shape::Capsule { half_height: 1.0, radius: 0.25 };
+        let pose = Pose::new(Vec3::new(0.0, 1.2, 0.0), Quat::from_rotation_z(std::f32::consts::FRAC_PI_2));
+        let m = single(collide(&capsule, &pose, &ground, &Pose::identity()));
+        assert!((m.normal + Vec3::unit_y()).length() < 1e-4);
+        assert_eq!(m.points.len(), 2);
+        for p in &m.points {
+            assert!((p.depth - 0.05).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn sphere_deep_inside_box() {
+        let b = Shape::Box { half_extents: Vec3::splat(1.0) };
+        let s = Shape::Sphere { radius: 0.1 };
+        let m = single(collide(&b, &Pose::identity(), &s, &Pose::from_position(Vec3::new(0.0, 0.0, 0.8))));
+        assert!((m.normal - Vec3::unit_z()).length() < 1e-4);
+        assert!((m.points[0].depth - 0.3).abs() < 1e-4);
+    }
+
+    #[test]
+    fn sphere_on_trimesh_floor() {
+        let positions = [-5.0, 0.0, -5.0, 5.0, 0.0, -5.0, 5.0, 0.0, 5.0, -5.0, 0.0, 5.0];
+        let indices = [0, 2, 1, 0, 3, 2];
+        let floor = Shape::trimesh(TriMesh::from_flat(&positions, &indices));
+        let s = Shape::Sphere { radius: 0.5 };
+
+        let m = single(collide(&s, &Pose::from_position(Vec3::new(0.0, 0.4, 0.0)), &floor, &Pose::identity()));
+        assert!((m.normal + Vec3::unit_y()).length() < 1e-4);
+        assert!((m.points[0].depth - 0.1).abs() < 1e-4);
+
+        let b = Shape::Box { half_extents: Vec3::splat(0.5) };
+        let m = single(collide(&floor, &Pose::identity(), &b, &Pose::from_position(Vec3::new(0.0, 0.45, 0.0))));
+        assert!((m.normal - Vec3::unit_y()).length() < 1e-4);
+        assert_eq!(m.points.len(), 4);
+    }
+}
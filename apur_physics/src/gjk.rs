@@ -0,0 +1,238 @@
+use glam::Vec3;
+
+#[derive(Clone, Copy)]
+struct SupportPoint {
+    w: Vec3,
+    a: Vec3,
+    b: Vec3,
+}
+
+pub enum GjkResult {
+    /// Closest points on A and B and the distance between them.
+    Separated { point_a: Vec3, point_b: Vec3, distance: f32 },
+    Overlapping,
+}
+
+/// Distance between two convex sets given by their support functions,
+/// both expressed in the same frame.
+pub fn closest_points(
+    support_a: impl Fn(Vec3) -> Vec3,
+    support_b: impl Fn(Vec3) -> Vec3,
+) -> GjkResult {
+    const MAX_ITERATIONS: usize = 64;
+    const REL_EPS: f32 = 1e-6;
+
+    let support = |d: Vec3| {
+        let a = support_a(d);
+        let b = support_b(-d);
+        SupportPoint { w: a - b, a, b }
+    };
+
+    let mut simplex = vec![support(Vec3::unit_x())];
+    let mut weights = vec![1.0];
+    let mut v = simplex[0].w;
+
+    for _ in 0..MAX_ITERATIONS {
+        let v_len_sq = v.length_squared();
+        if v_len_sq < 1e-12 {
+            return GjkResult::Overlapping;
+        }
+
+        let p = support(-v);
+        if v_len_sq - v.dot(p.w) <= REL_EPS * v_len_sq
+            || simplex.iter().any(|s| (s.w - p.w).length_squared() < 1e-12)
+        {
+            break;
+        }
+
+        simplex.push(p);
+        match solve_simplex(&simplex) {
+            Some((closest, lambdas)) => {
+                let kept = simplex
+                    .iter()
+                    .zip(lambdas.iter())
+                    .filter(|(_, &l)| l > 0.0)
+                    .map(|(s, &l)| (*s, l))
+                    .collect::<Vec<_>>();
+                simplex = kept.iter().map(|(s, _)| *s).collect();
+                weights = kept.iter().map(|(_, l)| *l).collect();
+                // no progress means we're at the numeric limit, the previous answer stands
+                if closest.length_squared() >= v_len_sq {
+                    v = closest;
+                    break;
+                }
+                v = closest;
+            },
+            None => return GjkResult::Overlapping,
+        }
+    }
+
+    let mut point_a = Vec3::zero();
+    let mut point_b = Vec3::zero();
+    for (s, &l) in simplex.iter().zip(weights.iter()) {
+        point_a += s.a * l;
+        point_b += s.b * l;
+    }
+    GjkResult::Separated { point_a, point_b, distance: v.length() }
+}
+
+/// Closest point of the simplex to the origin with barycentric weights per vertex,
+/// zero weights mark vertices that can be dropped. `None` when the origin is inside.
+fn solve_simplex(simplex: &[SupportPoint]) -> Option<(Vec3, Vec<f32>)> {
+    match simplex.len() {
+        1 => Some((simplex[0].w, vec![1.0])),
+        2 => {
+            let (l, p) = closest_on_segment(simplex[0].w, simplex[1].w);
+            Some((p, l.to_vec()))
+        },
+        3 => {
+            let (l, p) = closest_on_triangle(simplex[0].w, simplex[1].w, simplex[2].w);
+            Some((p, l.to_vec()))
+        },
+        4 => closest_on_tetrahedron(simplex[0].w, simplex[1].w, simplex[2].w, simplex[3].w)
+            .map(|(l, p)| (p, l.to_vec())),
+        _ => unreachable!("simplex can't have more than 4 vertices"),
+    }
+}
+
+fn closest_on_segment(a: Vec3, b: Vec3) -> ([f32; 2], Vec3) {
+    let ab = b - a;
+    let denom = ab.length_squared();
+    if denom < 1e-12 {
+        return ([1.0, 0.0], a);
+    }
+    let t = (-a).dot(ab) / denom;
+    if t <= 0.0 {
+        ([1.0, 0.0], a)
+    } else if t >= 1.0 {
+        ([0.0, 1.0], b)
+    } else {
+        ([1.0 - t, t], a + ab * t)
+    }
+}
+
+// Ericson, Real-Time Collision Detection, 5.1.5 with the query point at the origin
+fn closest_on_triangle(a: Vec3, b: Vec3, c: Vec3) -> ([f32; 3], Vec3) {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = -a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return ([1.0, 0.0, 0.0], a);
+    }
+
+    let bp = -b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return ([0.0, 1.0, 0.0], b);
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return ([1.0 - v, v, 0.0], a + ab * v);
+    }
+
+    let cp = -c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return ([0.0, 0.0, 1.0], c);
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return ([1.0 - w, 0.0, w], a + ac * w);
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return ([0.0, 1.0 - w, w], b + (c - b) * w);
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    ([1.0 - v - w, v, w], a + ab * v + ac * w)
+}
+
+fn closest_on_tetrahedron(a: Vec3, b: Vec3, c: Vec3, d: Vec3) -> Option<([f32; 4], Vec3)> {
+    // origin and the opposite vertex on different sides of a face means the face is visible
+    let outside = |p: Vec3, q: Vec3, r: Vec3, opposite: Vec3| {
+        let n = (q - p).cross(r - p);
+        let sign_origin = n.dot(-p);
+        let sign_opposite = n.dot(opposite - p);
+        // degenerate tetrahedra are treated as if every face were visible
+        sign_origin * sign_opposite < 0.0 || sign_opposite.abs() < 1e-12
+    };
+
+    let mut best: Option<([f32; 4], Vec3)> = None;
+    let mut consider = |lambdas: [f32; 4], point: Vec3| {
+        match best {
+            Some((_, p)) if p.length_squared() <= point.length_squared() => {},
+            _ => best = Some((lambdas, point)),
+        }
+    };
+
+    if outside(a, b, c, d) {
+        let (l, p) = closest_on_triangle(a, b, c);
+        consider([l[0], l[1], l[2], 0.0], p);
+    }
+    if outside(a, c, d, b) {
+        let (l, p) = closest_on_triangle(a, c, d);
+        consider([l[0], 0.0, l[1], l[2]], p);
+    }
+    if outside(a, d, b, c) {
+        let (l, p) = closest_on_triangle(a, d, b);
+        consider([l[0], l[2], 0.0, l[1]], p);
+    }
+    if outside(b, d, c, a) {
+        let (l, p) = closest_on_triangle(b, d, c);
+        consider([0.0, l[0], l[2], l[1]], p);
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube_support(center: Vec3, half: f32) -> impl Fn(Vec3) -> Vec3 {
+        move |d: Vec3| center + Vec3::new(half.copysign(d.x()), half.copysign(d.y()), half.copysign(d.z()))
+    }
+
+    #[test]
+    fn separated_cubes() {
+        match closest_points(cube_support(Vec3::zero(), 1.0), cube_support(Vec3::new(3.0, 0.5, 0.0), 1.0)) {
+            GjkResult::Separated { point_a, point_b, distance } => {
+                assert!((distance - 1.0).abs() < 1e-4);
+                assert!((point_a.x() - 1.0).abs() < 1e-4);
+                assert!((point_b.x() - 2.0).abs() < 1e-4);
+            },
+            GjkResult::Overlapping => panic!("cubes are apart"),
+        }
+    }
+
+    #[test]
+    fn overlapping_cubes() {
+        let result = closest_points(cube_support(Vec3::zero(), 1.0), cube_support(Vec3::new(1.5, 1.5, 1.5), 1.0));
+        assert!(matches!(result, GjkResult::Overlapping));
+    }
+
+    #[test]
+    fn point_to_cube_corner() {
+        let point = |_: Vec3| Vec3::new(2.0, 2.0, 2.0);
+        match closest_points(point, cube_support(Vec3::zero(), 1.0)) {
+            GjkResult::Separated { distance, point_b, .. } => {
+                assert!((distance - 3f32.sqrt()).abs() < 1e-4);
+                assert!((point_b - Vec3::one()).length() < 1e-4);
+            },
+            GjkResult::Overlapping => panic!("point is outside the cube"),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use glam::{Quat, Vec3};
+
+use super::body::RigidBody;
+use super::collision::any_perpendicular;
+use super::solver::Row;
+use super::world::BodyHandle;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct JointHandle(pub(crate) usize);
+
+#[derive(Clone, Copy, Debug)]
+pub enum JointKind {
+    /// Anchors coincide, rotation is free.
+    BallSocket,
+    /// Anchors coincide and the bodies only rotate around the hinge axis,
+    /// optionally within `limits` radians of the starting angle.
+    Hinge { local_axis_a: Vec3, local_axis_b: Vec3, local_ref_a: Vec3, local_ref_b: Vec3, limits: Option<(f32, f32)> },
+    /// Relative rotation is locked and the anchors only move apart along the axis.
+    Slider { local_axis_a: Vec3 },
+    /// Both relative position and rotation are locked.
+    Fixed,
+    /// Anchor distance is kept between `min` and `max`.
+    Distance { min: f32, max: f32 },
+}
+
+/// Which side of a one sided row is being held, lower and upper bounds push in opposite directions.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum LimitBound {
+    Lower,
+    Upper,
+}
+
+pub struct Joint {
+    body_a: BodyHandle,
+    body_b: BodyHandle,
+    local_anchor_a: Vec3,
+    local_anchor_b: Vec3,
+    /// Orientation of b relative to a when the joint was made.
+    rest_rotation: Quat,
+    kind: JointKind,
+    /// Accumulated impulses of each row from the last step, for warm starting.
+    impulses: [f32; 7],
+    /// Bound of the limit row in the last step, its impulse is only reused while the same one holds.
+    active_limit: Option<LimitBound>,
+}
+
+impl Joint {
+    /// Builds a joint from the current poses of the bodies, anchors are in world space.
+    pub(crate) fn new(
+        (body_a, a, anchor_a): (BodyHandle, &RigidBody, Vec3),
+        (body_b, b, anchor_b): (BodyHandle, &RigidBody, Vec3),
+        kind: JointKind,
+    ) -> Self {
+        Self {
+            body_a,
+            body_b,
+            local_anchor_a: a.get_pose().inverse_transform_point(anchor_a),
+            local_anchor_b: b.get_pose().inverse_transform_point(anchor_b),
+            rest_rotation: (a.get_orientation().conjugate() * b.get_orientation()).normalize(),
+            kind,
+            impulses: [0.0; 7],
+            active_limit: None,
+        }
+    }
+
+    pub(crate) fn hinge_kind(a: &RigidBody, b: &RigidBody, axis: Vec3) -> JointKind {
+        let axis = axis.normalize();
+        let reference = any_perpendicular(axis);
+        JointKind::Hinge {
+            local_axis_a: a.get_pose().inverse_transform_vector(axis),
+            local_axis_b: b.get_pose().inverse_transform_vector(axis),
+            local_ref_a: a.get_pose().inverse_transform_vector(reference),
+            local_ref_b: b.get_pose().inverse_transform_vector(reference),
+            limits: None,
+        }
+    }
+
+    pub fn get_bodies(&self) -> (BodyHandle, BodyHandle) {
+        (self.body_a, self.body_b)
+    }
+
+    pub fn get_kind(&self) -> &JointKind {
+        &self.kind
+    }
+
+    /// Only hinges have limits, other joints ignore this.
+    pub fn set_hinge_limits(&mut self, new_limits: Option<(f32, f32)>) {
+        if let JointKind::Hinge { limits, .. } = &mut self.kind {
+            *limits = new_limits;
+        }
+    }
+
+    /// Current hinge angle relative to the angle when the joint was made.
+    pub fn get_hinge_angle(&self, a: &RigidBody, b: &RigidBody) -> Option<f32> {
+        match self.kind {
+            JointKind::Hinge { local_axis_a, local_ref_a, local_ref_b, .. } => {
+                let axis = a.get_pose().transform_vector(local_axis_a);
+                let ref_a = a.get_pose().transform_vector(local_ref_a);
+                let ref_b = b.get_pose().transform_vector(local_ref_b);
+                Some(ref_a.cross(ref_b).dot(axis).atan2(ref_a.dot(ref_b)))
+            },
+            _ => None,
+        }
+    }
+
+    /// Emits this joint's rows, `ia` and `ib` index the bodies in the solver.
+    /// Returns the bound its limit row holds, if it has one, to pass on to `store_impulses`.
+    pub(crate) fn build_rows(
+        &self,
+        a: &RigidBody,
+        b: &RigidBody,
+        ia: usize,
+        ib: usize,
+        erp: f32,
+        rows: &mut Vec<Row>,
+    ) -> Option<LimitBound> {
+        let ra = a.get_orientation().mul_vec3(self.local_anchor_a);
+        let rb = b.get_orientation().mul_vec3(self.local_anchor_b);
+        let error = (b.get_position() + rb) - (a.get_position() + ra);
+        let impulses = &self.impulses;
+        let mut bound = None;
+        let mut slot = 0;
+        let mut push = |row: Row| {
+            rows.push(row.with_impulse(impulses[slot]));
+            slot += 1;
+        };
+
+        let axes = [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()];
+        let point_rows = |push: &mut dyn FnMut(Row)| {
+            for &axis in &axes {
+                push(Row::point(ia, ib, axis, ra, rb).with_bias(erp * error.dot(axis)));
+            }
+        };
+        let lock_rotation = |push: &mut dyn FnMut(Row)| {
+            // small angle rotation taking b from where it should be to where it is
+            let target = a.get_orientation() * self.rest_rotation;
+            let mut diff = b.get_orientation() * target.conjugate();
+            if diff.w() < 0.0 {
+                diff = Quat::from_xyzw(-diff.x(), -diff.y(), -diff.z(), -diff.w());
+            }
+            let angle_error = Vec3::new(diff.x(), diff.y(), diff.z()) * 2.0;
+            for &axis in &axes {
+                push(Row::angular(ia, ib, axis).with_bias(erp * angle_error.dot(axis)));
+            }
+        };
+
+        match self.kind {
+            JointKind::BallSocket => point_rows(&mut push),
+            JointKind::Fixed => {
+                point_rows(&mut push);
+                lock_rotation(&mut push);
+            },
+            JointKind::Hinge { local_axis_a, local_axis_b, limits, .. } => {
+                point_rows(&mut push);
+                let axis_a = a.get_orientation().mul_vec3(local_axis_a);
+                let axis_b = b.get_orientation().mul_vec3(local_axis_b);
+                let p = any_perpendicular(axis_a);
+                let q = axis_a.cross(p);
+                let misalignment = axis_a.cross(axis_b);
+                push(Row::angular(ia, ib, p).with_bias(erp * misalignment.dot(p)));
+                push(Row::angular(ia, ib, q).with_bias(erp * misalignment.dot(q)));
+
+                if let Some((lower, upper)) = limits {
+                    let angle = self.get_hinge_angle(a, b).unwrap_or(0.0);
+                    let row = Row::angular(ia, ib, axis_a);
+                    if angle <= lower {
+                        bound = Some(LimitBound::Lower);
+                        push(row.with_bias(erp * (angle - lower)).with_bounds(0.0, f32::MAX));
+                    } else if angle >= upper {
+                        bound = Some(LimitBound::Upper);
+                        push(row.with_bias(erp * (angle - upper)).with_bounds(f32::MIN, 0.0));
+                    }
+                }
+            },
+            JointKind::Slider { local_axis_a } => {
+                lock_rotation(&mut push);
+                let axis = a.get_orientation().mul_vec3(local_axis_a);
+                let p = any_perpendicular(axis);
+                let q = axis.cross(p);
+                for &dir in &[p, q] {
+                    // the lever arm on a reaches to b's anchor so rotating a drags the slide
+                    push(Row::point(ia, ib, dir, ra + error, rb).with_bias(erp * error.dot(dir)));
+                }
+            },
+            JointKind::Distance { min, max } => {
+                let length = error.length();
+                let dir = if length > 1e-6 { error / length } else { Vec3::unit_y() };
+                let row = Row::point(ia, ib, dir, ra, rb);
+                if (max - min).abs() < 1e-6 {
+                    push(row.with_bias(erp * (length - min)));
+                } else if length < min {
+                    bound = Some(LimitBound::Lower);
+                    push(row.with_bias(erp * (length - min)).with_bounds(0.0, f32::MAX));
+                } else if length > max {
+                    bound = Some(LimitBound::Upper);
+                    push(row.with_bias(erp * (length - max)).with_bounds(f32::MIN, 0.0));
+                }
+            },
+        }
+
+        // the limit row is always the last one, an impulse cached for the other bound would pull the wrong way
+        if bound.is_some() && bound != self.active_limit {
+            if let Some(row) = rows.last_mut() {
+                row.impulse = 0.0;
+            }
+        }
+        bound
+    }
+
+    /// Stores the solved impulses of the rows built by `build_rows` and the bound it returned.
+    pub(crate) fn store_impulses(&mut self, rows: &[Row], bound: Option<LimitBound>) {
+        self.active_limit = bound;
+        self.impulses = [0.0; 7];
+        for (cached, row) in self.impulses.iter_mut().zip(rows) {
+            *cached = row.impulse;
+        }
+    }
+}
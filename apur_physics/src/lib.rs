@@ -1,3 +1,26 @@
+mod aabb;
+mod body;
+mod broadphase;
+mod character;
+mod collision;
+mod gjk;
+mod joint;
+mod query;
+mod shape;
+mod solver;
+mod world;
+
+pub use body::{RigidBody, sphere_inertia, box_inertia};
+pub use world::{World, BodyHandle, Contact};
+pub use joint::{Joint, JointKind, JointHandle};
+pub use solver::SolverSettings;
+pub use query::QueryHit;
+pub use character::CharacterController;
+pub use broadphase::{DynamicTree, ProxyId};
+pub use aabb::Aabb;
+pub use shape::{Pose, Shape, ConvexHull, Face, TriMesh};
+pub use collision::{collide, ContactManifold, ContactPoint};
+
 #[cfg(test)]
 mod tests {
     #[test]
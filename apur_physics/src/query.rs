@@ -0,0 +1,284 @@
+use std::borrow::Cow;
+
+use glam::Vec3;
+
+use super::aabb::Aabb;
+use super::collision::{Convex, Core};
+use super::gjk::{self, GjkResult};
+use super::shape::{ConvexHull, Pose, Shape, TriMesh};
+use super::world::BodyHandle;
+
+#[derive(Clone, Copy, Debug)]
+pub struct QueryHit {
+    pub body: BodyHandle,
+    /// World space point where the ray or the swept shape first touches the body.
+    pub point: Vec3,
+    /// Surface normal of the body at `point`, facing the query.
+    pub normal: Vec3,
+    /// Distance travelled along the query direction until the hit.
+    pub distance: f32,
+}
+
+/// Hit against a single shape before it's tied to a body.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Impact {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Impact {
+    pub fn with_body(self, body: BodyHandle) -> QueryHit {
+        QueryHit { body, point: self.point, normal: self.normal, distance: self.distance }
+    }
+}
+
+// gap at which conservative advancement considers the shapes touching
+const TOLERANCE: f32 = 1e-4;
+const MAX_ITERATIONS: usize = 32;
+
+/// Ray `origin + t * dir` against a shape, `dir` has to be normalized.
+/// Rays starting inside a convex shape hit it at distance zero.
+pub(crate) fn ray_shape(shape: &Shape, pose: &Pose, origin: Vec3, dir: Vec3, max_distance: f32) -> Option<Impact> {
+    match shape {
+        Shape::TriMesh(mesh) => {
+            let local_origin = pose.inverse_transform_point(origin);
+            let local_dir = pose.inverse_transform_vector(dir);
+            ray_mesh(mesh, local_origin, local_dir, max_distance).map(|hit| Impact {
+                point: pose.transform_point(hit.point),
+                normal: pose.transform_vector(hit.normal),
+                distance: hit.distance,
+            })
+        },
+        _ => {
+            let point = Convex { core: Core::Point, radius: 0.0 };
+            cast_convex(&point, &Pose::from_position(origin), dir, max_distance, &Convex::from_shape(shape), pose)
+        },
+    }
+}
+
+/// Sweeps `shape` from `pose` along the normalized `dir` against `target`.
+/// Meshes can only be swept against, casting a mesh never hits anything.
+pub(crate) fn sweep_shape(
+    shape: &Shape,
+    pose: &Pose,
+    dir: Vec3,
+    max_distance: f32,
+    target: &Shape,
+    target_pose: &Pose,
+) -> Option<Impact> {
+    match (shape, target) {
+        (Shape::TriMesh(_), _) => None,
+        (_, Shape::TriMesh(mesh)) => {
+            // work in the mesh frame and only look at triangles the sweep can reach
+            let rel = target_pose.relative(pose);
+            let local_dir = target_pose.inverse_transform_vector(dir);
+            let start = shape.get_aabb(&rel);
+            let end = Aabb::new(start.min + local_dir * max_distance, start.max + local_dir * max_distance);
+            let convex = Convex::from_shape(shape);
+
+            let mut best: Option<Impact> = None;
+            mesh.query_aabb(&start.union(&end), |tri| {
+                let [a, b, c] = mesh.get_triangle(tri);
+                if (b - a).cross(c - a).length_squared() < 1e-12 {
+                    return;
+                }
+                let triangle = Convex { core: Core::Polytope(Cow::Owned(ConvexHull::triangle(a, b, c))), radius: 0.0 };
+                let limit = best.map_or(max_distance, |hit| hit.distance);
+                if let Some(hit) = cast_convex(&convex, &rel, local_dir, limit, &triangle, &Pose::identity()) {
+                    best = Some(hit);
+                }
+            });
+            best.map(|hit| Impact {
+                point: target_pose.transform_point(hit.point),
+                normal: target_pose.transform_vector(hit.normal),
+                distance: hit.distance,
+            })
+        },
+        _ => cast_convex(&Convex::from_shape(shape), pose, dir, max_distance, &Convex::from_shape(target), target_pose),
+    }
+}
+
+/// Conservative advancement: `a` moves along `dir` by the gap to `b` divided by the
+/// closing speed, which can never step past the first time of impact.
+fn cast_convex(a: &Convex, pose_a: &Pose, dir: Vec3, max_distance: f32, b: &Convex, pose_b: &Pose) -> Option<Impact> {
+    let mut t = 0.0;
+    let mut normal = -dir;
+    for _ in 0..MAX_ITERATIONS {
+        let moved = Pose::new(pose_a.position + dir * t, pose_a.orientation);
+        let (point_a, point_b, distance) = match gjk::closest_points(|d| a.support(&moved, d), |d| b.support(pose_b, d)) {
+            GjkResult::Separated { point_a, point_b, distance } => (point_a, point_b, distance),
+            // cores only overlap when the query started inside
+            GjkResult::Overlapping => return Some(Impact { point: moved.position, normal, distance: t }),
+        };
+
+        let n = if distance > 1e-6 { (point_b - point_a) / distance } else { dir };
+        normal = -n;
+        let gap = distance - a.radius - b.radius;
+        if gap < TOLERANCE {
+            return Some(Impact { point: point_b - n * b.radius, normal, distance: t });
+        }
+
+        let closing = dir.dot(n);
+        if closing <= 1e-6 {
+            return None;
+        }
+        t += gap / closing;
+        if t > max_distance {
+            return None;
+        }
+    }
+    None
+}
+
+fn ray_mesh(mesh: &TriMesh, origin: Vec3, dir: Vec3, max_distance: f32) -> Option<Impact> {
+    let mut best: Option<Impact> = None;
+    mesh.query_ray(origin, dir, max_distance, |tri| {
+        let [a, b, c] = mesh.get_triangle(tri);
+        let limit = best.map_or(max_distance, |hit| hit.distance);
+        if let Some(t) = ray_triangle(origin, dir, a, b, c) {
+            if t <= limit {
+                // meshes are two sided, face the normal back at the ray
+                let n = (b - a).cross(c - a).normalize();
+                let normal = if n.dot(dir) > 0.0 { -n } else { n };
+                best = Some(Impact { point: origin + dir * t, normal, distance: t });
+            }
+        }
+    });
+    best
+}
+
+// Möller-Trumbore, culling neither side
+fn ray_triangle(origin: Vec3, dir: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+    let e1 = b - a;
+    let e2 = c - a;
+    let p = dir.cross(e2);
+    let det = e1.dot(p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(e1);
+    let v = dir.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = e2.dot(q) * inv_det;
+    if t >= 0.0 { Some(t) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::RigidBody;
+    use crate::world::World;
+
+    fn add(world: &mut World, shape: Shape, position: Vec3) -> BodyHandle {
+        let mut body = RigidBody::from_shape(shape, 0.0);
+        body.set_position(position);
+        world.add_body(body)
+    }
+
+    fn floor_mesh() -> Shape {
+        let vertices = vec![
+            Vec3::new(-10.0, 0.0, -10.0),
+            Vec3::new(10.0, 0.0, -10.0),
+            Vec3::new(10.0, 0.0, 10.0),
+            Vec3::new(-10.0, 0.0, 10.0),
+        ];
+        Shape::trimesh(TriMesh::new(vertices, &[0, 2, 1, 0, 3, 2]))
+    }
+
+    #[test]
+    fn raycast_returns_closest_and_all_hits() {
+        let mut world = World::default();
+        let near = add(&mut world, Shape::Sphere { radius: 1.0 }, Vec3::new(0.0, 0.0, -5.0));
+        let far = add(&mut world, Shape::Box { half_extents: Vec3::splat(1.0) }, Vec3::new(0.0, 0.0, -10.0));
+        add(&mut world, Shape::Sphere { radius: 1.0 }, Vec3::new(5.0, 0.0, -5.0));
+
+        let hit = world.raycast(Vec3::zero(), -Vec3::unit_z(), 100.0, &[]).unwrap();
+        assert_eq!(hit.body, near);
+        assert!((hit.distance - 4.0).abs() < 1e-3);
+        assert!((hit.point - Vec3::new(0.0, 0.0, -4.0)).length() < 1e-3);
+        assert!(hit.normal.z() > 0.999);
+
+        let hits = world.raycast_all(Vec3::zero(), -Vec3::unit_z(), 100.0, &[]);
+        assert_eq!(hits.iter().map(|h| h.body).collect::<Vec<_>>(), vec![near, far]);
+        assert!((hits[1].distance - 9.0).abs() < 1e-3);
+
+        let skipped = world.raycast(Vec3::zero(), -Vec3::unit_z(), 100.0, &[near]).unwrap();
+        assert_eq!(skipped.body, far);
+        assert!(world.raycast(Vec3::zero(), -Vec3::unit_z(), 3.0, &[]).is_none());
+    }
+
+    #[test]
+    fn raycast_hits_mesh_and_capsule() {
+        let mut world = World::default();
+        let floor = add(&mut world, floor_mesh(), Vec3::zero());
+        let pillar = add(&mut world, Shape::Capsule { half_height: 1.0, radius: 0.5 }, Vec3::new(3.0, 1.5, 0.0));
+
+        let down = world.raycast(Vec3::new(1.0, 5.0, 2.0), -Vec3::unit_y(), 100.0, &[]).unwrap();
+        assert_eq!(down.body, floor);
+        assert!((down.distance - 5.0).abs() < 1e-4);
+        assert!(down.normal.y() > 0.999);
+
+        // from below the one sided looking floor still faces the ray
+        let up = world.raycast(Vec3::new(1.0, -1.0, 2.0), Vec3::unit_y(), 100.0, &[]).unwrap();
+        assert!(up.normal.y() < -0.999);
+
+        let side = world.raycast(Vec3::new(0.0, 2.0, 0.0), Vec3::unit_x(), 100.0, &[]).unwrap();
+        assert_eq!(side.body, pillar);
+        assert!((side.distance - 2.5).abs() < 1e-3);
+        assert!(side.normal.x() < -0.999);
+    }
+
+    #[test]
+    fn shape_cast_stops_at_first_contact() {
+        let mut world = World::default();
+        let floor = add(&mut world, floor_mesh(), Vec3::zero());
+        let wall = add(&mut world, Shape::Box { half_extents: Vec3::new(0.5, 2.0, 5.0) }, Vec3::new(5.0, 2.0, 0.0));
+
+        let ball = Shape::Sphere { radius: 0.5 };
+        let fall = world.shape_cast(&ball, &Pose::from_position(Vec3::new(0.0, 3.0, 0.0)), -Vec3::unit_y(), 10.0, &[]).unwrap();
+        assert_eq!(fall.body, floor);
+        assert!((fall.distance - 2.5).abs() < 1e-3);
+        assert!(fall.point.y().abs() < 1e-3);
+
+        let capsule = Shape::Capsule { half_height: 0.5, radius: 0.5 };
+        let slide = world.shape_cast(&capsule, &Pose::from_position(Vec3::new(0.0, 2.0, 0.0)), Vec3::unit_x(), 10.0, &[]).unwrap();
+        assert_eq!(slide.body, wall);
+        assert!((slide.distance - 4.0).abs() < 1e-3);
+        assert!(slide.normal.x() < -0.999);
+
+        assert!(world.shape_cast(&capsule, &Pose::from_position(Vec3::new(0.0, 2.0, 0.0)), Vec3::unit_x(), 3.0, &[]).is_none());
+    }
+
+    #[test]
+    fn zero_directions_hit_nothing() {
+        let mut world = World::default();
+        add(&mut world, Shape::Sphere { radius: 1.0 }, Vec3::zero());
+        assert!(world.raycast_all(Vec3::zero(), Vec3::zero(), 10.0, &[]).is_empty());
+        let ball = Shape::Sphere { radius: 0.5 };
+        assert!(world.shape_cast(&ball, &Pose::from_position(Vec3::new(0.0, 3.0, 0.0)), Vec3::zero(), 10.0, &[]).is_none());
+    }
+
+    #[test]
+    fn overlap_reports_touching_bodies() {
+        let mut world = World::default();
+        let floor = add(&mut world, floor_mesh(), Vec3::zero());
+        let crate_ = add(&mut world, Shape::Box { half_extents: Vec3::splat(0.5) }, Vec3::new(1.0, 0.5, 0.0));
+        add(&mut world, Shape::Box { half_extents: Vec3::splat(0.5) }, Vec3::new(5.0, 0.5, 0.0));
+
+        let probe = Shape::Sphere { radius: 0.6 };
+        let mut found = world.overlap(&probe, &Pose::from_position(Vec3::new(0.0, 0.5, 0.0)), &[]);
+        found.sort();
+        assert_eq!(found, vec![floor, crate_]);
+        assert_eq!(world.overlap(&probe, &Pose::from_position(Vec3::new(0.0, 0.5, 0.0)), &[floor]), vec![crate_]);
+        assert!(world.overlap(&probe, &Pose::from_position(Vec3::new(0.0, 3.0, 0.0)), &[]).is_empty());
+    }
+}
@@ -0,0 +1,692 @@
+use std::sync::Arc;
+
+use glam::{Mat3, Quat, Vec3};
+
+use super::aabb::Aabb;
+use super::broadphase::ray_aabb;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Pose {
+    pub position: Vec3,
+    pub orientation: Quat,
+}
+
+impl Pose {
+    pub fn new(position: Vec3, orientation: Quat) -> Self {
+        Self { position, orientation }
+    }
+
+    pub fn from_position(position: Vec3) -> Self {
+        Self { position, orientation: Quat::identity() }
+    }
+
+    pub fn identity() -> Self {
+        Self::from_position(Vec3::zero())
+    }
+
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        self.orientation.mul_vec3(point) + self.position
+    }
+
+    pub fn transform_vector(&self, vector: Vec3) -> Vec3 {
+        self.orientation.mul_vec3(vector)
+    }
+
+    pub fn inverse_transform_point(&self, point: Vec3) -> Vec3 {
+        self.orientation.conjugate().mul_vec3(point - self.position)
+    }
+
+    pub fn inverse_transform_vector(&self, vector: Vec3) -> Vec3 {
+        self.orientation.conjugate().mul_vec3(vector)
+    }
+
+    /// Pose of `other` expressed in the frame of `self`.
+    pub fn relative(&self, other: &Pose) -> Pose {
+        Pose {
+            position: self.inverse_transform_point(other.position),
+            orientation: (self.orientation.conjugate() * other.orientation).normalize(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Shape {
+    Sphere { radius: f32 },
+    Box { half_extents: Vec3 },
+    /// Capsule aligned with the local Y axis, `half_height` excludes the caps.
+    Capsule { half_height: f32, radius: f32 },
+    ConvexHull(Arc<ConvexHull>),
+    TriMesh(Arc<TriMesh>),
+}
+
+impl Shape {
+    /// Returns `None` when the points are all coplanar.
+    pub fn convex_hull(points: &[Vec3]) -> Option<Self> {
+        ConvexHull::new(points).map(|h| Shape::ConvexHull(Arc::new(h)))
+    }
+
+    pub fn trimesh(mesh: TriMesh) -> Self {
+        Shape::TriMesh(Arc::new(mesh))
+    }
+
+    pub fn is_convex(&self) -> bool {
+        !matches!(self, Shape::TriMesh(_))
+    }
+
+    pub fn get_local_aabb(&self) -> Aabb {
+        match self {
+            Shape::Sphere { radius } => Aabb::from_center(Vec3::zero(), Vec3::splat(*radius)),
+            Shape::Box { half_extents } => Aabb::from_center(Vec3::zero(), *half_extents),
+            Shape::Capsule { half_height, radius } => {
+                Aabb::from_center(Vec3::zero(), Vec3::new(*radius, half_height + radius, *radius))
+            },
+            Shape::ConvexHull(hull) => Aabb::from_points(hull.get_vertices()),
+            Shape::TriMesh(mesh) => mesh.get_aabb(),
+        }
+    }
+
+    pub fn get_aabb(&self, pose: &Pose) -> Aabb {
+        match self {
+            // spheres don't care about orientation and give a tighter box this way
+            Shape::Sphere { radius } => Aabb::from_center(pose.position, Vec3::splat(*radius)),
+            _ => self.get_local_aabb().transformed(pose),
+        }
+    }
+
+    /// Mass and inertia tensor around the center of mass for a uniform `density`.
+    /// Triangle meshes are meant for static geometry and have no mass.
+    pub fn mass_properties(&self, density: f32) -> (f32, Mat3) {
+        match self {
+            Shape::Sphere { radius } => {
+                let mass = density * 4.0 / 3.0 * std::f32::consts::PI * radius.powi(3);
+                (mass, super::body::sphere_inertia(mass, *radius))
+            },
+            Shape::Box { half_extents } => {
+                let mass = density * 8.0 * half_extents.x() * half_extents.y() * half_extents.z();
+                (mass, super::body::box_inertia(mass, *half_extents))
+            },
+            Shape::Capsule { half_height, radius } => {
+                let pi = std::f32::consts::PI;
+                let (r, h) = (*radius, 2.0 * half_height);
+                let cyl_mass = density * pi * r * r * h;
+                let caps_mass = density * 4.0 / 3.0 * pi * r.powi(3);
+
+                let cyl_axial = 0.5 * cyl_mass * r * r;
+                let cyl_side = cyl_mass * (3.0 * r * r + h * h) / 12.0;
+                // two hemispheres, shifted by the parallel axis theorem
+                let caps_axial = 0.4 * caps_mass * r * r;
+                let caps_side = caps_mass * (0.4 * r * r + 0.5 * h * h + 0.375 * h * r);
+
+                let side = cyl_side + caps_side;
+                let axial = cyl_axial + caps_axial;
+                (cyl_mass + caps_mass, Mat3::from_cols(
+                    Vec3::new(side, 0.0, 0.0),
+                    Vec3::new(0.0, axial, 0.0),
+                    Vec3::new(0.0, 0.0, side),
+                ))
+            },
+            Shape::ConvexHull(hull) => hull.mass_properties(density),
+            Shape::TriMesh(_) => (0.0, Mat3::zero()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Face {
+    /// Counter-clockwise when looking against the normal.
+    pub vertices: Vec<usize>,
+    pub normal: Vec3,
+    pub offset: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConvexHull {
+    vertices: Vec<Vec3>,
+    faces: Vec<Face>,
+    edges: Vec<(usize, usize)>,
+    centroid: Vec3,
+}
+
+impl ConvexHull {
+    pub fn new(points: &[Vec3]) -> Option<Self> {
+        let triangles = quickhull(points)?;
+
+        // keep only the points that ended up on the hull
+        let mut remap = vec![usize::MAX; points.len()];
+        let mut vertices = vec![];
+        for tri in &triangles {
+            for &i in tri {
+                if remap[i] == usize::MAX {
+                    remap[i] = vertices.len();
+                    vertices.push(points[i]);
+                }
+            }
+        }
+        let triangles = triangles
+            .into_iter()
+            .map(|t| [remap[t[0]], remap[t[1]], remap[t[2]]])
+            .collect::<Vec<_>>();
+
+        let polygons = merge_coplanar(&vertices, &triangles);
+        Some(Self::from_polygons(vertices, polygons))
+    }
+
+    pub fn cuboid(half_extents: Vec3) -> Self {
+        let h = half_extents;
+        let vertices = (0..8)
+            .map(|i| Vec3::new(
+                if i & 1 == 0 { -h.x() } else { h.x() },
+                if i & 2 == 0 { -h.y() } else { h.y() },
+                if i & 4 == 0 { -h.z() } else { h.z() },
+            ))
+            .collect();
+        let faces = vec![
+            vec![0, 4, 6, 2], vec![1, 3, 7, 5], // -x, +x
+            vec![0, 1, 5, 4], vec![2, 6, 7, 3], // -y, +y
+            vec![0, 2, 3, 1], vec![4, 5, 7, 6], // -z, +z
+        ];
+        Self::from_polygons(vertices, faces)
+    }
+
+    /// A flat, two sided hull used to collide convex shapes against mesh triangles.
+    pub fn triangle(a: Vec3, b: Vec3, c: Vec3) -> Self {
+        Self::from_polygons(vec![a, b, c], vec![vec![0, 1, 2], vec![0, 2, 1]])
+    }
+
+    fn from_polygons(vertices: Vec<Vec3>, polygons: Vec<Vec<usize>>) -> Self {
+        let faces = polygons
+            .into_iter()
+            .map(|indices| {
+                // Newell's method is robust for slightly non-planar polygons
+                let mut normal = Vec3::zero();
+                for (i, &a) in indices.iter().enumerate() {
+                    let b = indices[(i + 1) % indices.len()];
+                    normal += vertices[a].cross(vertices[b]);
+                }
+                let normal = normal.normalize();
+                let offset = normal.dot(vertices[indices[0]]);
+                Face { vertices: indices, normal, offset }
+            })
+            .collect::<Vec<_>>();
+
+        let mut edges = vec![];
+        for face in &faces {
+            for (i, &a) in face.vertices.iter().enumerate() {
+                let b = face.vertices[(i + 1) % face.vertices.len()];
+                if !edges.contains(&(a, b)) && !edges.contains(&(b, a)) {
+                    edges.push((a, b));
+                }
+            }
+        }
+
+        let centroid = vertices.iter().fold(Vec3::zero(), |acc, &v| acc + v) / vertices.len() as f32;
+
+        Self { vertices, faces, edges, centroid }
+    }
+
+    pub fn get_vertices(&self) -> &[Vec3] {
+        &self.vertices
+    }
+
+    pub fn get_faces(&self) -> &[Face] {
+        &self.faces
+    }
+
+    pub fn get_edges(&self) -> &[(usize, usize)] {
+        &self.edges
+    }
+
+    pub fn get_centroid(&self) -> Vec3 {
+        self.centroid
+    }
+
+    pub fn support(&self, dir: Vec3) -> Vec3 {
+        let mut best = self.vertices[0];
+        let mut best_dot = best.dot(dir);
+        for &v in &self.vertices[1..] {
+            let d = v.dot(dir);
+            if d > best_dot {
+                best = v;
+                best_dot = d;
+            }
+        }
+        best
+    }
+
+    /// Integrates over tetrahedra fanned from the centroid.
+    fn mass_properties(&self, density: f32) -> (f32, Mat3) {
+        let origin = self.centroid;
+        let mut volume = 0.0;
+        let mut center = Vec3::zero();
+        let mut covariance = [[0f32; 3]; 3];
+
+        for face in &self.faces {
+            let a = self.vertices[face.vertices[0]] - origin;
+            for w in face.vertices[1..].windows(2) {
+                let b = self.vertices[w[0]] - origin;
+                let c = self.vertices[w[1]] - origin;
+                let det = a.dot(b.cross(c));
+                volume += det / 6.0;
+                center += (a + b + c) * (det / 24.0);
+
+                // covariance of a tetrahedron with a vertex at the origin
+                let s = a + b + c;
+                for i in 0..3 {
+                    for j in 0..3 {
+                        let aa = a[i] * a[j] + b[i] * b[j] + c[i] * c[j];
+                        covariance[i][j] += det / 120.0 * (aa + s[i] * s[j]);
+                    }
+                }
+            }
+        }
+
+        if volume <= 0.0 {
+            return (0.0, Mat3::zero());
+        }
+
+        let mass = density * volume;
+        let com = center / volume;
+        let mut cols = [Vec3::zero(); 3];
+        let mut c = [[0f32; 3]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                // move the covariance from the fan origin to the center of mass
+                c[i][j] = density * covariance[i][j] - mass * com[i] * com[j];
+            }
+        }
+        let trace = c[0][0] + c[1][1] + c[2][2];
+        for (j, col) in cols.iter_mut().enumerate() {
+            *col = Vec3::new(
+                if j == 0 { trace } else { 0.0 } - c[0][j],
+                if j == 1 { trace } else { 0.0 } - c[1][j],
+                if j == 2 { trace } else { 0.0 } - c[2][j],
+            );
+        }
+        (mass, Mat3::from_cols(cols[0], cols[1], cols[2]))
+    }
+}
+
+/// Incremental hull returning outward facing triangles indexing into `points`.
+fn quickhull(points: &[Vec3]) -> Option<Vec<[usize; 3]>> {
+    if points.len() < 4 {
+        return None;
+    }
+
+    let bounds = Aabb::from_points(points);
+    let eps = 1e-5 * (bounds.max - bounds.min).length().max(1e-3);
+
+    let p0 = 0;
+    let p1 = (0..points.len()).max_by(|&a, &b| {
+        cmp_f32((points[a] - points[p0]).length_squared(), (points[b] - points[p0]).length_squared())
+    })?;
+    // every point is the same one
+    if (points[p1] - points[p0]).length() < eps {
+        return None;
+    }
+    let dir = (points[p1] - points[p0]).normalize();
+    let p2 = (0..points.len()).max_by(|&a, &b| {
+        let da = (points[a] - points[p0]).cross(dir).length_squared();
+        let db = (points[b] - points[p0]).cross(dir).length_squared();
+        cmp_f32(da, db)
+    })?;
+    let normal = (points[p1] - points[p0]).cross(points[p2] - points[p0]);
+    if normal.length() < eps {
+        return None;
+    }
+    let normal = normal.normalize();
+    let p3 = (0..points.len()).max_by(|&a, &b| {
+        cmp_f32(normal.dot(points[a] - points[p0]).abs(), normal.dot(points[b] - points[p0]).abs())
+    })?;
+    if normal.dot(points[p3] - points[p0]).abs() < eps {
+        return None;
+    }
+
+    let mut faces = if normal.dot(points[p3] - points[p0]) < 0.0 {
+        vec![[p0, p1, p2], [p0, p3, p1], [p1, p3, p2], [p2, p3, p0]]
+    } else {
+        vec![[p0, p2, p1], [p0, p1, p3], [p1, p2, p3], [p2, p0, p3]]
+    };
+
+    let face_plane = |f: &[usize; 3]| {
+        let n = (points[f[1]] - points[f[0]]).cross(points[f[2]] - points[f[0]]).normalize();
+        (n, n.dot(points[f[0]]))
+    };
+
+    for (i, &p) in points.iter().enumerate() {
+        if i == p0 || i == p1 || i == p2 || i == p3 {
+            continue;
+        }
+
+        let visible = faces
+            .iter()
+            .map(|f| {
+                let (n, d) = face_plane(f);
+                n.dot(p) - d > eps
+            })
+            .collect::<Vec<_>>();
+        if !visible.iter().any(|&v| v) {
+            continue;
+        }
+
+        // edges of visible faces whose twin belongs to a hidden face form the horizon
+        let mut horizon = vec![];
+        for (f, _) in faces.iter().zip(visible.iter()).filter(|(_, &v)| v) {
+            for k in 0..3 {
+                let (a, b) = (f[k], f[(k + 1) % 3]);
+                let twin_hidden = faces
+                    .iter()
+                    .zip(visible.iter())
+                    .any(|(g, &gv)| !gv && (0..3).any(|m| g[m] == b && g[(m + 1) % 3] == a));
+                if twin_hidden {
+                    horizon.push((a, b));
+                }
+            }
+        }
+
+        let mut kept = faces
+            .iter()
+            .zip(visible.iter())
+            .filter(|(_, &v)| !v)
+            .map(|(f, _)| *f)
+            .collect::<Vec<_>>();
+        kept.extend(horizon.into_iter().map(|(a, b)| [a, b, i]));
+        faces = kept;
+    }
+
+    Some(faces)
+}
+
+/// Groups hull triangles lying on the same plane into convex polygons.
+fn merge_coplanar(vertices: &[Vec3], triangles: &[[usize; 3]]) -> Vec<Vec<usize>> {
+    let plane = |t: &[usize; 3]| {
+        let n = (vertices[t[1]] - vertices[t[0]]).cross(vertices[t[2]] - vertices[t[0]]).normalize();
+        (n, n.dot(vertices[t[0]]))
+    };
+
+    let mut used = vec![false; triangles.len()];
+    let mut polygons = vec![];
+    for i in 0..triangles.len() {
+        if used[i] {
+            continue;
+        }
+        let (n, d) = plane(&triangles[i]);
+        let mut indices = vec![];
+        for (j, t) in triangles.iter().enumerate().skip(i) {
+            let (nj, dj) = plane(t);
+            if !used[j] && n.dot(nj) > 1.0 - 1e-4 && (d - dj).abs() < 1e-4 * d.abs().max(1.0) {
+                used[j] = true;
+                for &v in t {
+                    if !indices.contains(&v) {
+                        indices.push(v);
+                    }
+                }
+            }
+        }
+
+        // order around the centroid so the polygon winds counter-clockwise about n
+        let center = indices.iter().fold(Vec3::zero(), |acc, &v| acc + vertices[v]) / indices.len() as f32;
+        let u = (vertices[indices[0]] - center).normalize();
+        let w = n.cross(u);
+        indices.sort_by(|&a, &b| {
+            let da = vertices[a] - center;
+            let db = vertices[b] - center;
+            cmp_f32(da.dot(w).atan2(da.dot(u)), db.dot(w).atan2(db.dot(u)))
+        });
+        polygons.push(indices);
+    }
+    polygons
+}
+
+fn cmp_f32(a: f32, b: f32) -> std::cmp::Ordering {
+    a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+}
+
+enum BvhNode {
+    Leaf { aabb: Aabb, start: usize, count: usize },
+    Inner { aabb: Aabb, left: usize, right: usize },
+}
+
+impl BvhNode {
+    fn get_aabb(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf { aabb, .. } | BvhNode::Inner { aabb, .. } => aabb,
+        }
+    }
+}
+
+/// Static triangle soup with a bounding volume hierarchy over its triangles.
+pub struct TriMesh {
+    vertices: Vec<Vec3>,
+    triangles: Vec<[u32; 3]>,
+    nodes: Vec<BvhNode>,
+}
+
+impl TriMesh {
+
+    const LEAF_SIZE: usize = 4;
+
+    pub fn new(vertices: Vec<Vec3>, indices: &[u32]) -> Self {
+        let triangles = indices
+            .chunks(3)
+            .filter(|t| t.len() == 3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+        let mut mesh = Self { vertices, triangles, nodes: vec![] };
+        mesh.build_bvh();
+        mesh
+    }
+
+    /// Builds a mesh straight from tobj style data: flat `xyz` positions and triangle indices.
+    pub fn from_flat(positions: &[f32], indices: &[u32]) -> Self {
+        let vertices = positions.chunks(3).map(|p| Vec3::new(p[0], p[1], p[2])).collect();
+        Self::new(vertices, indices)
+    }
+
+    pub fn scale(&mut self, scale: Vec3) {
+        for v in &mut self.vertices {
+            *v *= scale;
+        }
+        self.build_bvh();
+    }
+
+    pub fn get_vertices(&self) -> &[Vec3] {
+        &self.vertices
+    }
+
+    pub fn get_triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn get_triangle(&self, index: usize) -> [Vec3; 3] {
+        let t = self.triangles[index];
+        [self.vertices[t[0] as usize], self.vertices[t[1] as usize], self.vertices[t[2] as usize]]
+    }
+
+    pub fn get_aabb(&self) -> Aabb {
+        self.nodes.first().map(|n| *n.get_aabb()).unwrap_or_else(|| Aabb::from_center(Vec3::zero(), Vec3::zero()))
+    }
+
+    /// Calls `callback` with the index of every triangle whose bounds touch `aabb`.
+    pub fn query_aabb(&self, aabb: &Aabb, mut callback: impl FnMut(usize)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if !node.get_aabb().intersects(aabb) {
+                continue;
+            }
+            match *node {
+                BvhNode::Leaf { start, count, .. } => {
+                    for tri in start..start + count {
+                        if self.triangle_aabb(tri).intersects(aabb) {
+                            callback(tri);
+                        }
+                    }
+                },
+                BvhNode::Inner { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                },
+            }
+        }
+    }
+
+    /// Calls `callback` with the index of every triangle whose bounds the ray
+    /// `origin + t * dir` crosses for `t` in `[0, max_t]`.
+    pub fn query_ray(&self, origin: Vec3, dir: Vec3, max_t: f32, mut callback: impl FnMut(usize)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let hits = |aabb: &Aabb| matches!(ray_aabb(origin, dir, aabb), Some(t) if t <= max_t);
+        let mut stack = vec![0];
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if !hits(node.get_aabb()) {
+                continue;
+            }
+            match *node {
+                BvhNode::Leaf { start, count, .. } => {
+                    for tri in start..start + count {
+                        if hits(&self.triangle_aabb(tri)) {
+                            callback(tri);
+                        }
+                    }
+                },
+                BvhNode::Inner { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                },
+            }
+        }
+    }
+
+    fn triangle_aabb(&self, index: usize) -> Aabb {
+        Aabb::from_points(&self.get_triangle(index))
+    }
+
+    fn build_bvh(&mut self) {
+        self.nodes.clear();
+        if self.triangles.is_empty() {
+            return;
+        }
+        let count = self.triangles.len();
+        self.build_node(0, count);
+    }
+
+    fn build_node(&mut self, start: usize, count: usize) -> usize {
+        let aabb = (start..start + count)
+            .map(|t| self.triangle_aabb(t))
+            .fold(Aabb::empty(), |acc, b| acc.union(&b));
+
+        let index = self.nodes.len();
+        if count <= Self::LEAF_SIZE {
+            self.nodes.push(BvhNode::Leaf { aabb, start, count });
+            return index;
+        }
+
+        // median split along the widest axis of the triangle centroids
+        let centroid = |mesh: &Self, t: &[u32; 3]| {
+            (mesh.vertices[t[0] as usize] + mesh.vertices[t[1] as usize] + mesh.vertices[t[2] as usize]) / 3.0
+        };
+        let centroids = Aabb::from_points(&self.triangles[start..start + count]
+            .iter()
+            .map(|t| centroid(self, t))
+            .collect::<Vec<_>>());
+        let extent = centroids.max - centroids.min;
+        let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+            0
+        } else if extent.y() >= extent.z() {
+            1
+        } else {
+            2
+        };
+
+        let vertices = &self.vertices;
+        self.triangles[start..start + count].sort_by(|a, b| {
+            let ca = (vertices[a[0] as usize] + vertices[a[1] as usize] + vertices[a[2] as usize])[axis];
+            let cb = (vertices[b[0] as usize] + vertices[b[1] as usize] + vertices[b[2] as usize])[axis];
+            cmp_f32(ca, cb)
+        });
+
+        self.nodes.push(BvhNode::Leaf { aabb, start, count });
+        let half = count / 2;
+        let left = self.build_node(start, half);
+        let right = self.build_node(start + half, count - half);
+        self.nodes[index] = BvhNode::Inner { aabb, left, right };
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hull_of_cube_corners_has_six_quads() {
+        let mut points = vec![];
+        for i in 0..8 {
+            points.push(Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32));
+        }
+        // interior points must not end up on the hull
+        points.push(Vec3::splat(0.5));
+        points.push(Vec3::new(0.2, 0.7, 0.4));
+
+        let hull = ConvexHull::new(&points).unwrap();
+        assert_eq!(hull.get_vertices().len(), 8);
+        assert_eq!(hull.get_faces().len(), 6);
+        assert!(hull.get_faces().iter().all(|f| f.vertices.len() == 4));
+        assert_eq!(hull.get_edges().len(), 12);
+        for face in hull.get_faces() {
+            assert!(face.normal.dot(hull.get_centroid()) < face.offset);
+        }
+    }
+
+    #[test]
+    fn coplanar_points_have_no_hull() {
+        let points = [Vec3::zero(), Vec3::unit_x(), Vec3::unit_z(), Vec3::new(1.0, 0.0, 1.0)];
+        assert!(ConvexHull::new(&points).is_none());
+    }
+
+    #[test]
+    fn coincident_points_have_no_hull() {
+        let points = [Vec3::one(); 5];
+        assert!(ConvexHull::new(&points).is_none());
+    }
+
+    #[test]
+    fn hull_mass_matches_box() {
+        let half = Vec3::new(1.0, 2.0, 0.5);
+        let (box_mass, box_inertia) = Shape::Box { half_extents: half }.mass_properties(3.0);
+        let (hull_mass, hull_inertia) = ConvexHull::cuboid(half).mass_properties(3.0);
+        assert!((box_mass - hull_mass).abs() < 1e-3);
+        assert!((box_inertia.x_axis() - hull_inertia.x_axis()).length() < 1e-3);
+        assert!((box_inertia.y_axis() - hull_inertia.y_axis()).length() < 1e-3);
+        assert!((box_inertia.z_axis() - hull_inertia.z_axis()).length() < 1e-3);
+    }
+
+    #[test]
+    fn trimesh_query_finds_overlapping_triangles() {
+        // a 10x10 grid of quads on the XZ plane
+        let mut positions = vec![];
+        for z in 0..=10 {
+            for x in 0..=10 {
+                positions.extend_from_slice(&[x as f32, 0.0, z as f32]);
+            }
+        }
+        let mut indices = vec![];
+        for z in 0..10u32 {
+            for x in 0..10u32 {
+                let i = z * 11 + x;
+                indices.extend_from_slice(&[i, i + 11, i + 1, i + 1, i + 11, i + 12]);
+            }
+        }
+        let mesh = TriMesh::from_flat(&positions, &indices);
+        assert_eq!(mesh.get_triangle_count(), 200);
+
+        let mut hits = vec![];
+        mesh.query_aabb(&Aabb::new(Vec3::new(2.2, -1.0, 2.2), Vec3::new(2.8, 1.0, 2.8)), |t| hits.push(t));
+        assert_eq!(hits.len(), 2);
+    }
+}
@@ -0,0 +1,154 @@
+use glam::{Mat3, Vec3};
+
+#[derive(Clone, Copy, Debug)]
+pub struct SolverSettings {
+    /// Velocity iterations per step, more is stiffer but slower.
+    pub iterations: usize,
+    /// Starts each step from last step's impulses, helps stacks and chains converge.
+    pub warm_starting: bool,
+    /// Fraction of the position error corrected per step.
+    pub baumgarte: f32,
+    /// Penetration tolerated before contacts push back, avoids jitter on resting contacts.
+    pub penetration_slop: f32,
+    /// Approach speed below which contacts don't bounce.
+    pub restitution_threshold: f32,
+}
+
+impl Default for SolverSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 10,
+            warm_starting: true,
+            baumgarte: 0.2,
+            penetration_slop: 0.005,
+            restitution_threshold: 1.0,
+        }
+    }
+}
+
+/// Velocity state of a body for the duration of a solve.
+#[derive(Clone, Copy)]
+pub(crate) struct SolverBody {
+    pub linear_velocity: Vec3,
+    pub angular_velocity: Vec3,
+    pub inv_mass: f32,
+    pub inv_inertia: Mat3,
+}
+
+/// One scalar constraint `J * v + bias = 0` between two bodies, with its
+/// accumulated impulse clamped to `[lower, upper]`.
+pub(crate) struct Row {
+    pub body_a: usize,
+    pub body_b: usize,
+    pub linear_a: Vec3,
+    pub angular_a: Vec3,
+    pub linear_b: Vec3,
+    pub angular_b: Vec3,
+    pub bias: f32,
+    pub lower: f32,
+    pub upper: f32,
+    pub impulse: f32,
+    /// Friction rows take their bounds from the normal row at this index.
+    pub friction: Option<(usize, f32)>,
+    eff_mass: f32,
+}
+
+impl Row {
+    pub fn new(body_a: usize, body_b: usize, linear: Vec3, angular_a: Vec3, angular_b: Vec3) -> Self {
+        Self {
+            body_a,
+            body_b,
+            linear_a: -linear,
+            angular_a: -angular_a,
+            linear_b: linear,
+            angular_b,
+            bias: 0.0,
+            lower: f32::MIN,
+            upper: f32::MAX,
+            impulse: 0.0,
+            friction: None,
+            eff_mass: 0.0,
+        }
+    }
+
+    /// Constraint keeping the points `a + ra` and `b + rb` from moving apart along `axis`.
+    pub fn point(body_a: usize, body_b: usize, axis: Vec3, ra: Vec3, rb: Vec3) -> Self {
+        Self::new(body_a, body_b, axis, ra.cross(axis), rb.cross(axis))
+    }
+
+    /// Constraint on the relative angular velocity around `axis`.
+    pub fn angular(body_a: usize, body_b: usize, axis: Vec3) -> Self {
+        Self::new(body_a, body_b, Vec3::zero(), axis, axis)
+    }
+
+    pub fn with_bias(mut self, bias: f32) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    pub fn with_bounds(mut self, lower: f32, upper: f32) -> Self {
+        self.lower = lower;
+        self.upper = upper;
+        self
+    }
+
+    pub fn with_impulse(mut self, impulse: f32) -> Self {
+        self.impulse = impulse;
+        self
+    }
+
+    fn relative_velocity(&self, bodies: &[SolverBody]) -> f32 {
+        let a = &bodies[self.body_a];
+        let b = &bodies[self.body_b];
+        self.linear_a.dot(a.linear_velocity) + self.angular_a.dot(a.angular_velocity)
+            + self.linear_b.dot(b.linear_velocity) + self.angular_b.dot(b.angular_velocity)
+    }
+
+    fn apply(&self, bodies: &mut [SolverBody], impulse: f32) {
+        let a = &mut bodies[self.body_a];
+        a.linear_velocity += self.linear_a * (a.inv_mass * impulse);
+        a.angular_velocity += a.inv_inertia * self.angular_a * impulse;
+        let b = &mut bodies[self.body_b];
+        b.linear_velocity += self.linear_b * (b.inv_mass * impulse);
+        b.angular_velocity += b.inv_inertia * self.angular_b * impulse;
+    }
+}
+
+pub(crate) fn solve(rows: &mut [Row], bodies: &mut [SolverBody], settings: &SolverSettings) {
+    for row in rows.iter_mut() {
+        let a = &bodies[row.body_a];
+        let b = &bodies[row.body_b];
+        let k = a.inv_mass * row.linear_a.length_squared()
+            + row.angular_a.dot(a.inv_inertia * row.angular_a)
+            + b.inv_mass * row.linear_b.length_squared()
+            + row.angular_b.dot(b.inv_inertia * row.angular_b);
+        row.eff_mass = if k > 1e-12 { 1.0 / k } else { 0.0 };
+    }
+
+    if settings.warm_starting {
+        for row in rows.iter() {
+            row.apply(bodies, row.impulse);
+        }
+    } else {
+        for row in rows.iter_mut() {
+            row.impulse = 0.0;
+        }
+    }
+
+    for _ in 0..settings.iterations {
+        for i in 0..rows.len() {
+            if let Some((normal, mu)) = rows[i].friction {
+                let limit = mu * rows[normal].impulse;
+                rows[i].lower = -limit;
+                rows[i].upper = limit;
+            }
+
+            let row = &rows[i];
+            let delta = -row.eff_mass * (row.relative_velocity(bodies) + row.bias);
+            let old = row.impulse;
+            let new = (old + delta).clamp(row.lower, row.upper);
+            rows[i].impulse = new;
+            rows[i].apply(bodies, new - old);
+        }
+    }
+}
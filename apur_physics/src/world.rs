@@ -0,0 +1,833 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use glam::{Mat3, Vec3};
+
+use super::body::RigidBody;
+use super::broadphase::{DynamicTree, ProxyId};
+use super::collision::{self, any_perpendicular, ContactManifold};
+use super::joint::{Joint, JointHandle, JointKind};
+use super::query::{self, QueryHit};
+use super::shape::{Pose, Shape};
+use super::solver::{self, Row, SolverBody, SolverSettings};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct BodyHandle(usize);
+
+pub struct Contact {
+    pub body_a: BodyHandle,
+    pub body_b: BodyHandle,
+    /// Normal points from `body_a` to `body_b`.
+    pub manifold: ContactManifold,
+}
+
+/// Solved impulses of a contact point, kept around to warm start the next step.
+struct CachedContact {
+    /// Contact position in body a's local space, used to match points between steps.
+    local_point: Vec3,
+    normal: Vec3,
+    impulses: [f32; 3],
+}
+
+pub struct World {
+    bodies: Vec<Option<RigidBody>>,
+    proxies: Vec<Option<ProxyId>>,
+    free_slots: Vec<usize>,
+    broadphase: DynamicTree<BodyHandle>,
+    contacts: Vec<Contact>,
+    contact_cache: HashMap<(BodyHandle, BodyHandle), Vec<CachedContact>>,
+    joints: Vec<Option<Joint>>,
+    joint_free_slots: Vec<usize>,
+    /// Body pairs held together by some joint, lowest handle first. They don't collide.
+    joined_pairs: HashSet<(BodyHandle, BodyHandle)>,
+    solver_settings: SolverSettings,
+    gravity: Vec3,
+    timestep: f32,
+    accumulator: f32,
+}
+
+impl World {
+
+    pub const DEFAULT_TIMESTEP: f32 = 1.0 / 60.0;
+    // when a frame takes too long we drop the extra time instead of spiralling
+    const MAX_SUBSTEPS: u32 = 8;
+
+    pub fn new(gravity: Vec3) -> Self {
+        Self {
+            bodies: vec![],
+            proxies: vec![],
+            free_slots: vec![],
+            broadphase: DynamicTree::default(),
+            contacts: vec![],
+            contact_cache: HashMap::new(),
+            joints: vec![],
+            joint_free_slots: vec![],
+            joined_pairs: HashSet::new(),
+            solver_settings: SolverSettings::default(),
+            gravity,
+            timestep: Self::DEFAULT_TIMESTEP,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn add_body(&mut self, body: RigidBody) -> BodyHandle {
+        let slot = match self.free_slots.pop() {
+            Some(slot) => slot,
+            None => {
+                self.bodies.push(None);
+                self.proxies.push(None);
+                self.bodies.len() - 1
+            },
+        };
+        let handle = BodyHandle(slot);
+        self.proxies[slot] = body.get_aabb().map(|aabb| self.broadphase.insert(aabb, handle));
+        self.bodies[slot] = Some(body);
+        handle
+    }
+
+    pub fn remove_body(&mut self, handle: BodyHandle) -> Option<RigidBody> {
+        let body = self.bodies.get_mut(handle.0)?.take();
+        if body.is_some() {
+            if let Some(proxy) = self.proxies[handle.0].take() {
+                self.broadphase.remove(proxy);
+            }
+            self.contacts.retain(|c| c.body_a != handle && c.body_b != handle);
+            self.contact_cache.retain(|&(a, b), _| a != handle && b != handle);
+            for slot in 0..self.joints.len() {
+                if let Some(joint) = &self.joints[slot] {
+                    let (a, b) = joint.get_bodies();
+                    if a == handle || b == handle {
+                        self.remove_joint(JointHandle(slot));
+                    }
+                }
+            }
+            self.free_slots.push(handle.0);
+        }
+        body
+    }
+
+    pub fn get_body(&self, handle: BodyHandle) -> Option<&RigidBody> {
+        self.bodies.get(handle.0)?.as_ref()
+    }
+
+    pub fn get_body_mut(&mut self, handle: BodyHandle) -> Option<&mut RigidBody> {
+        self.bodies.get_mut(handle.0)?.as_mut()
+    }
+
+    /// Joins two bodies at a world space anchor, letting them rotate freely around it.
+    pub fn add_ball_socket(&mut self, a: BodyHandle, b: BodyHandle, anchor: Vec3) -> Option<JointHandle> {
+        self.add_joint(a, b, anchor, anchor, |_, _| JointKind::BallSocket)
+    }
+
+    /// Joins two bodies at a world space anchor, letting them rotate only around `axis`.
+    pub fn add_hinge(&mut self, a: BodyHandle, b: BodyHandle, anchor: Vec3, axis: Vec3) -> Option<JointHandle> {
+        self.add_joint(a, b, anchor, anchor, |a, b| Joint::hinge_kind(a, b, axis))
+    }
+
+    /// Locks relative rotation and lets b only translate along the world space `axis`.
+    pub fn add_slider(&mut self, a: BodyHandle, b: BodyHandle, axis: Vec3) -> Option<JointHandle> {
+        let anchor = self.get_body(b)?.get_position();
+        self.add_joint(a, b, anchor, anchor, |a, _| JointKind::Slider {
+            local_axis_a: a.get_pose().inverse_transform_vector(axis.normalize()),
+        })
+    }
+
+    /// Glues two bodies together in their current relative pose.
+    pub fn add_fixed(&mut self, a: BodyHandle, b: BodyHandle) -> Option<JointHandle> {
+        let anchor = self.get_body(b)?.get_position();
+        self.add_joint(a, b, anchor, anchor, |_, _| JointKind::Fixed)
+    }
+
+    /// Keeps the distance between two world space anchors within `[min, max]`,
+    /// pass the same value twice for a rigid rod.
+    pub fn add_distance(
+        &mut self,
+        a: BodyHandle,
+        b: BodyHandle,
+        anchor_a: Vec3,
+        anchor_b: Vec3,
+        min: f32,
+        max: f32,
+    ) -> Option<JointHandle> {
+        assert!(min <= max, "distance joint min must not exceed max");
+        self.add_joint(a, b, anchor_a, anchor_b, |_, _| JointKind::Distance { min, max })
+    }
+
+    fn add_joint(
+        &mut self,
+        ha: BodyHandle,
+        hb: BodyHandle,
+        anchor_a: Vec3,
+        anchor_b: Vec3,
+        kind: impl FnOnce(&RigidBody, &RigidBody) -> JointKind,
+    ) -> Option<JointHandle> {
+        if ha == hb {
+            return None;
+        }
+        let (a, b) = (self.get_body(ha)?, self.get_body(hb)?);
+        let joint = Joint::new((ha, a, anchor_a), (hb, b, anchor_b), kind(a, b));
+
+        let slot = match self.joint_free_slots.pop() {
+            Some(slot) => slot,
+            None => {
+                self.joints.push(None);
+                self.joints.len() - 1
+            },
+        };
+        self.joints[slot] = Some(joint);
+        self.joined_pairs.insert((ha.min(hb), ha.max(hb)));
+        Some(JointHandle(slot))
+    }
+
+    pub fn remove_joint(&mut self, handle: JointHandle) -> Option<Joint> {
+        let joint = self.joints.get_mut(handle.0)?.take();
+        if let Some(joint) = &joint {
+            self.joint_free_slots.push(handle.0);
+            // the pair may be joined more than once
+            let (ha, hb) = joint.get_bodies();
+            let pair = (ha.min(hb), ha.max(hb));
+            let still_joined = self.joints.iter().flatten().any(|j| {
+                let (ja, jb) = j.get_bodies();
+                (ja.min(jb), ja.max(jb)) == pair
+            });
+            if !still_joined {
+                self.joined_pairs.remove(&pair);
+            }
+        }
+        joint
+    }
+
+    pub fn get_joint(&self, handle: JointHandle) -> Option<&Joint> {
+        self.joints.get(handle.0)?.as_ref()
+    }
+
+    pub fn get_joint_mut(&mut self, handle: JointHandle) -> Option<&mut Joint> {
+        self.joints.get_mut(handle.0)?.as_mut()
+    }
+
+    pub fn joints(&self) -> impl Iterator<Item = (JointHandle, &Joint)> {
+        self.joints
+            .iter()
+            .enumerate()
+            .filter_map(|(i, j)| j.as_ref().map(|j| (JointHandle(i), j)))
+    }
+
+    pub fn get_solver_settings(&self) -> &SolverSettings {
+        &self.solver_settings
+    }
+
+    pub fn set_solver_settings(&mut self, settings: SolverSettings) {
+        self.solver_settings = settings;
+    }
+
+    /// Closest body hit by the ray `origin + t * dir` within `max_distance`,
+    /// ignoring the bodies in `exclude`.
+    pub fn raycast(&self, origin: Vec3, dir: Vec3, max_distance: f32, exclude: &[BodyHandle]) -> Option<QueryHit> {
+        self.raycast_all(origin, dir, max_distance, exclude).into_iter().next()
+    }
+
+    /// Every body hit by the ray, nearest first. A zero `dir` hits nothing.
+    pub fn raycast_all(&self, origin: Vec3, dir: Vec3, max_distance: f32, exclude: &[BodyHandle]) -> Vec<QueryHit> {
+        if dir.length_squared() == 0.0 {
+            return vec![];
+        }
+        let dir = dir.normalize();
+        let mut hits = vec![];
+        self.broadphase.query_ray(origin, dir, max_distance, |proxy| {
+            let handle = *self.broadphase.get_data(proxy);
+            if let Some((body, shape)) = self.query_candidate(handle, exclude) {
+                if let Some(hit) = query::ray_shape(shape, &body.get_pose(), origin, dir, max_distance) {
+                    hits.push(hit.with_body(handle));
+                }
+            }
+            true
+        });
+        hits.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap_or(std::cmp::Ordering::Equal));
+        hits
+    }
+
+    /// Sweeps `shape` from `pose` along `dir` without rotating it and returns
+    /// the first body it would touch within `max_distance`. A zero `dir` touches nothing.
+    pub fn shape_cast(
+        &self,
+        shape: &Shape,
+        pose: &Pose,
+        dir: Vec3,
+        max_distance: f32,
+        exclude: &[BodyHandle],
+    ) -> Option<QueryHit> {
+        if dir.length_squared() == 0.0 {
+            return None;
+        }
+        let dir = dir.normalize();
+        let start = shape.get_aabb(pose);
+        let end = start.transformed(&Pose::from_position(dir * max_distance));
+        let mut best: Option<QueryHit> = None;
+        self.broadphase.query_aabb(&start.union(&end), |proxy| {
+            let handle = *self.broadphase.get_data(proxy);
+            if let Some((body, target)) = self.query_candidate(handle, exclude) {
+                let limit = best.map_or(max_distance, |hit| hit.distance);
+                if let Some(hit) = query::sweep_shape(shape, pose, dir, limit, target, &body.get_pose()) {
+                    best = Some(hit.with_body(handle));
+                }
+            }
+            true
+        });
+        best
+    }
+
+    /// Bodies touching `shape` placed at `pose`.
+    pub fn overlap(&self, shape: &Shape, pose: &Pose, exclude: &[BodyHandle]) -> Vec<BodyHandle> {
+        let mut found = vec![];
+        self.broadphase.query_aabb(&shape.get_aabb(pose), |proxy| {
+            let handle = *self.broadphase.get_data(proxy);
+            if let Some((body, other)) = self.query_candidate(handle, exclude) {
+                if !collision::collide(shape, pose, other, &body.get_pose()).is_empty() {
+                    found.push(handle);
+                }
+            }
+            true
+        });
+        found
+    }
+
+    fn query_candidate(&self, handle: BodyHandle, exclude: &[BodyHandle]) -> Option<(&RigidBody, &Shape)> {
+        if exclude.contains(&handle) {
+            return None;
+        }
+        let body = self.get_body(handle)?;
+        Some((body, body.get_shape()?))
+    }
+
+    /// Contacts found during the last step.
+    pub fn get_contacts(&self) -> &[Contact] {
+        &self.contacts
+    }
+
+    pub fn get_broadphase(&self) -> &DynamicTree<BodyHandle> {
+        &self.broadphase
+    }
+
+    pub fn bodies(&self) -> impl Iterator<Item = (BodyHandle, &RigidBody)> {
+        self.bodies
+            .iter()
+            .enumerate()
+            .filter_map(|(i, b)| b.as_ref().map(|b| (BodyHandle(i), b)))
+    }
+
+    pub fn get_gravity(&self) -> Vec3 {
+        self.gravity
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vec3) {
+        self.gravity = gravity;
+    }
+
+    pub fn get_timestep(&self) -> f32 {
+        self.timestep
+    }
+
+    pub fn set_timestep(&mut self, timestep: f32) {
+        assert!(timestep > 0.0, "timestep must be positive");
+        self.timestep = timestep;
+    }
+
+    /// Fraction of a timestep left in the accumulator, useful to interpolate
+    /// rendered transforms between the last two simulated states.
+    pub fn get_interpolation_alpha(&self) -> f32 {
+        self.accumulator / self.timestep
+    }
+
+    /// Advances the simulation by real elapsed time, running as many fixed
+    /// timesteps as fit. Returns how many steps were taken.
+    pub fn step(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed.as_secs_f32();
+
+        let mut steps = 0;
+        while self.accumulator >= self.timestep && steps < Self::MAX_SUBSTEPS {
+            self.step_fixed();
+            self.accumulator -= self.timestep;
+            steps += 1;
+        }
+
+        if steps == Self::MAX_SUBSTEPS {
+            self.accumulator = self.accumulator.min(self.timestep);
+        }
+
+        steps
+    }
+
+    /// Runs exactly one fixed timestep using semi-implicit Euler:
+    /// velocities are integrated first, contacts and joints correct them
+    /// and the corrected velocities move the bodies.
+    pub fn step_fixed(&mut self) {
+        let dt = self.timestep;
+        let gravity = self.gravity;
+
+        for body in self.bodies.iter_mut().flatten() {
+            body.integrate_velocity(dt, gravity);
+        }
+
+        self.update_broadphase();
+        self.find_contacts();
+        self.solve_constraints(dt);
+
+        for body in self.bodies.iter_mut().flatten() {
+            body.integrate_position(dt);
+            body.clear_accumulators();
+        }
+    }
+
+    /// Refits proxies of moved bodies, shapes can also be swapped between steps.
+    fn update_broadphase(&mut self) {
+        for (i, body) in self.bodies.iter().enumerate() {
+            let body = match body {
+                Some(body) => body,
+                None => continue,
+            };
+            match (self.proxies[i], body.get_aabb()) {
+                // static bodies too, they can be moved or given a new shape by hand
+                (Some(proxy), Some(aabb)) => {
+                    self.broadphase.update(proxy, aabb);
+                },
+                (Some(proxy), None) => {
+                    self.broadphase.remove(proxy);
+                    self.proxies[i] = None;
+                },
+                (None, Some(aabb)) => self.proxies[i] = Some(self.broadphase.insert(aabb, BodyHandle(i))),
+                (None, None) => {},
+            }
+        }
+    }
+
+    fn find_contacts(&mut self) {
+        self.contacts.clear();
+        for (pa, pb) in self.broadphase.query_pairs() {
+            let (ha, hb) = (*self.broadphase.get_data(pa), *self.broadphase.get_data(pb));
+            let (ha, hb) = (ha.min(hb), ha.max(hb));
+            let (a, b) = match (self.get_body(ha), self.get_body(hb)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            if a.is_static() && b.is_static() {
+                continue;
+            }
+            if self.joined_pairs.contains(&(ha, hb)) {
+                continue;
+            }
+            let (shape_a, shape_b) = match (a.get_shape(), b.get_shape()) {
+                (Some(sa), Some(sb)) => (sa, sb),
+                _ => continue,
+            };
+            for manifold in collision::collide(shape_a, &a.get_pose(), shape_b, &b.get_pose()) {
+                self.contacts.push(Contact { body_a: ha, body_b: hb, manifold });
+            }
+        }
+    }
+
+    fn solve_constraints(&mut self, dt: f32) {
+        let settings = self.solver_settings;
+        let erp = settings.baumgarte / dt;
+
+        let mut bodies: Vec<SolverBody> = self.bodies
+            .iter()
+            .map(|body| match body {
+                Some(body) => SolverBody {
+                    linear_velocity: body.get_linear_velocity(),
+                    angular_velocity: body.get_angular_velocity(),
+                    inv_mass: body.get_inv_mass(),
+                    inv_inertia: body.get_world_inv_inertia(),
+                },
+                None => SolverBody {
+                    linear_velocity: Vec3::zero(),
+                    angular_velocity: Vec3::zero(),
+                    inv_mass: 0.0,
+                    inv_inertia: Mat3::zero(),
+                },
+            })
+            .collect();
+        let mut rows = vec![];
+
+        // every contact point adds a normal row followed by two friction rows
+        for contact in &self.contacts {
+            let (ia, ib) = (contact.body_a.0, contact.body_b.0);
+            let a = self.bodies[ia].as_ref().unwrap();
+            let b = self.bodies[ib].as_ref().unwrap();
+            let normal = contact.manifold.normal;
+            let t1 = any_perpendicular(normal);
+            let t2 = normal.cross(t1);
+            let mu = (a.get_friction() * b.get_friction()).sqrt();
+            let restitution = a.get_restitution().max(b.get_restitution());
+            let cached = self.contact_cache.get(&(contact.body_a, contact.body_b));
+
+            for point in &contact.manifold.points {
+                let ra = point.position - a.get_position();
+                let rb = point.position - b.get_position();
+                let local_point = a.get_pose().inverse_transform_point(point.position);
+                let impulses = cached
+                    .and_then(|cached| cached.iter().find(|c| {
+                        (c.local_point - local_point).length_squared() < CACHE_MATCH_DISTANCE * CACHE_MATCH_DISTANCE
+                            && c.normal.dot(normal) > 0.95
+                    }))
+                    .map_or([0.0; 3], |c| c.impulses);
+
+                let approach = (b.get_point_velocity(point.position) - a.get_point_velocity(point.position)).dot(normal);
+                let mut bias = -erp * (point.depth - settings.penetration_slop).max(0.0);
+                if approach < -settings.restitution_threshold {
+                    bias = bias.min(restitution * approach);
+                }
+
+                let normal_index = rows.len();
+                rows.push(Row::point(ia, ib, normal, ra, rb)
+                    .with_bias(bias)
+                    .with_bounds(0.0, f32::MAX)
+                    .with_impulse(impulses[0]));
+                for (k, &tangent) in [t1, t2].iter().enumerate() {
+                    let mut row = Row::point(ia, ib, tangent, ra, rb).with_impulse(impulses[k + 1]);
+                    row.friction = Some((normal_index, mu));
+                    rows.push(row);
+                }
+            }
+        }
+        let contact_rows = rows.len();
+
+        let mut joint_ranges = vec![];
+        for (slot, joint) in self.joints.iter().enumerate() {
+            if let Some(joint) = joint {
+                let (ha, hb) = joint.get_bodies();
+                let a = self.bodies[ha.0].as_ref().unwrap();
+                let b = self.bodies[hb.0].as_ref().unwrap();
+                let start = rows.len();
+                let bound = joint.build_rows(a, b, ha.0, hb.0, erp, &mut rows);
+                joint_ranges.push((slot, start..rows.len(), bound));
+            }
+        }
+
+        solver::solve(&mut rows, &mut bodies, &settings);
+
+        for (body, solved) in self.bodies.iter_mut().zip(&bodies) {
+            if let Some(body) = body {
+                if !body.is_static() {
+                    body.set_linear_velocity(solved.linear_velocity);
+                    body.set_angular_velocity(solved.angular_velocity);
+                }
+            }
+        }
+
+        self.contact_cache.clear();
+        let mut contact_rows = rows[..contact_rows].chunks(3);
+        for contact in &self.contacts {
+            let a = self.bodies[contact.body_a.0].as_ref().unwrap();
+            let cached = self.contact_cache.entry((contact.body_a, contact.body_b)).or_default();
+            for (point, solved) in contact.manifold.points.iter().zip(&mut contact_rows) {
+                cached.push(CachedContact {
+                    local_point: a.get_pose().inverse_transform_point(point.position),
+                    normal: contact.manifold.normal,
+                    impulses: [solved[0].impulse, solved[1].impulse, solved[2].impulse],
+                });
+            }
+        }
+
+        for (slot, range, bound) in joint_ranges {
+            if let Some(joint) = &mut self.joints[slot] {
+                joint.store_impulses(&rows[range], bound);
+            }
+        }
+    }
+}
+
+// contact points closer than this between steps are treated as the same point
+const CACHE_MATCH_DISTANCE: f32 = 0.05;
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new(Vec3::new(0.0, -9.81, 0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::sphere_inertia;
+
+    #[test]
+    fn free_fall_matches_semi_implicit_euler() {
+        let mut world = World::default();
+        let handle = world.add_body(RigidBody::new(1.0, sphere_inertia(1.0, 1.0)));
+        world.get_body_mut(handle).unwrap().set_damping(0.0, 0.0);
+
+        for _ in 0..60 {
+            world.step_fixed();
+        }
+
+        let body = world.get_body(handle).unwrap();
+        let dt = World::DEFAULT_TIMESTEP;
+        // sum_{k=1}^{n} g * k * dt * dt
+        let expected = -9.81 * dt * dt * (60.0 * 61.0 / 2.0);
+        assert!((body.get_linear_velocity().y() + 9.81).abs() < 1e-3);
+        assert!((body.get_position().y() - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn step_accumulates_fixed_timesteps() {
+        let mut world = World::default();
+        assert_eq!(world.step(Duration::from_millis(10)), 0);
+        assert_eq!(world.step(Duration::from_millis(10)), 1);
+        assert_eq!(world.step(Duration::from_secs(10)), World::MAX_SUBSTEPS);
+        assert!(world.get_interpolation_alpha() <= 1.0);
+    }
+
+    #[test]
+    fn broadphase_finds_touching_bodies() {
+        use crate::shape::Shape;
+
+        let mut world = World::new(Vec3::zero());
+        let ground = world.add_body(RigidBody::from_shape(Shape::Box { half_extents: Vec3::new(10.0, 1.0, 10.0) }, 0.0));
+        let mut ball = RigidBody::from_shape(Shape::Sphere { radius: 0.5 }, 1.0);
+        ball.set_position(Vec3::new(0.0, 1.4, 0.0));
+        let ball = world.add_body(ball);
+        let mut far = RigidBody::from_shape(Shape::Sphere { radius: 0.5 }, 1.0);
+        far.set_position(Vec3::new(0.0, 10.0, 0.0));
+        world.add_body(far);
+
+        world.step_fixed();
+        let contacts = world.get_contacts();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!((contacts[0].body_a, contacts[0].body_b), (ground, ball));
+        assert!(contacts[0].manifold.normal.y() > 0.99);
+    }
+
+    #[test]
+    fn moved_static_bodies_are_refit() {
+        use crate::shape::Shape;
+
+        let mut world = World::new(Vec3::zero());
+        let wall = world.add_body(RigidBody::from_shape(Shape::Box { half_extents: Vec3::one() }, 0.0));
+        world.step_fixed();
+
+        world.get_body_mut(wall).unwrap().set_position(Vec3::new(20.0, 0.0, 0.0));
+        world.step_fixed();
+        assert!(world.raycast(Vec3::new(0.0, 5.0, 0.0), -Vec3::unit_y(), 10.0, &[]).is_none());
+        let hit = world.raycast(Vec3::new(20.0, 5.0, 0.0), -Vec3::unit_y(), 10.0, &[]).unwrap();
+        assert_eq!(hit.body, wall);
+
+        world.get_body_mut(wall).unwrap().set_shape(Some(Shape::Box { half_extents: Vec3::splat(10.0) }));
+        world.step_fixed();
+        assert_eq!(world.raycast(Vec3::new(12.0, 20.0, 0.0), -Vec3::unit_y(), 30.0, &[]).map(|hit| hit.body), Some(wall));
+    }
+
+    fn ground(world: &mut World) -> BodyHandle {
+        use crate::shape::Shape;
+
+        let mut ground = RigidBody::from_shape(Shape::Box { half_extents: Vec3::new(10.0, 1.0, 10.0) }, 0.0);
+        ground.set_position(Vec3::new(0.0, -1.0, 0.0));
+        world.add_body(ground)
+    }
+
+    #[test]
+    fn box_stack_comes_to_rest() {
+        use crate::shape::Shape;
+
+        let mut world = World::default();
+        ground(&mut world);
+        let boxes: Vec<_> = (0..5)
+            .map(|i| {
+                let mut body = RigidBody::from_shape(Shape::Box { half_extents: Vec3::splat(0.5) }, 1.0);
+                body.set_position(Vec3::new(0.0, 0.5 + i as f32 * 1.0, 0.0));
+                world.add_body(body)
+            })
+            .collect();
+
+        for _ in 0..300 {
+            world.step_fixed();
+        }
+
+        for (i, &handle) in boxes.iter().enumerate() {
+            let body = world.get_body(handle).unwrap();
+            let expected = 0.5 + i as f32;
+            assert!((body.get_position().y() - expected).abs() < 0.05, "box {} at {:?}", i, body.get_position());
+            assert!(body.get_position().x().abs() < 0.02 && body.get_position().z().abs() < 0.02, "box {} drifted to {:?}", i, body.get_position());
+            assert!(body.get_linear_velocity().length() < 0.05);
+        }
+    }
+
+    #[test]
+    fn restitution_makes_balls_bounce() {
+        use crate::shape::Shape;
+
+        let mut world = World::default();
+        ground(&mut world);
+        let drop = |world: &mut World, x: f32, restitution: f32| {
+            let mut ball = RigidBody::from_shape(Shape::Sphere { radius: 0.5 }, 1.0);
+            ball.set_position(Vec3::new(x, 3.0, 0.0));
+            ball.set_restitution(restitution);
+            world.add_body(ball)
+        };
+        let bouncy = drop(&mut world, -2.0, 0.8);
+        let dead = drop(&mut world, 2.0, 0.0);
+
+        let mut bouncy_peak = f32::MIN;
+        let mut dead_peak = f32::MIN;
+        let mut landed = false;
+        for _ in 0..180 {
+            world.step_fixed();
+            let vy = world.get_body(bouncy).unwrap().get_linear_velocity().y();
+            landed |= vy > 0.0;
+            if landed {
+                bouncy_peak = bouncy_peak.max(world.get_body(bouncy).unwrap().get_position().y());
+                dead_peak = dead_peak.max(world.get_body(dead).unwrap().get_position().y());
+            }
+        }
+
+        // falling 2.5m and bouncing back with 0.8 of the speed reaches 0.64 of the height
+        assert!(bouncy_peak > 0.5 + 2.5 * 0.5, "bouncy ball peaked at {}", bouncy_peak);
+        assert!(dead_peak < 0.6, "dead ball peaked at {}", dead_peak);
+    }
+
+    #[test]
+    fn ball_socket_pendulum_keeps_its_length() {
+        let mut world = World::default();
+        let pivot = world.add_body(RigidBody::new_static());
+        let mut bob = RigidBody::new(1.0, sphere_inertia(1.0, 0.2));
+        bob.set_position(Vec3::new(2.0, 0.0, 0.0));
+        let bob = world.add_body(bob);
+        world.add_ball_socket(pivot, bob, Vec3::zero()).unwrap();
+
+        let mut lowest = f32::MAX;
+        for _ in 0..240 {
+            world.step_fixed();
+            let pos = world.get_body(bob).unwrap().get_position();
+            lowest = lowest.min(pos.y());
+            assert!((pos.length() - 2.0).abs() < 0.05, "pendulum stretched to {}", pos.length());
+        }
+        assert!(lowest < -1.9);
+    }
+
+    #[test]
+    fn hinge_only_rotates_around_its_axis() {
+        let mut world = World::new(Vec3::zero());
+        let frame = world.add_body(RigidBody::new_static());
+        let door = world.add_body(RigidBody::new(1.0, crate::body::box_inertia(1.0, Vec3::new(0.5, 1.0, 0.05))));
+        let hinge = world.add_hinge(frame, door, Vec3::new(-0.5, 0.0, 0.0), Vec3::unit_y()).unwrap();
+        world.get_joint_mut(hinge).unwrap().set_hinge_limits(Some((-0.5, 0.5)));
+
+        // spin the door around the hinge and knock it off axis at the same time
+        world.get_body_mut(door).unwrap().set_angular_velocity(Vec3::new(2.0, 3.0, 1.0));
+        for _ in 0..120 {
+            world.step_fixed();
+            let body = world.get_body(door).unwrap();
+            let up = body.get_orientation().mul_vec3(Vec3::unit_y());
+            assert!(up.y() > 0.99, "hinge axis tilted to {:?}", up);
+            let angle = world.get_joint(hinge).unwrap().get_hinge_angle(world.get_body(frame).unwrap(), body).unwrap();
+            assert!(angle.abs() < 0.6, "hinge angle {} past limit", angle);
+        }
+        let body = world.get_body(door).unwrap();
+        let anchor = body.get_position() + body.get_orientation().mul_vec3(Vec3::new(-0.5, 0.0, 0.0));
+        assert!((anchor - Vec3::new(-0.5, 0.0, 0.0)).length() < 0.02);
+    }
+
+    #[test]
+    fn distance_joint_respects_max() {
+        let mut world = World::default();
+        let anchor = world.add_body(RigidBody::new_static());
+        let mut weight = RigidBody::new(1.0, sphere_inertia(1.0, 0.2));
+        weight.set_position(Vec3::new(0.0, -1.0, 0.0));
+        let weight = world.add_body(weight);
+        world.add_distance(anchor, weight, Vec3::zero(), Vec3::new(0.0, -1.0, 0.0), 0.5, 2.0).unwrap();
+
+        for _ in 0..120 {
+            world.step_fixed();
+        }
+        let y = world.get_body(weight).unwrap().get_position().y();
+        assert!((y + 2.0).abs() < 0.05, "rope hangs at {}", y);
+    }
+
+    #[test]
+    fn limit_impulse_is_dropped_when_the_bound_flips() {
+        let mut world = World::default();
+        let anchor = world.add_body(RigidBody::new_static());
+        let mut weight = RigidBody::new(1.0, sphere_inertia(1.0, 0.2));
+        weight.set_position(Vec3::new(0.0, -0.2, 0.0));
+        let weight = world.add_body(weight);
+        let joint = world.add_distance(anchor, weight, Vec3::zero(), Vec3::new(0.0, -0.2, 0.0), 0.5, 2.0).unwrap();
+
+        let build = |world: &World| {
+            let mut rows = vec![];
+            let (a, b) = (world.get_body(anchor).unwrap(), world.get_body(weight).unwrap());
+            let bound = world.get_joint(joint).unwrap().build_rows(a, b, 0, 1, 1.0, &mut rows);
+            (rows, bound)
+        };
+
+        // too short, pushed apart
+        let (mut rows, bound) = build(&world);
+        rows[0].impulse = 3.0;
+        world.get_joint_mut(joint).unwrap().store_impulses(&rows, bound);
+        assert_eq!(build(&world).0[0].impulse, 3.0);
+
+        // too long now, the push from before must not carry over into the pull
+        world.get_body_mut(weight).unwrap().set_position(Vec3::new(0.0, -3.0, 0.0));
+        let (rows, flipped) = build(&world);
+        assert_ne!(flipped, bound);
+        assert_eq!(rows[0].impulse, 0.0);
+    }
+
+    #[test]
+    fn joined_bodies_dont_collide() {
+        use crate::shape::Shape;
+
+        let mut world = World::new(Vec3::zero());
+        let a = world.add_body(RigidBody::from_shape(Shape::Sphere { radius: 0.5 }, 1.0));
+        let mut b = RigidBody::from_shape(Shape::Sphere { radius: 0.5 }, 1.0);
+        b.set_position(Vec3::new(0.5, 0.0, 0.0));
+        let b = world.add_body(b);
+        let first = world.add_ball_socket(a, b, Vec3::new(0.25, 0.0, 0.0)).unwrap();
+        let second = world.add_ball_socket(b, a, Vec3::new(0.25, 0.0, 0.0)).unwrap();
+
+        world.step_fixed();
+        assert!(world.get_contacts().is_empty());
+        // still held by the other joint
+        world.remove_joint(first);
+        world.step_fixed();
+        assert!(world.get_contacts().is_empty());
+        world.remove_joint(second);
+        world.step_fixed();
+        assert_eq!(world.get_contacts().len(), 1);
+    }
+
+    #[test]
+    fn fixed_and_slider_joints_lock_motion() {
+        let mut world = World::default();
+        let base = world.add_body(RigidBody::new_static());
+        let mut glued = RigidBody::new(1.0, sphere_inertia(1.0, 0.5));
+        glued.set_position(Vec3::new(1.0, 0.0, 0.0));
+        let glued = world.add_body(glued);
+        world.add_fixed(base, glued).unwrap();
+        let mut rail = RigidBody::new(1.0, sphere_inertia(1.0, 0.5));
+        rail.set_position(Vec3::new(-1.0, 0.0, 0.0));
+        let rail = world.add_body(rail);
+        world.add_slider(base, rail, Vec3::new(1.0, -1.0, 0.0)).unwrap();
+
+        for _ in 0..60 {
+            world.step_fixed();
+        }
+        let glued = world.get_body(glued).unwrap();
+        assert!((glued.get_position() - Vec3::new(1.0, 0.0, 0.0)).length() < 0.05);
+        let rail = world.get_body(rail).unwrap();
+        let offset = rail.get_position() - Vec3::new(-1.0, 0.0, 0.0);
+        // slid down the diagonal rail without leaving it
+        assert!(offset.y() < -1.0);
+        assert!((offset.x() + offset.y()).abs() < 0.05 && offset.z().abs() < 0.05);
+        assert!(rail.get_angular_velocity().length() < 1e-3);
+    }
+
+    #[test]
+    fn removed_slots_are_reused() {
+        let mut world = World::default();
+        let a = world.add_body(RigidBody::new_static());
+        let _b = world.add_body(RigidBody::new_static());
+        assert!(world.remove_body(a).is_some());
+        assert!(world.get_body(a).is_none());
+        assert_eq!(world.add_body(RigidBody::new_static()), a);
+        assert_eq!(world.bodies().count(), 2);
+    }
+}
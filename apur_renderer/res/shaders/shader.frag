@@ -2,16 +2,227 @@
 
 layout(location = 0) in vec2 f_tex_coords;
 layout(location = 1) in vec3 f_normal;
+layout(location = 2) in vec3 f_to_camera;
+layout(location = 3) in vec4 f_tangent;
+layout(location = 4) in vec3 f_world_position;
 
 layout(location = 0) out vec4 out_color;
 
 layout(set = 0, binding = 1) uniform sampler s_albedo;
 layout(set = 1, binding = 0) uniform texture2D t_albedo;
-layout(set = 0, binding = 2) uniform Light {
+layout(set = 1, binding = 1) uniform Material {
+    vec4 diffuse;
+    vec4 ambient;
+    vec4 specular;
+    // 1.0 when the diffuse, normal, specular and bump maps are bound
+    vec4 maps;
+    float alpha_map;
+    // exponent of the Blinn-Phong highlight
+    float shininess;
+    float bump_scale;
+    // zero unless the material is alpha masked
+    float alpha_cutoff;
+    float metallic;
+    float roughness;
+    float metallic_roughness_map;
+} material;
+layout(set = 1, binding = 2) uniform texture2D t_normal;
+layout(set = 1, binding = 3) uniform texture2D t_specular;
+layout(set = 1, binding = 4) uniform texture2D t_bump;
+layout(set = 1, binding = 5) uniform texture2D t_alpha;
+// metalness in blue, roughness in green
+layout(set = 1, binding = 6) uniform texture2D t_metallic_roughness;
+struct Light {
+    vec3 position;
+    // point and spot lights reach zero here, unbounded when zero
+    float range;
+    // towards the light for directional lights, away from it for spot lights
     vec3 direction;
-} light;
+    uint kind;
+    // premultiplied by the intensity
+    vec3 color;
+    float cos_inner;
+    float cos_outer;
+};
+layout(set = 0, binding = 2) readonly buffer Lights {
+    uint light_count;
+    Light lights[];
+};
+layout(set = 0, binding = 3) uniform Shading {
+    // 0 for Cook-Torrance, 1 for the Blinn-Phong fallback
+    uint model;
+} shading;
+
+// image based lighting, all of it prefiltered from the sky
+layout(set = 2, binding = 0) uniform sampler s_environment;
+layout(set = 2, binding = 1) uniform textureCube t_irradiance;
+layout(set = 2, binding = 2) uniform textureCube t_prefiltered;
+layout(set = 2, binding = 3) uniform texture2D t_brdf_lut;
+layout(set = 2, binding = 4) uniform Environment {
+    float exposure;
+    // mip level of the fully rough reflections
+    float specular_max_lod;
+} environment;
+
+const float PI = 3.14159265;
+const uint LIGHT_DIRECTIONAL = 0;
+const uint LIGHT_SPOT = 2;
+// perfectly smooth surfaces would turn lights into infinitely small highlights
+const float MIN_ROUGHNESS = 0.05;
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a2 = pow(roughness, 4.0);
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's masking and shadowing with Schlick's approximation, k as for punctual lights
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// light reflected towards v from a light in direction l, whatever the specular lobe reflects
+// is taken away from the diffuse one so the two never add up to more than came in
+vec3 cook_torrance(vec3 n, vec3 v, vec3 l, vec3 radiance, vec3 diffuse_color, vec3 f0, float roughness) {
+    float n_dot_l = max(dot(n, l), 0.0);
+    if (n_dot_l <= 0.0) {
+        return vec3(0.0);
+    }
+    vec3 h = normalize(l + v);
+    float n_dot_v = max(dot(n, v), 1e-4);
+    vec3 f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    float d = distribution_ggx(max(dot(n, h), 0.0), roughness);
+    float g = geometry_smith(n_dot_v, n_dot_l, roughness);
+    vec3 specular = d * g * f / (4.0 * n_dot_v * n_dot_l);
+    vec3 diffuse = (1.0 - f) * diffuse_color / PI;
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+vec3 blinn_phong(vec3 n, vec3 v, vec3 l, vec3 radiance, vec3 diffuse_color, vec3 specular, float shininess) {
+    float n_dot_l = max(dot(n, l), 0.0);
+    if (n_dot_l <= 0.0) {
+        return vec3(0.0);
+    }
+    float highlight = pow(max(dot(n, normalize(l + v)), 0.0), shininess);
+    return (n_dot_l * diffuse_color + highlight * specular) * radiance / PI;
+}
+
+// radiance arriving at the surface from a light, and the direction towards it in l
+vec3 light_radiance(Light light, out vec3 l) {
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        l = light.direction;
+        return light.color;
+    }
+    vec3 to_light = light.position - f_world_position;
+    float distance2 = dot(to_light, to_light);
+    l = to_light * inversesqrt(max(distance2, 1e-8));
+    // inverse square falloff, windowed so it smoothly reaches zero at the range
+    float attenuation = 1.0 / max(distance2, 1e-4);
+    if (light.range > 0.0) {
+        float ratio2 = distance2 / (light.range * light.range);
+        float window = clamp(1.0 - ratio2 * ratio2, 0.0, 1.0);
+        attenuation *= window * window;
+    }
+    if (light.kind == LIGHT_SPOT) {
+        float cos_angle = dot(-l, light.direction);
+        attenuation *= smoothstep(light.cos_outer, light.cos_inner, cos_angle);
+    }
+    return light.color * attenuation;
+}
+
+// MikkTSpace frame, the bitangent is rebuilt per pixel from the interpolated vectors
+mat3 tangent_frame(vec3 n) {
+    vec3 t = normalize(f_tangent.xyz - n * dot(n, f_tangent.xyz));
+    vec3 b = f_tangent.w * cross(n, t);
+    return mat3(t, b, n);
+}
+
+// Mikkelsen's bump mapping of unparametrized surfaces
+vec3 bump_normal(vec3 n, vec3 p, float height) {
+    vec3 dpdx = dFdx(p);
+    vec3 dpdy = dFdy(p);
+    vec3 r1 = cross(dpdy, n);
+    vec3 r2 = cross(n, dpdx);
+    float det = dot(dpdx, r1);
+    vec3 grad = sign(det) * (dFdx(height) * r1 + dFdy(height) * r2);
+    return normalize(abs(det) * n - grad);
+}
 
 void main() {
-    // we assume a white light
-    out_color = max(0.1, dot(f_normal, light.direction)) * texture(sampler2D(t_albedo, s_albedo), f_tex_coords);
+    vec4 albedo_sample = texture(sampler2D(t_albedo, s_albedo), f_tex_coords);
+    vec3 albedo = material.maps.x > 0.5 ? albedo_sample.rgb : material.diffuse.rgb;
+    float alpha = material.diffuse.a * (material.maps.x > 0.5 ? albedo_sample.a : 1.0);
+    if (material.alpha_map > 0.5) {
+        alpha *= texture(sampler2D(t_alpha, s_albedo), f_tex_coords).r;
+    }
+    if (alpha < material.alpha_cutoff) {
+        discard;
+    }
+
+    // the camera doesn't move within a draw, so this has the same derivatives as the world position
+    vec3 p = -f_to_camera;
+    vec3 n = normalize(f_normal);
+    if (material.maps.y > 0.5) {
+        vec3 tangent_normal = texture(sampler2D(t_normal, s_albedo), f_tex_coords).xyz * 2.0 - 1.0;
+        tangent_normal.xy *= material.bump_scale;
+        n = normalize(tangent_frame(n) * tangent_normal);
+    } else if (material.maps.w > 0.5) {
+        float height = texture(sampler2D(t_bump, s_albedo), f_tex_coords).r * material.bump_scale;
+        n = bump_normal(n, p, height);
+    }
+
+    vec3 specular_map = vec3(1.0);
+    if (material.maps.z > 0.5) {
+        specular_map = texture(sampler2D(t_specular, s_albedo), f_tex_coords).rgb;
+    }
+
+    // the surface as both models see it, the fallback maps its exponent onto a roughness
+    // so the sky's reflections still match the highlight
+    vec3 diffuse_color;
+    vec3 f0;
+    float roughness;
+    if (shading.model == 0) {
+        float metallic = material.metallic;
+        roughness = material.roughness;
+        if (material.metallic_roughness_map > 0.5) {
+            vec4 metallic_roughness = texture(sampler2D(t_metallic_roughness, s_albedo), f_tex_coords);
+            metallic *= metallic_roughness.b;
+            roughness *= metallic_roughness.g;
+        }
+        // dielectrics reflect about 4%, which the specular map scales, metals tint their reflections
+        f0 = mix(vec3(0.04) * specular_map, albedo, metallic);
+        diffuse_color = albedo * (1.0 - metallic);
+        roughness = max(roughness, MIN_ROUGHNESS);
+    } else {
+        f0 = material.specular.rgb * specular_map;
+        diffuse_color = albedo;
+        roughness = sqrt(sqrt(2.0 / (material.shininess + 2.0)));
+    }
+
+    vec3 v = normalize(f_to_camera);
+    vec3 direct = vec3(0.0);
+    for (uint i = 0; i < light_count; i++) {
+        vec3 l;
+        vec3 radiance = light_radiance(lights[i], l);
+        direct += shading.model == 0
+            ? cook_torrance(n, v, l, radiance, diffuse_color, f0, roughness)
+            : blinn_phong(n, v, l, radiance, diffuse_color, f0, material.shininess);
+    }
+
+    // light from the sky
+    float n_dot_v = max(dot(n, v), 0.0);
+    vec3 fresnel = f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+    vec3 irradiance = texture(samplerCube(t_irradiance, s_environment), n).rgb;
+    float lod = roughness * environment.specular_max_lod;
+    vec3 reflected = textureLod(samplerCube(t_prefiltered, s_environment), reflect(-v, n), lod).rgb;
+    vec2 brdf = texture(sampler2D(t_brdf_lut, s_environment), vec2(n_dot_v, roughness)).rg;
+    vec3 ambient = environment.exposure * ((1.0 - fresnel) * diffuse_color * irradiance + reflected * (f0 * brdf.x + brdf.y));
+
+    vec3 color = ambient + direct;
+    out_color = vec4(color, alpha);
 }
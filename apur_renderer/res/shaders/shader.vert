@@ -3,9 +3,19 @@
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec2 tex_coords;
 layout(location = 2) in vec3 normal;
+// per instance model matrix, one column per attribute
+layout(location = 3) in vec4 model_0;
+layout(location = 4) in vec4 model_1;
+layout(location = 5) in vec4 model_2;
+layout(location = 6) in vec4 model_3;
+// w is the sign of the bitangent
+layout(location = 7) in vec4 tangent;
 
 layout(location = 0) out vec2 f_tex_coords;
 layout(location = 1) out vec3 f_normal;
+layout(location = 2) out vec3 f_to_camera;
+layout(location = 3) out vec4 f_tangent;
+layout(location = 4) out vec3 f_world_position;
 
 layout(set = 0, binding = 0) uniform Transforms {
     mat4 view;
@@ -13,13 +23,19 @@ layout(set = 0, binding = 0) uniform Transforms {
 };
 
 void main() {
-    f_normal = normal;
+    mat4 model = mat4(model_0, model_1, model_2, model_3);
+    f_normal = normalize(transpose(inverse(mat3(model))) * normal);
+    f_tangent = vec4(normalize(mat3(model) * tangent.xyz), tangent.w);
     
     f_tex_coords = tex_coords;
     f_tex_coords.y = 1.0 - f_tex_coords.y;
     
-    // sponza was too big so we hardcode scale down by 100x
-    gl_Position = proj * view * vec4(position / 100.0, 1.0);
+    vec4 world_position = model * vec4(position, 1.0);
+    vec3 camera_position = inverse(view)[3].xyz;
+    f_to_camera = camera_position - world_position.xyz;
+    f_world_position = world_position.xyz;
+
+    gl_Position = proj * view * world_position;
     gl_Position.y = -gl_Position.y;
     gl_Position.z = (gl_Position.z + gl_Position.w) / 2.0;
 }
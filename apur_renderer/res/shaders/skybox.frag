@@ -7,6 +7,12 @@ layout(location = 0) out vec4 out_color;
 layout(set = 0, binding = 0) uniform sampler s_cubemap;
 layout(set = 0, binding = 1) uniform textureCube t_cubemap;
 
+layout(set = 0, binding = 3) uniform Sky {
+    // linear scale, 1.0 shows the cubemap as it is
+    float exposure;
+};
+
 void main() {
-    out_color = texture(samplerCube(t_cubemap, s_cubemap), f_tex_coords);
+    vec3 color = texture(samplerCube(t_cubemap, s_cubemap), f_tex_coords).rgb;
+    out_color = vec4(color * exposure, 1.0);
 }
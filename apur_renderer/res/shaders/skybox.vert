@@ -10,10 +10,14 @@ layout(set = 0, binding = 2) uniform Transforms {
 };
 
 void main() {
-    f_tex_coords = position * vec3(1.0, -1.0, 1.0);
+    f_tex_coords = position;
     // we ignore the camera translation because we treat the position
     // of the cube in cam position but not the rotation
     mat4 fixed_view = view;
     fixed_view[3] = vec4(0.0, 0.0, 0.0, view[3][3]);
-    gl_Position = proj * fixed_view * vec4(position, 1.0);
+    vec4 clip_pos = proj * fixed_view * vec4(position, 1.0);
+    // z = w puts the cube on the far plane, behind everything the scene drew
+    gl_Position = clip_pos.xyww;
+    // same flip as the scene, vulkan's clip space y points down
+    gl_Position.y = -gl_Position.y;
 }
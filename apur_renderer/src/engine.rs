@@ -1,12 +1,32 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
 use winit::window::Window;
+use winit::dpi::PhysicalSize;
+
+use glam::{Quat, Vec3};
+use apur_physics::{CharacterController, QueryHit, RigidBody, Shape};
 
+mod assets;
 mod camera;
+mod environment;
+mod light;
+mod loader;
 mod model;
 mod renderer;
+mod scene;
+mod vfs;
 
-use model::{Model};
+use assets::{AssetManager, Handle};
+use light::{Light, LightId, Lights};
+use loader::{AssetLoader, Loaded};
+use model::{LoadReport, Model, ModelData, TextureData};
 use camera::{Camera, Frustum};
-use renderer::{Renderer, RenderData};
+use renderer::{Renderer, RenderData, ShadingModel, SkyBoxRenderer};
+use scene::{NodeId, SceneGraph, Transform};
+use vfs::Vfs;
 
 pub struct Engine {
     device: wgpu::Device,
@@ -15,16 +35,109 @@ pub struct Engine {
     depth_texture_view: wgpu::TextureView,
     render_data: RenderData,
     renderer: Renderer,
+    skybox: SkyBoxRenderer,
+    /// In stops, 0 shows the sky as it is.
+    sky_exposure: f32,
+    assets: AssetManager,
     update_mats: bool,
     camera: Camera,
     frustum: Frustum,
+    physics: apur_physics::World,
+    scene: SceneGraph,
+    /// Spun a little every frame, the eyeball orbits along as its child.
+    earth: Option<NodeId>,
+    lights: Lights,
+    /// Spot light following the camera while it's switched on.
+    flashlight: Option<LightId>,
+    window_size: PhysicalSize<u32>,
+    controller: CharacterController,
+    move_input: MoveInput,
+    fly_mode: bool,
+    /// The level's collision mesh never came, walking would just fall forever.
+    no_ground: bool,
+    loader: AssetLoader,
+    /// Last progress printed while streaming in assets.
+    load_percent: usize,
+    /// Names of models still streaming in, by model index.
+    loading_models: HashMap<usize, String>,
+    /// Models whose collision mesh becomes static collision geometry once loaded.
+    pending_colliders: HashSet<usize>,
+}
+
+/// Writes the mesh cache of every .obj under `dir`, so they don't have to be parsed on
+/// the first launch either. Returns how many couldn't be baked.
+pub fn bake_models(dir: &Path) -> usize {
+    let mut vfs = Vfs::new();
+    vfs.mount_dir("models", dir);
+    let mut names = vec![];
+    find_objs(dir, "", &mut names);
+    names.sort();
+
+    let mut failed = 0;
+    for name in &names {
+        match model::bake_obj(&vfs, name) {
+            Ok(cache) => println!("[Info] Baked {}", cache.display()),
+            Err(err) => {
+                println!("[Error] Failed to bake {}: {}", name, err);
+                failed += 1;
+            },
+        }
+    }
+    println!("[Info] Baked {} of {} models", names.len() - failed, names.len());
+    failed
+}
+
+/// Paths of the .objs under `dir` without the extension, relative to where the search started.
+fn find_objs(dir: &Path, prefix: &str, names: &mut Vec<String>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            println!("[Error] Failed to read {}: {}", dir.display(), err);
+            return;
+        },
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        if path.is_dir() {
+            find_objs(&path, &format!("{}{}/", prefix, file_name), names);
+        } else if let Some(stem) = file_name.strip_suffix(".obj") {
+            names.push(format!("{}{}", prefix, stem));
+        }
+    }
+}
+
+/// Movement keys currently held down.
+#[derive(Default)]
+struct MoveInput {
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
 }
 
 impl Engine {
 
-    const CAMERA_SPEED: f32 = 0.1;
+    const FLY_SPEED: f32 = 3.0;
+    const WALK_SPEED: f32 = 2.5;
+    const CHARACTER_HALF_HEIGHT: f32 = 0.6;
+    const CHARACTER_RADIUS: f32 = 0.3;
+    // measured from the feet
+    const EYE_HEIGHT: f32 = 1.6;
+    // long hitches would otherwise make the character skip through thin walls
+    const MAX_FRAME_TIME: f32 = 0.1;
+    // sponza is modelled in centimeters
+    const SCENE_SCALE: f32 = 0.01;
+    const PICK_DISTANCE: f32 = 100.0;
+    // loaded models and textures uploaded per frame, keeps big batches from stalling a frame
+    const MAX_UPLOADS_PER_FRAME: usize = 4;
+    // radians per second
+    const EARTH_SPIN: f32 = 0.3;
+    // see AssetManager::environment for what names can be
+    const DEFAULT_SKYBOX: &str = "tm";
     
-    pub fn new(window: &Window) -> Self {
+    /// `skybox` picks the sky, either six faces models/textures/<name>_{px,nx,py,ny,pz,nz}.jpg
+    /// or an .hdr or .exr panorama models/textures/<name>. `sky_exposure` brightens it in stops.
+    pub fn new(window: &Window, skybox: Option<&str>, sky_exposure: f32) -> Self {
         let window_width = window.inner_size().width;
         let window_height = window.inner_size().height;
         
@@ -48,6 +161,8 @@ impl Engine {
 
         let mut camera = Camera::default();
         camera.move_pos(-5.0);
+        // stand on the floor instead of having the eye in it
+        camera.set_position(camera.get_position() + Vec3::new(0.0, Self::EYE_HEIGHT, 0.0));
         let frustum = Frustum::new(window_width, window_height);
         
         let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
@@ -62,28 +177,226 @@ impl Engine {
 
         let depth_texture_view = depth_texture.create_default_view();
 
-        let renderer = Renderer::new(&device);
-        let model = Model::load_model(&device, &mut queue, "sponza");
+        let vfs = Arc::new(Vfs::with_default_mounts());
+        let mut assets = AssetManager::new(&device, &mut queue, Arc::clone(&vfs));
+        let renderer = Renderer::new(&device, &mut assets);
         let render_data = RenderData::new(
             &device,
-            model,
             camera.view(),
             frustum.projection(),
             renderer.get_bind_group_layout(),
-            renderer.get_texture_bind_group_layout()
+        );
+        let skybox = SkyBoxRenderer::new(
+            &device,
+            renderer.get_environment_bind_group_layout(),
+            skybox.unwrap_or(Self::DEFAULT_SKYBOX),
+            &mut queue,
+            &mut assets,
+            camera.view(),
+            frustum.projection(),
+            sky_exposure.exp2(),
         );
 
-        Self {
+        let mut engine = Self {
             device,
             queue,
             swapchain,
             depth_texture_view,
             render_data,
             renderer,
+            skybox,
+            sky_exposure,
+            assets,
             camera,
             frustum,
             update_mats: false,
+            physics: apur_physics::World::default(),
+            scene: SceneGraph::new(),
+            earth: None,
+            lights: Lights::new(),
+            flashlight: None,
+            window_size: window.inner_size(),
+            controller: CharacterController::new(Vec3::zero(), Self::CHARACTER_HALF_HEIGHT, Self::CHARACTER_RADIUS),
+            move_input: MoveInput::default(),
+            fly_mode: false,
+            no_ground: false,
+            loader: AssetLoader::new(vfs),
+            load_percent: 0,
+            loading_models: HashMap::new(),
+            pending_colliders: HashSet::new(),
+        };
+        engine.place_character_at_camera();
+        engine.load_default_scene();
+        engine
+    }
+
+    /// Index of a model that is loaded or still streaming in.
+    fn find_model(&self, name: &str) -> Option<usize> {
+        let loading = self.loading_models.iter().find(|(_, n)| n.as_str() == name).map(|(&idx, _)| idx);
+        loading.or_else(|| self.assets.get_model(name).and_then(|model| self.render_data.find_model(&model)))
+    }
+
+    fn print_load_report(name: &str, report: &LoadReport) {
+        for (texture, reason) in &report.substituted_textures {
+            println!("[Info] {}: using a checkerboard for texture {} ({})", name, texture, reason);
+        }
+        for (mesh, reason) in &report.skipped_meshes {
+            println!("[Info] {}: skipped mesh {} ({})", name, mesh, reason);
+        }
+    }
+
+    /// Loads a model from models/ in the asset `Vfs`, the returned index is what scene nodes refer to.
+    /// Loading a model that is already loaded or loading gives back the same index.
+    /// Parsing and decoding happen on the loader threads, so the model shows up empty until
+    /// its meshes arrive, then with placeholder textures until those do.
+    pub fn load_model_async(&mut self, name: &str) -> usize {
+        match self.find_model(name) {
+            Some(idx) => idx,
+            None => self.queue_model(name, None),
+        }
+    }
+
+    /// Like `load_model_async`, the model's triangles scaled by `scale` also become static
+    /// collision geometry. Always loads a fresh copy, the collision mesh is built along with it.
+    pub fn load_level_async(&mut self, name: &str, scale: f32) -> usize {
+        let idx = self.queue_model(name, Some(scale));
+        self.pending_colliders.insert(idx);
+        idx
+    }
+
+    fn queue_model(&mut self, name: &str, collider_scale: Option<f32>) -> usize {
+        let empty = Model::from_data(&self.device, &mut self.queue, ModelData::default(), &mut self.assets);
+        let idx = self.render_data.add_model(&self.device, Handle::new(empty), self.renderer.get_texture_bind_group_layout());
+        self.loader.load_model(idx, name, collider_scale);
+        self.loading_models.insert(idx, name.to_string());
+        idx
+    }
+
+    /// Uploads whatever the loader threads finished since the last frame.
+    fn poll_loader(&mut self) {
+        let loaded = self.loader.poll(Self::MAX_UPLOADS_PER_FRAME);
+        if loaded.is_empty() {
+            return;
+        }
+        let layout = self.renderer.get_texture_bind_group_layout();
+        // the level whose collider never arrives, handled once `layout` is done with
+        let mut groundless = None;
+        for item in loaded {
+            match item {
+                Loaded::Model { id, name, result: Ok(mut data) } => {
+                    if self.pending_colliders.remove(&id) {
+                        match data.collision_mesh.take() {
+                            Some(mesh) => {
+                                self.physics.add_body(RigidBody::from_shape(Shape::trimesh(mesh), 0.0));
+                            },
+                            None => groundless = Some(name.clone()),
+                        }
+                    }
+                    let pending = data.take_pending_textures();
+                    let color_spaces = data.color_spaces();
+                    let model = Model::from_data(&self.device, &mut self.queue, *data, &mut self.assets);
+                    Self::print_load_report(&name, model.get_load_report());
+                    let model = self.assets.add_model(&name, model);
+                    self.render_data.replace_model(&self.device, id, model, layout);
+                    for request in pending {
+                        let color_space = color_spaces[request.index];
+                        let key = match request.cache_key() {
+                            Some(path) => (path, color_space),
+                            None => {
+                                self.loader.load_texture(id, request, color_space);
+                                continue;
+                            },
+                        };
+                        // textures shared with other models are only decoded and uploaded once
+                        if let Some(texture) = self.assets.get_texture(&key) {
+                            self.render_data.replace_texture(&self.device, id, request.index, texture, layout);
+                        } else if self.assets.begin_texture_load(key, id, request.index) {
+                            self.loader.load_texture(id, request, color_space);
+                        }
+                    }
+                },
+                Loaded::Model { id, name, result: Err(err) } => {
+                    println!("[Error] Failed to load model {}: {}", name, err);
+                    if self.pending_colliders.remove(&id) {
+                        groundless = Some(name);
+                    }
+                },
+                Loaded::Texture { id, index, name, path, color_space, result } => {
+                    let mip_chain = match result {
+                        Ok(mip_chain) => mip_chain,
+                        Err(reason) => {
+                            let model = self.loading_models.get(&id).map_or("model", String::as_str);
+                            println!("[Info] {}: using a checkerboard for texture {} ({})", model, name, reason);
+                            let checkerboard = TextureData::checkerboard(&name);
+                            let mips = checkerboard.mips(color_space);
+                            std::iter::once(checkerboard).chain(mips).collect()
+                        },
+                    };
+                    let waiting = match &path {
+                        Some(path) => self.assets.finish_texture_load(&(path.clone(), color_space)),
+                        None => vec![(id, index)],
+                    };
+                    let texture = self.assets.upload_texture(&self.device, &mut self.queue, path, &mip_chain, color_space);
+                    for (model, index) in waiting {
+                        self.render_data.replace_texture(&self.device, model, index, texture.clone(), layout);
+                    }
+                },
+            }
+        }
+
+        if let Some(name) = groundless {
+            self.lose_ground(&name);
+        }
+
+        let (finished, queued) = self.loader.get_progress();
+        if self.loader.is_idle() {
+            println!("[Info] Finished loading assets ({} jobs)", queued);
+            self.loading_models.clear();
+            self.load_percent = 0;
+        } else {
+            // models queue their textures as they come in so this can go back down
+            let percent = 100 * finished / queued;
+            if percent != self.load_percent {
+                self.load_percent = percent;
+                println!("[Info] Loading assets: {}%", percent);
+            }
+        }
+    }
+
+    fn load_default_scene(&mut self) {
+        let sponza = self.load_level_async("sponza", Self::SCENE_SCALE);
+        let teapot = self.load_model_async("teapot");
+        let bunny = self.load_model_async("bunny");
+        let earth = self.load_model_async("earth");
+        let eyeball = self.load_model_async("eyeball");
+
+        let scaled = |translation: Vec3, scale: f32| Transform { translation, scale: Vec3::splat(scale), ..Transform::default() };
+        let scene = &mut self.scene;
+        // models that fail to load just stay empty
+        scene.add_node(None, scaled(Vec3::zero(), Self::SCENE_SCALE), Some(sponza));
+        for &x in &[-2.0, 2.0] {
+            scene.add_node(None, scaled(Vec3::new(x, 0.0, 0.0), 0.15), Some(teapot));
         }
+        scene.add_node(None, scaled(Vec3::new(0.0, 0.35, 0.0), 2.0), Some(bunny));
+        let earth = scene.add_node(None, scaled(Vec3::new(0.0, 3.0, -3.0), 0.003), Some(earth));
+        // local to the earth, so it is carried along with it
+        scene.add_node(Some(earth), scaled(Vec3::new(600.0, 0.0, 0.0), 40.0), Some(eyeball));
+        self.earth = Some(earth);
+
+        // as bright as the single fixed light this replaced
+        self.lights.add(Light::directional(Vec3::new(0.0, -1.0, -1.0), Vec3::one(), std::f32::consts::PI));
+        for &x in &[-2.0, 2.0] {
+            self.lights.add(Light::point(Vec3::new(x, 1.0, 0.0), Vec3::new(1.0, 0.6, 0.3), 2.0, 6.0));
+        }
+        self.lights.add(Light::spot(
+            Vec3::new(0.0, 3.0, 1.5),
+            Vec3::new(0.0, -1.0, -0.5),
+            Vec3::new(0.6, 0.8, 1.0),
+            8.0,
+            10.0,
+            0.3,
+            0.45,
+        ));
     }
 
     pub fn render(&mut self) {
@@ -103,25 +416,180 @@ impl Engine {
         // https://github.com/gfx-rs/wgpu-rs/issues/9#issuecomment-494022784
         // https://github.com/gpuweb/gpuweb/pull/509
         // self.render_data.update_view(self.camera.view());
+        if self.scene.update() {
+            for model in 0..self.render_data.get_model_count() {
+                let instances = Self::instances_of(&self.scene, model);
+                self.render_data.update_instances(&self.device, model, &instances);
+            }
+        }
+
+        if self.update_mats {
+            if let Some(flashlight) = self.flashlight {
+                let light = self.lights.get_mut(flashlight);
+                light.position = self.camera.get_position();
+                light.direction = self.camera.get_forward();
+            }
+        }
+
+        if self.lights.take_changed() {
+            let lights = self.lights.to_uniforms();
+            self.render_data.update_lights(&self.device, &mut encoder, self.renderer.get_bind_group_layout(), &lights);
+        }
+
         if self.update_mats {
             self.update_mats = false;
             let temp_buffer = self.device
                 .create_buffer_mapped(1, wgpu::BufferUsage::COPY_SRC)
                 .fill_from_slice(&[self.camera.view()]);
             encoder.copy_buffer_to_buffer(&temp_buffer, 0, self.render_data.get_uniforms_buffer(), 0, 64);
+            encoder.copy_buffer_to_buffer(&temp_buffer, 0, self.skybox.get_transforms_buffer(), 0, 64);
         }
 
-        self.renderer.render(&frame, &mut encoder, &self.depth_texture_view, &self.render_data);
+        self.renderer.render(
+            &frame,
+            &mut encoder,
+            &self.depth_texture_view,
+            &self.render_data,
+            &self.skybox,
+            self.camera.get_position(),
+        );
         self.queue.submit(&[encoder.finish()]);
     }
 
+    /// World matrices of every node showing the given model.
+    fn instances_of(scene: &SceneGraph, model: usize) -> Vec<glam::Mat4> {
+        scene.renderables().filter(|&(_, m, _)| m == model).map(|(_, _, world)| world).collect()
+    }
+
+    /// Brightens or darkens the sky and the light it casts by `stops`, each one doubling or
+    /// halving it. HDR skies usually need some to fit the screen.
+    pub fn set_sky_exposure(&mut self, stops: f32) {
+        self.sky_exposure = stops;
+        self.skybox.set_exposure(&self.device, &mut self.queue, stops.exp2());
+        println!("[Info] Sky exposure: {} stops", stops);
+    }
+
+    pub fn get_sky_exposure(&self) -> f32 {
+        self.sky_exposure
+    }
+
+    pub fn update(&mut self, elapsed: Duration) {
+        self.poll_loader();
+        self.physics.step(elapsed);
+        if let Some(earth) = self.earth {
+            let spin = Quat::from_rotation_y(Self::EARTH_SPIN * elapsed.as_secs_f32());
+            self.scene.set_rotation(earth, spin * self.scene.get_local(earth).rotation);
+        }
+
+        let dt = elapsed.as_secs_f32().min(Self::MAX_FRAME_TIME);
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let ahead = axis(self.move_input.forward, self.move_input.back);
+        let strafe = axis(self.move_input.right, self.move_input.left);
+        let forward = self.camera.get_forward();
+        let right = forward.cross(Vec3::unit_y()).normalize();
+
+        // until the level's collision mesh is in there's nothing to stand on
+        if !self.fly_mode && !self.pending_colliders.is_empty() {
+            return;
+        }
+
+        if self.fly_mode {
+            if ahead != 0.0 || strafe != 0.0 {
+                self.camera.move_pos(ahead * Self::FLY_SPEED * dt);
+                self.camera.set_position(self.camera.get_position() + right * strafe * Self::FLY_SPEED * dt);
+                self.update_mats = true;
+            }
+        } else {
+            // the camera can't look straight up or down so the flattened forward is never zero
+            let flat_forward = Vec3::new(forward.x(), 0.0, forward.z()).normalize();
+            let mut walk = flat_forward * ahead + right * strafe;
+            if walk.length_squared() > 1.0 {
+                walk = walk.normalize();
+            }
+            self.controller.update(&self.physics, walk * Self::WALK_SPEED, dt, &[]);
+            self.camera.set_position(self.controller.get_feet_position() + Vec3::new(0.0, Self::EYE_HEIGHT, 0.0));
+            self.update_mats = true;
+        }
+    }
+
+    /// Casts a ray from the camera through the cursor, given in window pixels.
+    pub fn pick(&self, cursor_x: f64, cursor_y: f64) -> Option<QueryHit> {
+        let ndc_x = 2.0 * cursor_x as f32 / self.window_size.width as f32 - 1.0;
+        let ndc_y = 1.0 - 2.0 * cursor_y as f32 / self.window_size.height as f32;
+        let (origin, dir) = self.frustum.unproject(self.camera.view(), ndc_x, ndc_y);
+        self.physics.raycast(origin, dir, Self::PICK_DISTANCE, &[])
+    }
+
     pub fn handle_mouse_move(&mut self, dx: f64, dy: f64) {
         self.camera.change_angle(dx as f32, dy as f32);
         self.update_mats = true;
     }
 
-    pub fn move_camera(&mut self, forward: bool) {
-        self.camera.move_pos(if forward { 1.0 } else { -1.0 } * Self::CAMERA_SPEED);
-        self.update_mats = true;
+    /// WASD moves, space jumps while walking.
+    pub fn handle_move_key(&mut self, scancode: u32, pressed: bool) {
+        match scancode {
+            0x11 => self.move_input.forward = pressed,
+            0x1F => self.move_input.back = pressed,
+            0x1E => self.move_input.left = pressed,
+            0x20 => self.move_input.right = pressed,
+            0x39 if pressed && !self.fly_mode => { self.controller.jump(); },
+            _ => { },
+        }
+    }
+
+    /// Switches between walking and flying freely through walls, for debugging.
+    pub fn toggle_fly_mode(&mut self) {
+        if self.fly_mode && self.no_ground {
+            println!("[Info] Nothing to walk on, staying in free-fly");
+            return;
+        }
+        self.fly_mode = !self.fly_mode;
+        if !self.fly_mode {
+            self.place_character_at_camera();
+        }
+        println!("[Info] Free-fly camera {}", if self.fly_mode { "on" } else { "off" });
+    }
+
+    /// The level model came without anything to stand on, fly instead of falling forever.
+    fn lose_ground(&mut self, name: &str) {
+        println!("[Error] {} has no collision mesh, switching to free-fly", name);
+        self.no_ground = true;
+        self.fly_mode = true;
+    }
+
+    /// Switches a spot light shining wherever the camera looks on or off.
+    pub fn toggle_flashlight(&mut self) {
+        match self.flashlight.take() {
+            Some(flashlight) => self.lights.remove(flashlight),
+            None => {
+                self.flashlight = Some(self.lights.add(Light::spot(
+                    self.camera.get_position(),
+                    self.camera.get_forward(),
+                    Vec3::new(1.0, 0.95, 0.8),
+                    4.0,
+                    15.0,
+                    0.2,
+                    0.35,
+                )));
+            },
+        }
+        let state = if self.flashlight.is_some() { "on" } else { "off" };
+        println!("[Info] Flashlight {}, {} lights in the scene", state, self.lights.get_count());
+    }
+
+    /// Switches between Cook-Torrance and the Blinn-Phong fallback.
+    pub fn toggle_shading_model(&mut self) {
+        let model = match self.render_data.get_shading_model() {
+            ShadingModel::CookTorrance => ShadingModel::BlinnPhong,
+            ShadingModel::BlinnPhong => ShadingModel::CookTorrance,
+        };
+        self.render_data.set_shading_model(&self.device, &mut self.queue, model);
+        println!("[Info] Shading model: {:?}", model);
+    }
+
+    fn place_character_at_camera(&mut self) {
+        let center_height = Self::CHARACTER_HALF_HEIGHT + Self::CHARACTER_RADIUS;
+        let feet = self.camera.get_position() - Vec3::new(0.0, Self::EYE_HEIGHT, 0.0);
+        self.controller.set_position(feet + Vec3::new(0.0, center_height, 0.0));
     }
 }
@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+
+use super::environment::{self, HdrCube, HdrImage};
+use super::model::{ColorSpace, DefaultTextures, Model, TextureData, TextureRequest, TextureSource};
+use super::vfs::Vfs;
+
+/// Shared reference to a loaded asset, whatever it holds on the GPU is freed once
+/// the last handle to it is dropped.
+pub struct Handle<T>(Rc<T>);
+
+impl<T> Handle<T> {
+    /// A handle nothing else shares, for assets that aren't worth caching.
+    pub fn new(asset: T) -> Self {
+        Handle(Rc::new(asset))
+    }
+
+    /// Whether both handles refer to the same loaded asset.
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Rc::ptr_eq(&a.0, &b.0)
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle(Rc::clone(&self.0))
+    }
+}
+
+impl<T> Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+pub type TextureHandle = Handle<wgpu::TextureView>;
+
+/// A texture file and how it's sampled, the same image can be both color and data.
+pub type TextureKey = (String, ColorSpace);
+
+/// A sky cubemap and the maps image based lighting samples from it, all half float.
+pub struct EnvironmentMaps {
+    skybox: wgpu::TextureView,
+    irradiance: wgpu::TextureView,
+    specular: wgpu::TextureView,
+}
+
+impl EnvironmentMaps {
+    const IRRADIANCE_SIZE: u32 = 32;
+    const SPECULAR_SIZE: u32 = 64;
+    /// Roughness goes from 0 at the first level to 1 at the last.
+    pub const SPECULAR_LEVELS: u32 = 5;
+
+    pub fn get_skybox(&self) -> &wgpu::TextureView {
+        &self.skybox
+    }
+
+    pub fn get_irradiance(&self) -> &wgpu::TextureView {
+        &self.irradiance
+    }
+
+    pub fn get_specular(&self) -> &wgpu::TextureView {
+        &self.specular
+    }
+}
+
+/// Assets by key, only weak references are kept so the cache never keeps anything alive.
+pub struct AssetCache<K, T> {
+    entries: HashMap<K, Weak<T>>,
+}
+
+impl<K: Hash + Eq, T> AssetCache<K, T> {
+    pub fn new() -> Self {
+        Self { entries: HashMap::new() }
+    }
+
+    /// The asset loaded under `key` if something still holds a handle to it.
+    pub fn get(&self, key: &K) -> Option<Handle<T>> {
+        self.entries.get(key).and_then(Weak::upgrade).map(Handle)
+    }
+
+    /// Caches an asset, replacing whatever was loaded under `key` before.
+    pub fn insert(&mut self, key: K, asset: T) -> Handle<T> {
+        // entries of dropped assets would pile up otherwise
+        self.entries.retain(|_, weak| weak.strong_count() > 0);
+        let asset = Rc::new(asset);
+        self.entries.insert(key, Rc::downgrade(&asset));
+        Handle(asset)
+    }
+
+    pub fn get_or_insert_with(&mut self, key: K, load: impl FnOnce() -> T) -> Handle<T> {
+        match self.get(&key) {
+            Some(asset) => asset,
+            None => self.insert(key, load()),
+        }
+    }
+
+    /// Assets that are still alive.
+    pub fn len(&self) -> usize {
+        self.entries.values().filter(|weak| weak.strong_count() > 0).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Hash + Eq, T> Default for AssetCache<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Every model, texture, environment and shader module the engine loaded, keyed by path
+/// so models sharing files share them on the GPU as well.
+pub struct AssetManager {
+    vfs: Arc<Vfs>,
+    textures: AssetCache<TextureKey, wgpu::TextureView>,
+    models: AssetCache<String, Model>,
+    environments: AssetCache<String, EnvironmentMaps>,
+    shaders: AssetCache<String, wgpu::ShaderModule>,
+    /// Textures being decoded on the loader threads and the (model, texture) slots waiting for each.
+    texture_loads: HashMap<TextureKey, Vec<(usize, usize)>>,
+    defaults: DefaultTextures,
+    brdf_lut: wgpu::TextureView,
+}
+
+impl AssetManager {
+    const BRDF_LUT_SIZE: u32 = 64;
+
+    /// Everything is read through `vfs`.
+    pub fn new(device: &wgpu::Device, queue: &mut wgpu::Queue, vfs: Arc<Vfs>) -> Self {
+        let mut cmd_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        let mut solid = |name, rgba| {
+            Handle::new(Self::create_texture(device, &mut cmd_encoder, &TextureData::solid(name, rgba), &[], ColorSpace::Linear))
+        };
+        let defaults = DefaultTextures {
+            white: solid("white", [255; 4]),
+            flat_normal: solid("flat normal", [128, 128, 255, 255]),
+        };
+        let brdf_lut = Self::create_hdr_texture(device, &mut cmd_encoder, &environment::brdf_lut(Self::BRDF_LUT_SIZE));
+        queue.submit(&[cmd_encoder.finish()]);
+
+        Self {
+            vfs,
+            textures: AssetCache::new(),
+            models: AssetCache::new(),
+            environments: AssetCache::new(),
+            shaders: AssetCache::new(),
+            texture_loads: HashMap::new(),
+            defaults,
+            brdf_lut,
+        }
+    }
+
+    /// Textures bound where a material has no map, shared by every model.
+    pub fn get_defaults(&self) -> &DefaultTextures {
+        &self.defaults
+    }
+
+    /// Split sum lookup table of image based lighting, the same for every environment.
+    pub fn get_brdf_lut(&self) -> &wgpu::TextureView {
+        &self.brdf_lut
+    }
+
+    pub fn get_texture(&self, key: &TextureKey) -> Option<TextureHandle> {
+        self.textures.get(key)
+    }
+
+    pub fn add_texture(&mut self, key: TextureKey, view: wgpu::TextureView) -> TextureHandle {
+        self.textures.insert(key, view)
+    }
+
+    /// Uploads a decoded texture, `mip_chain` starts with the full size image. Textures
+    /// without a path aren't cached.
+    pub fn upload_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &mut wgpu::Queue,
+        path: Option<String>,
+        mip_chain: &[TextureData],
+        color_space: ColorSpace,
+    ) -> TextureHandle {
+        let mut cmd_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        let view = Self::create_texture(device, &mut cmd_encoder, &mip_chain[0], &mip_chain[1..], color_space);
+        queue.submit(&[cmd_encoder.finish()]);
+        match path {
+            Some(path) => self.add_texture((path, color_space), view),
+            None => Handle::new(view),
+        }
+    }
+
+    /// Records a texture slot waiting on a load, returns true if nothing was loading that
+    /// texture yet and the caller has to queue it.
+    pub fn begin_texture_load(&mut self, key: TextureKey, model: usize, texture: usize) -> bool {
+        let waiting = self.texture_loads.entry(key).or_default();
+        waiting.push((model, texture));
+        waiting.len() == 1
+    }
+
+    /// The (model, texture) slots that were waiting on a texture that finished loading.
+    pub fn finish_texture_load(&mut self, key: &TextureKey) -> Vec<(usize, usize)> {
+        self.texture_loads.remove(key).unwrap_or_default()
+    }
+
+    /// Creates a texture from an image and its smaller mip levels, the copies are recorded
+    /// into `cmd_encoder`.
+    pub fn create_texture(
+        device: &wgpu::Device,
+        cmd_encoder: &mut wgpu::CommandEncoder,
+        image: &TextureData,
+        mips: &[TextureData],
+        color_space: ColorSpace,
+    ) -> wgpu::TextureView {
+        let texture_extent = wgpu::Extent3d {
+            width: image.width,
+            height: image.height,
+            depth: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: texture_extent,
+            array_layer_count: 1,
+            mip_level_count: 1 + mips.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // srgb textures are decoded to linear when sampled so lighting happens in linear space
+            format: Self::texture_format(color_space),
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+
+        // the whole mip chain is built on the cpu, level 0 is the image itself
+        for (level, mip) in std::iter::once(image).chain(mips).enumerate() {
+            Self::copy_to_texture(device, cmd_encoder, &mip.pixels, mip.width, mip.height, &texture, level as u32, 0);
+        }
+
+        texture.create_default_view()
+    }
+
+    fn texture_format(color_space: ColorSpace) -> wgpu::TextureFormat {
+        match color_space {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+
+    /// Copies tightly packed rows of pixels of whatever size the texture format has.
+    #[allow(clippy::too_many_arguments)]
+    fn copy_to_texture(
+        device: &wgpu::Device,
+        cmd_encoder: &mut wgpu::CommandEncoder,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        texture: &wgpu::Texture,
+        mip_level: u32,
+        array_layer: u32,
+    ) {
+        let image_buf = device
+            .create_buffer_mapped(pixels.len(), wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(pixels);
+
+        cmd_encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: &image_buf,
+                offset: 0,
+                row_pitch: pixels.len() as u32 / height,
+                image_height: height,
+            },
+            wgpu::TextureCopyView {
+                texture,
+                mip_level,
+                array_layer,
+                origin: wgpu::Origin3d { x: 0f32, y: 0f32, z: 0f32 },
+            },
+            wgpu::Extent3d { width, height, depth: 1 },
+        );
+    }
+
+    /// The model loaded under `name` if it's still in use somewhere.
+    pub fn get_model(&self, name: &str) -> Option<Handle<Model>> {
+        self.models.get(&name.to_string())
+    }
+
+    pub fn add_model(&mut self, name: &str, model: Model) -> Handle<Model> {
+        self.models.insert(name.to_string(), model)
+    }
+
+    /// Loads models/textures/<name>_px.jpg and the other five faces as the sky, or when `name`
+    /// ends in .hdr or .exr the equirectangular panorama models/textures/<name>, and filters
+    /// it for image based lighting.
+    pub fn environment(&mut self, device: &wgpu::Device, queue: &mut wgpu::Queue, name: &str) -> Handle<EnvironmentMaps> {
+        if let Some(environment) = self.environments.get(&name.to_string()) {
+            return environment;
+        }
+        println!("[Info] Loading environment: {}", name);
+
+        let faces = if environment::is_panorama(name) { self.panorama_faces(name) } else { self.cubemap_faces(name) };
+        let cube = HdrCube::new(faces.unwrap_or_else(|reason| {
+            // faces have to match in size, so one broken face replaces all of them
+            println!("[Error] Failed to load environment {}: {}", name, reason);
+            (0..6).map(|_| HdrImage::from_srgb(&TextureData::checkerboard(name))).collect()
+        }));
+        let irradiance = cube.irradiance(EnvironmentMaps::IRRADIANCE_SIZE);
+        let specular = cube.prefilter_specular(EnvironmentMaps::SPECULAR_SIZE, EnvironmentMaps::SPECULAR_LEVELS);
+
+        let mut cmd_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        let environment = EnvironmentMaps {
+            skybox: Self::create_cube_texture(device, &mut cmd_encoder, &cube),
+            irradiance: Self::create_cube_texture(device, &mut cmd_encoder, &irradiance),
+            specular: Self::create_cube_texture(device, &mut cmd_encoder, &specular),
+        };
+        queue.submit(&[cmd_encoder.finish()]);
+        self.environments.insert(name.to_string(), environment)
+    }
+
+    /// The six faces of a cubemap stored as separate sRGB images.
+    fn cubemap_faces(&self, name: &str) -> Result<Vec<HdrImage>, String> {
+        const MAP_SUFFIXES: [&str; 6] = ["_px.jpg", "_nx.jpg", "_py.jpg", "_ny.jpg", "_pz.jpg", "_nz.jpg"];
+
+        let faces = MAP_SUFFIXES
+            .iter()
+            .enumerate()
+            .map(|(index, suffix)| {
+                let face_name = format!("{}{}", name, suffix);
+                let source = TextureSource::File(format!("models/textures/{}", face_name));
+                let face = TextureRequest { index, name: face_name, source }.decode(&self.vfs)?;
+                Ok(HdrImage::from_srgb(&face))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        environment::check_cube_faces(&faces)?;
+        Ok(faces)
+    }
+
+    /// The six faces of the cubemap an equirectangular panorama wraps around.
+    fn panorama_faces(&self, name: &str) -> Result<Vec<HdrImage>, String> {
+        let path = format!("models/textures/{}", name);
+        let bytes = self.vfs.read(&path).map_err(|err| err.to_string())?;
+        let panorama = HdrImage::decode(&path, &bytes)?;
+        Ok(panorama.to_cube_faces(panorama.face_size()))
+    }
+
+    /// Uploads every level of every face into an Rgba16Float cubemap.
+    fn create_cube_texture(device: &wgpu::Device, cmd_encoder: &mut wgpu::CommandEncoder, cube: &HdrCube) -> wgpu::TextureView {
+        let size = cube.get_size();
+        let mip_level_count = cube.get_mip_level_count();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d { width: size, height: size, depth: 1 },
+            array_layer_count: 6,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+
+        for (layer, levels) in cube.get_faces().iter().enumerate() {
+            for (level, image) in levels.iter().enumerate() {
+                let pixels = image.to_rgba16f();
+                Self::copy_to_texture(device, cmd_encoder, &pixels, image.width, image.height, &texture, level as u32, layer as u32);
+            }
+        }
+
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            format: wgpu::TextureFormat::Rgba16Float,
+            dimension: wgpu::TextureViewDimension::Cube,
+            aspect: wgpu::TextureAspect::default(),
+            base_mip_level: 0,
+            level_count: mip_level_count,
+            base_array_layer: 0,
+            array_layer_count: 6,
+        })
+    }
+
+    /// Uploads a single level Rgba16Float texture.
+    fn create_hdr_texture(device: &wgpu::Device, cmd_encoder: &mut wgpu::CommandEncoder, image: &HdrImage) -> wgpu::TextureView {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d { width: image.width, height: image.height, depth: 1 },
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+        Self::copy_to_texture(device, cmd_encoder, &image.to_rgba16f(), image.width, image.height, &texture, 0, 0);
+        texture.create_default_view()
+    }
+
+    /// The compiled SPIR-V shader shaders/<name>.
+    pub fn shader(&mut self, device: &wgpu::Device, name: &str) -> Handle<wgpu::ShaderModule> {
+        let vfs = &self.vfs;
+        self.shaders.get_or_insert_with(name.to_string(), || {
+            let spirv = vfs
+                .read(&format!("shaders/{}", name))
+                .unwrap_or_else(|err| panic!("failed to read shader {}: {}", name, err));
+            let words = wgpu::read_spirv(std::io::Cursor::new(&spirv[..]))
+                .unwrap_or_else(|err| panic!("failed to read shader spir-v {}: {}", name, err));
+            device.create_shader_module(&words)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_are_shared_while_a_handle_is_alive() {
+        let mut cache = AssetCache::<String, u32>::new();
+        let mut loads = 0;
+        let mut load = |key: &str, cache: &mut AssetCache<String, u32>| {
+            cache.get_or_insert_with(key.to_string(), || {
+                loads += 1;
+                7
+            })
+        };
+
+        let first = load("textures/wall.png", &mut cache);
+        let second = load("textures/wall.png", &mut cache);
+        assert!(Handle::ptr_eq(&first, &second));
+        assert_eq!(*second, 7);
+        assert_eq!(cache.len(), 1);
+
+        drop(first);
+        drop(second);
+        assert!(cache.get(&String::from("textures/wall.png")).is_none());
+        assert!(cache.is_empty());
+
+        let _third = load("textures/wall.png", &mut cache);
+        assert_eq!(loads, 2);
+    }
+
+    #[test]
+    fn dropped_entries_are_pruned_on_insert() {
+        let mut cache = AssetCache::new();
+        drop(cache.insert("a", 1));
+        let _b = cache.insert("b", 2);
+        assert_eq!(cache.entries.len(), 1);
+    }
+}
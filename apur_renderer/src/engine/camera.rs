@@ -26,6 +26,18 @@ impl Camera {
     pub fn move_pos(&mut self, units: f32) {
         self.position += units * self.forward;
     }
+
+    pub fn get_position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position;
+    }
+
+    pub fn get_forward(&self) -> Vec3 {
+        self.forward
+    }
 }
 
 impl Default for Camera {
@@ -59,4 +71,18 @@ impl Frustum {
     pub fn projection(&self) -> Mat4 {
         Mat4::perspective_rh_gl(self.fov_y, self.aspect_ratio, self.znear, self.zfar)
     }
+
+    /// World space ray through a point given in normalized device coordinates
+    /// (y up), returned as origin on the near plane and unit direction.
+    pub fn unproject(&self, view: Mat4, ndc_x: f32, ndc_y: f32) -> (Vec3, Vec3) {
+        let inv = (self.projection() * view).inverse();
+        let unproject = |z: f32| {
+            let p = inv * glam::vec4(ndc_x, ndc_y, z, 1.0);
+            p.truncate() / p.w()
+        };
+        // projection is the gl one so depth goes from -1 to 1
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+        (near, (far - near).normalize())
+    }
 }
@@ -0,0 +1,573 @@
+use std::f32::consts::PI;
+use std::io::Cursor;
+
+use glam::Vec3;
+
+use super::model::{ColorSpace, TextureData};
+
+/// Linear RGB image whose values can go past 1, like an HDRI panorama or a face of a
+/// cubemap made from one.
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+    /// Decodes a Radiance .hdr or an OpenEXR .exr file, told apart by the extension of `path`.
+    pub fn decode(path: &str, bytes: &[u8]) -> Result<Self, String> {
+        let image = match extension(path).as_deref() {
+            Some("hdr") => Self::decode_hdr(bytes)?,
+            Some("exr") => Self::decode_exr(bytes)?,
+            _ => return Err(format!("{} isn't an .hdr or .exr image", path)),
+        };
+        if image.width == 0 || image.height == 0 {
+            return Err(format!("{} is empty", path));
+        }
+        Ok(image)
+    }
+
+    /// Linear copy of an 8 bit sRGB image, alpha is dropped.
+    pub fn from_srgb(image: &TextureData) -> Self {
+        let pixels = image.pixels.chunks(4).map(|p| [p[0], p[1], p[2]].map(|c| ColorSpace::Srgb.decode(c)));
+        Self { width: image.width, height: image.height, pixels: pixels.collect() }
+    }
+
+    fn decode_hdr(bytes: &[u8]) -> Result<Self, String> {
+        let decoder = image::hdr::HDRDecoder::new(Cursor::new(bytes)).map_err(|err| err.to_string())?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr().map_err(|err| err.to_string())?;
+        Ok(Self {
+            width: metadata.width,
+            height: metadata.height,
+            pixels: pixels.into_iter().map(|pixel| pixel.0).collect(),
+        })
+    }
+
+    fn decode_exr(bytes: &[u8]) -> Result<Self, String> {
+        use exr::prelude::{ReadChannels, ReadLayers};
+
+        let image = exr::prelude::read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .rgba_channels(
+                |size, _| Self {
+                    width: size.width() as u32,
+                    height: size.height() as u32,
+                    pixels: vec![[0.0; 3]; size.area()],
+                },
+                |image: &mut Self, position, (r, g, b, _): (f32, f32, f32, f32)| {
+                    image.pixels[position.y() * image.width as usize + position.x()] = [r, g, b];
+                },
+            )
+            .first_valid_layer()
+            .all_attributes()
+            .from_buffered(Cursor::new(bytes))
+            .map_err(|err| err.to_string())?;
+        Ok(image.layer_data.channel_data.pixels)
+    }
+
+    /// Side of the cubemap faces a panorama is turned into, about as sharp as the panorama
+    /// and a power of two so every mip halves evenly.
+    pub fn face_size(&self) -> u32 {
+        let size = (self.width / 4).max(1);
+        1 << (31 - size.leading_zeros())
+    }
+
+    /// The six faces of a cubemap in +x, -x, +y, -y, +z, -z order, seeing this image as an
+    /// equirectangular panorama. The middle of the panorama ends up at -z, the way the camera
+    /// starts out looking.
+    pub fn to_cube_faces(&self, size: u32) -> Vec<HdrImage> {
+        (0..6)
+            .map(|face| {
+                let pixels = (0..size * size)
+                    .map(|i| {
+                        // texel centers, with v going down the face like the rows do
+                        let u = 2.0 * ((i % size) as f32 + 0.5) / size as f32 - 1.0;
+                        let v = 2.0 * ((i / size) as f32 + 0.5) / size as f32 - 1.0;
+                        self.sample(cube_direction(face, u, v))
+                    })
+                    .collect();
+                HdrImage { width: size, height: size, pixels }
+            })
+            .collect()
+    }
+
+    /// Bilinear sample of the panorama in the direction `dir`, wrapping around horizontally.
+    fn sample(&self, dir: Vec3) -> [f32; 3] {
+        let dir = dir.normalize();
+        let u = 0.5 + dir.x().atan2(-dir.z()) / (2.0 * PI);
+        let v = dir.y().clamp(-1.0, 1.0).acos() / PI;
+        self.bilinear(u * self.width as f32 - 0.5, v * self.height as f32 - 0.5, true)
+    }
+
+    /// Blend of the four texels around (x, y), given in texels from the center of the first
+    /// one. Rows are clamped, columns wrap around with `wrap` and are clamped otherwise.
+    fn bilinear(&self, x: f32, y: f32, wrap: bool) -> [f32; 3] {
+        let (max_x, max_y) = ((self.width - 1) as f32, (self.height - 1) as f32);
+        let x = if wrap { x } else { x.clamp(0.0, max_x) };
+        let y = y.clamp(0.0, max_y);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let texel = |x: f32, y: f32| {
+            let x = if wrap { (x as i64).rem_euclid(self.width as i64) as u32 } else { x.min(max_x) as u32 };
+            self.pixels[(y.min(max_y) as u32 * self.width + x) as usize]
+        };
+        let (a, b) = (texel(x0, y0), texel(x0 + 1.0, y0));
+        let (c, d) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+        let mut out = [0.0; 3];
+        for i in 0..3 {
+            let top = a[i] + (b[i] - a[i]) * tx;
+            let bottom = c[i] + (d[i] - c[i]) * tx;
+            out[i] = top + (bottom - top) * ty;
+        }
+        out
+    }
+
+    /// Every level below this one, halving the size each time until 1x1, odd sizes drop their
+    /// last row and column. Only meant for square cubemap faces.
+    pub fn mips(&self) -> Vec<HdrImage> {
+        let mut mips: Vec<HdrImage> = vec![];
+        while mips.last().unwrap_or(self).width > 1 {
+            let next = mips.last().unwrap_or(self).downsample();
+            mips.push(next);
+        }
+        mips
+    }
+
+    fn downsample(&self) -> HdrImage {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 3];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                    let src_x = (2 * x + dx).min(self.width - 1);
+                    let src_y = (2 * y + dy).min(self.height - 1);
+                    let texel = self.pixels[(src_y * self.width + src_x) as usize];
+                    for i in 0..3 {
+                        sum[i] += texel[i] / 4.0;
+                    }
+                }
+                pixels.push(sum);
+            }
+        }
+        HdrImage { width, height, pixels }
+    }
+
+    /// Pixels as half floats with an alpha of 1, laid out for an Rgba16Float texture.
+    pub fn to_rgba16f(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&[r, g, b]| vec![r, g, b, 1.0])
+            .flat_map(|value| f16_bits(value).to_le_bytes().to_vec())
+            .collect()
+    }
+}
+
+/// A cubemap on the cpu, six square faces in +x, -x, +y, -y, +z, -z order, each with its mip
+/// chain. Directions are looked up the way Vulkan samples cubemaps.
+pub struct HdrCube {
+    faces: Vec<Vec<HdrImage>>,
+}
+
+impl HdrCube {
+    const SPECULAR_SAMPLES: u32 = 64;
+
+    /// Builds the mip chains of six faces of the same size.
+    pub fn new(faces: Vec<HdrImage>) -> Self {
+        let faces = faces
+            .into_iter()
+            .map(|face| {
+                let mips = face.mips();
+                std::iter::once(face).chain(mips).collect()
+            })
+            .collect();
+        Self { faces }
+    }
+
+    /// Six faces each with only the levels given, for maps that don't go down to 1x1.
+    fn from_levels(faces: Vec<Vec<HdrImage>>) -> Self {
+        Self { faces }
+    }
+
+    pub fn get_size(&self) -> u32 {
+        self.faces[0][0].width
+    }
+
+    pub fn get_mip_level_count(&self) -> u32 {
+        self.faces[0].len() as u32
+    }
+
+    /// The mip chain of every face.
+    pub fn get_faces(&self) -> &[Vec<HdrImage>] {
+        &self.faces
+    }
+
+    /// Trilinear sample in the direction `dir`, `level` is the mip level with fractions
+    /// blending the two around it. Texels at face edges aren't blended with the next face.
+    fn sample(&self, dir: Vec3, level: f32) -> [f32; 3] {
+        let level = level.clamp(0.0, (self.get_mip_level_count() - 1) as f32);
+        let (face, u, v) = cube_face_uv(dir);
+        let sample_level = |level: usize| {
+            let image = &self.faces[face][level];
+            let x = (u + 1.0) / 2.0 * image.width as f32 - 0.5;
+            let y = (v + 1.0) / 2.0 * image.height as f32 - 0.5;
+            image.bilinear(x, y, false)
+        };
+        let lower = sample_level(level.floor() as usize);
+        let t = level.fract();
+        if t == 0.0 {
+            return lower;
+        }
+        let upper = sample_level(level.ceil() as usize);
+        [0, 1, 2].map(|i| lower[i] + (upper[i] - lower[i]) * t)
+    }
+
+    /// Cosine weighted light arriving from every direction divided by pi, so a white diffuse
+    /// surface facing a direction reflects what the map holds there. Goes through the first
+    /// nine spherical harmonics, which is all the detail irradiance has anyway.
+    pub fn irradiance(&self, size: u32) -> HdrCube {
+        // a small level is plenty for low frequencies
+        let level = self.faces[0].iter().position(|image| image.width <= 32).unwrap_or(self.faces[0].len() - 1);
+        let mut coefficients = [[0.0f32; 3]; 9];
+        let mut total_weight = 0.0;
+        for (face, levels) in self.faces.iter().enumerate() {
+            let image = &levels[level];
+            let texel_size = 2.0 / image.width as f32;
+            for (i, texel) in image.pixels.iter().enumerate() {
+                let u = ((i as u32 % image.width) as f32 + 0.5) * texel_size - 1.0;
+                let v = ((i as u32 / image.width) as f32 + 0.5) * texel_size - 1.0;
+                // solid angle of the texel, bigger in the middle of the face
+                let weight = texel_size * texel_size / (1.0 + u * u + v * v).powf(1.5);
+                let basis = sh_basis(cube_direction(face, u, v).normalize());
+                for (coefficient, y) in coefficients.iter_mut().zip(basis.iter()) {
+                    for c in 0..3 {
+                        coefficient[c] += texel[c] * y * weight;
+                    }
+                }
+                total_weight += weight;
+            }
+        }
+
+        // the texel solid angles are approximate, make them add up to the whole sphere
+        let normalization = 4.0 * PI / total_weight;
+        // cosine lobe convolution per band, and the 1/pi of a lambertian surface
+        const BANDS: [f32; 9] = [1.0, 2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 0.25, 0.25, 0.25, 0.25, 0.25];
+        let faces = (0..6)
+            .map(|face| {
+                let pixels = (0..size * size)
+                    .map(|i| {
+                        let u = 2.0 * ((i % size) as f32 + 0.5) / size as f32 - 1.0;
+                        let v = 2.0 * ((i / size) as f32 + 0.5) / size as f32 - 1.0;
+                        let basis = sh_basis(cube_direction(face, u, v).normalize());
+                        let mut irradiance = [0.0; 3];
+                        for ((coefficient, y), band) in coefficients.iter().zip(basis.iter()).zip(BANDS.iter()) {
+                            for c in 0..3 {
+                                irradiance[c] += coefficient[c] * y * band * normalization;
+                            }
+                        }
+                        irradiance.map(|c| c.max(0.0))
+                    })
+                    .collect();
+                vec![HdrImage { width: size, height: size, pixels }]
+            })
+            .collect();
+        HdrCube::from_levels(faces)
+    }
+
+    /// Reflections of the map off surfaces of rising GGX roughness, level 0 is a mirror and the
+    /// last one fully rough, so shaders can pick the level by roughness. Looks are kept cheap
+    /// by sampling blurrier source levels for sparser samples.
+    pub fn prefilter_specular(&self, size: u32, levels: u32) -> HdrCube {
+        let source_size = self.get_size() as f32;
+        // solid angle of a source texel
+        let texel_angle = 4.0 * PI / (6.0 * source_size * source_size);
+        let faces = (0..6)
+            .map(|face| {
+                (0..levels)
+                    .map(|level| {
+                        let level_size = (size >> level).max(1);
+                        let roughness = level as f32 / (levels - 1).max(1) as f32;
+                        let pixels = (0..level_size * level_size)
+                            .map(|i| {
+                                let u = 2.0 * ((i % level_size) as f32 + 0.5) / level_size as f32 - 1.0;
+                                let v = 2.0 * ((i / level_size) as f32 + 0.5) / level_size as f32 - 1.0;
+                                let n = cube_direction(face, u, v).normalize();
+                                if level == 0 {
+                                    return self.sample(n, (source_size / level_size as f32).log2());
+                                }
+                                self.convolve_ggx(n, roughness, texel_angle)
+                            })
+                            .collect();
+                        HdrImage { width: level_size, height: level_size, pixels }
+                    })
+                    .collect()
+            })
+            .collect();
+        HdrCube::from_levels(faces)
+    }
+
+    /// GGX lobe around `n` with the view along the normal, importance sampled.
+    fn convolve_ggx(&self, n: Vec3, roughness: f32, texel_angle: f32) -> [f32; 3] {
+        let mut color = [0.0; 3];
+        let mut total_weight = 0.0;
+        for i in 0..Self::SPECULAR_SAMPLES {
+            let h = importance_sample_ggx(hammersley(i, Self::SPECULAR_SAMPLES), n, roughness);
+            let n_dot_h = n.dot(h);
+            let l = h * 2.0 * n_dot_h - n;
+            let n_dot_l = n.dot(l);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+            // with n = v the pdf of l is D / 4
+            let pdf = ggx_distribution(n_dot_h, roughness) / 4.0;
+            let sample_angle = 1.0 / (Self::SPECULAR_SAMPLES as f32 * pdf + 1e-4);
+            let level = (0.5 * (sample_angle / texel_angle).log2()).max(0.0);
+            let sample = self.sample(l, level);
+            for c in 0..3 {
+                color[c] += sample[c] * n_dot_l;
+            }
+            total_weight += n_dot_l;
+        }
+        color.map(|c| c / total_weight.max(1e-4))
+    }
+}
+
+/// Split sum lookup table for specular image based lighting, x is n.v and y the roughness.
+/// Red is the scale and green the bias applied to the specular color at normal incidence.
+pub fn brdf_lut(size: u32) -> HdrImage {
+    const SAMPLES: u32 = 128;
+    let pixels = (0..size * size)
+        .map(|i| {
+            let n_dot_v = ((i % size) as f32 + 0.5) / size as f32;
+            let roughness = ((i / size) as f32 + 0.5) / size as f32;
+            let n = Vec3::new(0.0, 0.0, 1.0);
+            let v = Vec3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+            // the k of Schlick's geometry term for image based lighting
+            let k = roughness * roughness / 2.0;
+            let g1 = |x: f32| x / (x * (1.0 - k) + k);
+            let (mut scale, mut bias) = (0.0, 0.0);
+            for i in 0..SAMPLES {
+                let h = importance_sample_ggx(hammersley(i, SAMPLES), n, roughness);
+                let v_dot_h = v.dot(h);
+                let l = h * 2.0 * v_dot_h - v;
+                let (n_dot_l, n_dot_h) = (l.z(), h.z());
+                if n_dot_l <= 0.0 {
+                    continue;
+                }
+                let visibility = g1(n_dot_v) * g1(n_dot_l) * v_dot_h.max(0.0) / (n_dot_h * n_dot_v);
+                let fresnel = (1.0 - v_dot_h.max(0.0)).powi(5);
+                scale += (1.0 - fresnel) * visibility;
+                bias += fresnel * visibility;
+            }
+            [scale / SAMPLES as f32, bias / SAMPLES as f32, 0.0]
+        })
+        .collect();
+    HdrImage { width: size, height: size, pixels }
+}
+
+/// Cubemap faces have to be six squares of the same size, the texture is sized from the first.
+pub fn check_cube_faces(faces: &[HdrImage]) -> Result<(), String> {
+    if faces.len() != 6 {
+        return Err(format!("expected 6 faces, got {}", faces.len()));
+    }
+    let size = faces[0].width;
+    match faces.iter().position(|face| face.width != size || face.height != size) {
+        Some(index) => Err(format!(
+            "face {} is {}x{}, every face has to be {}x{}",
+            index, faces[index].width, faces[index].height, size, size,
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Whether a cubemap name refers to a single .hdr or .exr panorama instead of six face images.
+pub fn is_panorama(name: &str) -> bool {
+    matches!(extension(name).as_deref(), Some("hdr") | Some("exr"))
+}
+
+fn extension(path: &str) -> Option<String> {
+    std::path::Path::new(path).extension().map(|ext| ext.to_string_lossy().to_lowercase())
+}
+
+/// Direction through the point (u, v) of a cubemap face, both going from -1 to 1 with v
+/// pointing down the face, following the face layout Vulkan samples cubemaps with.
+fn cube_direction(face: usize, u: f32, v: f32) -> Vec3 {
+    match face {
+        0 => Vec3::new(1.0, -v, -u),
+        1 => Vec3::new(-1.0, -v, u),
+        2 => Vec3::new(u, 1.0, v),
+        3 => Vec3::new(u, -1.0, -v),
+        4 => Vec3::new(u, -v, 1.0),
+        _ => Vec3::new(-u, -v, -1.0),
+    }
+}
+
+/// Which face a direction points at and where on it, the inverse of `cube_direction`.
+fn cube_face_uv(dir: Vec3) -> (usize, f32, f32) {
+    let (x, y, z) = (dir.x(), dir.y(), dir.z());
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    if ax >= ay && ax >= az {
+        if x > 0.0 { (0, -z / ax, -y / ax) } else { (1, z / ax, -y / ax) }
+    } else if ay >= az {
+        if y > 0.0 { (2, x / ay, z / ay) } else { (3, x / ay, -z / ay) }
+    } else if z > 0.0 {
+        (4, x / az, -y / az)
+    } else {
+        (5, -x / az, -y / az)
+    }
+}
+
+/// The first nine real spherical harmonics at a unit direction.
+fn sh_basis(dir: Vec3) -> [f32; 9] {
+    let (x, y, z) = (dir.x(), dir.y(), dir.z());
+    [
+        0.282_095,
+        0.488_603 * y,
+        0.488_603 * z,
+        0.488_603 * x,
+        1.092_548 * x * y,
+        1.092_548 * y * z,
+        0.315_392 * (3.0 * z * z - 1.0),
+        1.092_548 * x * z,
+        0.546_274 * (x * x - y * y),
+    ]
+}
+
+/// Low discrepancy point `i` of `count` in the unit square.
+fn hammersley(i: u32, count: u32) -> (f32, f32) {
+    (i as f32 / count as f32, i.reverse_bits() as f32 * 2.328_306_4e-10)
+}
+
+/// Half vector around `n` distributed like the GGX normal distribution.
+fn importance_sample_ggx((xi_x, xi_y): (f32, f32), n: Vec3, roughness: f32) -> Vec3 {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi_x;
+    let cos_theta = ((1.0 - xi_y) / (1.0 + (a * a - 1.0) * xi_y)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let up = if n.z().abs() < 0.999 { Vec3::new(0.0, 0.0, 1.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let tangent = up.cross(n).normalize();
+    let bitangent = n.cross(tangent);
+    (tangent * (phi.cos() * sin_theta) + bitangent * (phi.sin() * sin_theta) + n * cos_theta).normalize()
+}
+
+fn ggx_distribution(n_dot_h: f32, roughness: f32) -> f32 {
+    let a2 = roughness.powi(4);
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+/// IEEE half float closest to `value`, too large values and infinities become infinity and
+/// NaN stays NaN.
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // subnormal, or zero once it's too small for those too
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = (mantissa >> shift) as u16;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return sign | (half + round as u16);
+    }
+    let half = sign | ((exponent as u16) << 10) | (mantissa >> 13) as u16;
+    // rounding can carry into the exponent, which is still the right result
+    half + ((mantissa >> 12) & 1) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cube_faces_must_be_matching_squares() {
+        let face = |width, height| HdrImage { width, height, pixels: vec![[0.0; 3]; (width * height) as usize] };
+        let mut faces: Vec<HdrImage> = (0..6).map(|_| face(4, 4)).collect();
+        assert!(check_cube_faces(&faces).is_ok());
+        faces[3] = face(8, 8);
+        assert!(check_cube_faces(&faces).is_err());
+        faces = (0..6).map(|_| face(4, 2)).collect();
+        assert!(check_cube_faces(&faces).is_err());
+        assert!(check_cube_faces(&faces[..5]).is_err());
+    }
+
+    #[test]
+    fn floats_are_converted_to_halves() {
+        assert_eq!(f16_bits(0.0), 0);
+        assert_eq!(f16_bits(1.0), 0x3c00);
+        assert_eq!(f16_bits(-2.0), 0xc000);
+        assert_eq!(f16_bits(0.5), 0x3800);
+        assert_eq!(f16_bits(65504.0), 0x7bff);
+        assert_eq!(f16_bits(1.0e6), 0x7c00);
+        assert_eq!(f16_bits(6.0e-8), 0x0001);
+        assert_eq!(f16_bits(1.0e-9), 0);
+    }
+
+    #[test]
+    fn panorama_directions_land_on_the_matching_faces() {
+        // left half bright, right half dark, so +x is dark and -x bright
+        let (width, height) = (64, 32);
+        let pixels = (0..width * height).map(|i| if i % width < width / 2 { [4.0; 3] } else { [0.0; 3] }).collect();
+        let panorama = HdrImage { width, height, pixels };
+        assert_eq!(panorama.face_size(), 16);
+
+        let faces = panorama.to_cube_faces(8);
+        let center = |face: &HdrImage| face.pixels[(4 * 8 + 4) as usize][0];
+        assert_eq!(center(&faces[0]), 0.0);
+        assert_eq!(center(&faces[1]), 4.0);
+        assert_eq!(faces[0].mips().len(), 3);
+    }
+
+    #[test]
+    fn directions_sample_the_face_they_point_at() {
+        let faces = (0..6).map(|face| HdrImage { width: 4, height: 4, pixels: vec![[face as f32; 3]; 16] }).collect();
+        let cube = HdrCube::new(faces);
+        assert_eq!(cube.get_mip_level_count(), 3);
+        let dirs = [(1.0, 0.1, 0.2), (-1.0, 0.1, 0.2), (0.1, 1.0, 0.2), (0.1, -1.0, 0.2), (0.1, 0.2, 1.0), (0.1, 0.2, -1.0)];
+        for (face, &(x, y, z)) in dirs.iter().enumerate() {
+            assert_eq!(cube.sample(Vec3::new(x, y, z), 1.5)[0], face as f32);
+            let (found, u, v) = cube_face_uv(cube_direction(face, 0.3, -0.6));
+            assert_eq!(found, face);
+            assert!((u - 0.3).abs() < 1e-5 && (v + 0.6).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn uniform_light_stays_uniform_when_filtered() {
+        let white = || HdrImage { width: 16, height: 16, pixels: vec![[1.0; 3]; 256] };
+        let cube = HdrCube::new((0..6).map(|_| white()).collect());
+
+        let irradiance = cube.irradiance(4);
+        let specular = cube.prefilter_specular(8, 4);
+        assert_eq!(specular.get_mip_level_count(), 4);
+        let texels = irradiance.get_faces().iter().chain(specular.get_faces()).flatten().flat_map(|image| &image.pixels);
+        for texel in texels {
+            assert!((texel[0] - 1.0).abs() < 0.02, "{:?}", texel);
+        }
+    }
+
+    #[test]
+    fn brdf_lut_never_reflects_more_than_comes_in() {
+        let lut = brdf_lut(16);
+        for texel in &lut.pixels {
+            assert!(texel[0] >= 0.0 && texel[1] >= 0.0 && texel[0] + texel[1] <= 1.01, "{:?}", texel);
+        }
+        // smooth surfaces seen head on reflect everything the specular color says
+        let smooth_head_on = lut.pixels[15];
+        assert!(smooth_head_on[0] > 0.9 && smooth_head_on[1] < 0.05, "{:?}", smooth_head_on);
+    }
+}
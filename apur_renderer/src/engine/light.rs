@@ -0,0 +1,193 @@
+use glam::Vec3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct LightId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    /// Infinitely far away like the sun, only its direction matters.
+    Directional,
+    Point,
+    /// A point light narrowed to a cone, full strength within `inner_angle` of its direction
+    /// and fading out towards `outer_angle`, both in radians from the axis.
+    Spot { inner_angle: f32, outer_angle: f32 },
+}
+
+/// A light source, the renderer picks up changes on the next frame.
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Ignored for directional lights.
+    pub position: Vec3,
+    /// Where the light shines towards, ignored for point lights.
+    pub direction: Vec3,
+    pub color: Vec3,
+    /// Scales `color`, for point and spot lights it is what arrives one unit away.
+    pub intensity: f32,
+    /// Point and spot lights fall off with the square of the distance and smoothly reach zero
+    /// at `range`, zero leaves them unbounded.
+    pub range: f32,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self { kind: LightKind::Directional, position: Vec3::zero(), direction, color, intensity, range: 0.0 }
+    }
+
+    pub fn point(position: Vec3, color: Vec3, intensity: f32, range: f32) -> Self {
+        Self { kind: LightKind::Point, position, direction: -Vec3::unit_y(), color, intensity, range }
+    }
+
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Self { kind: LightKind::Spot { inner_angle, outer_angle }, position, direction, color, intensity, range }
+    }
+}
+
+/// A light as the fragment shader sees it, std430 layout.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct LightUniform {
+    position: [f32; 3],
+    range: f32,
+    /// Towards the light for directional lights, away from it for spot lights.
+    direction: [f32; 3],
+    /// 0 for directional, 1 for point and 2 for spot lights.
+    kind: u32,
+    /// Premultiplied by the intensity.
+    color: [f32; 3],
+    /// Cosines of the spot cone angles, the outer one is -1.0 for every other light.
+    cos_inner: f32,
+    cos_outer: f32,
+    _padding: [f32; 3],
+}
+
+impl LightUniform {
+    fn new(light: &Light) -> Self {
+        let direction = light.direction.normalize();
+        let (kind, direction, cos_inner, cos_outer) = match light.kind {
+            LightKind::Directional => (0, -direction, 1.0, -1.0),
+            LightKind::Point => (1, direction, 1.0, -1.0),
+            LightKind::Spot { inner_angle, outer_angle } => {
+                // an empty transition band would divide by zero in the shader
+                let cos_outer = outer_angle.cos();
+                (2, direction, inner_angle.cos().max(cos_outer + 1e-4), cos_outer)
+            },
+        };
+        Self {
+            position: light.position.into(),
+            range: light.range.max(0.0),
+            direction: direction.into(),
+            kind,
+            color: (light.color * light.intensity).into(),
+            cos_inner,
+            cos_outer,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// Every light in the scene, ids stay valid until their light is removed.
+pub struct Lights {
+    lights: Vec<Option<Light>>,
+    free_slots: Vec<usize>,
+    /// Some light changed since the last `take_changed`, the renderer uses this to re-upload.
+    changed: bool,
+}
+
+impl Lights {
+    pub fn new() -> Self {
+        Self { lights: vec![], free_slots: vec![], changed: true }
+    }
+
+    pub fn add(&mut self, light: Light) -> LightId {
+        self.changed = true;
+        match self.free_slots.pop() {
+            Some(slot) => {
+                self.lights[slot] = Some(light);
+                LightId(slot)
+            },
+            None => {
+                self.lights.push(Some(light));
+                LightId(self.lights.len() - 1)
+            },
+        }
+    }
+
+    pub fn remove(&mut self, id: LightId) {
+        if self.lights[id.0].take().is_some() {
+            self.free_slots.push(id.0);
+            self.changed = true;
+        }
+    }
+
+    /// Anything changed through this shows up on the next render.
+    pub fn get_mut(&mut self, id: LightId) -> &mut Light {
+        self.changed = true;
+        self.lights[id.0].as_mut().expect("light was removed")
+    }
+
+    pub fn get_count(&self) -> usize {
+        self.lights.len() - self.free_slots.len()
+    }
+
+    /// Whether the lights changed since this was last called.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+
+    /// The lights packed for the shader, in no particular order.
+    pub fn to_uniforms(&self) -> Vec<LightUniform> {
+        self.lights.iter().flatten().map(LightUniform::new).collect()
+    }
+}
+
+impl Default for Lights {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_slots_are_reused_and_skipped() {
+        let mut lights = Lights::new();
+        let sun = lights.add(Light::directional(Vec3::new(0.0, -1.0, 0.0), Vec3::one(), 3.0));
+        let lamp = lights.add(Light::point(Vec3::new(0.0, 2.0, 0.0), Vec3::new(1.0, 0.5, 0.0), 2.0, 10.0));
+        assert!(lights.take_changed());
+        assert!(!lights.take_changed());
+
+        lights.remove(lamp);
+        assert!(lights.take_changed());
+        assert_eq!(lights.get_count(), 1);
+        let uniforms = lights.to_uniforms();
+        assert_eq!(uniforms.len(), 1);
+        // directional lights point back towards where the light comes from
+        assert_eq!(uniforms[0].direction, [0.0, 1.0, 0.0]);
+        assert_eq!(uniforms[0].color, [3.0, 3.0, 3.0]);
+
+        let torch = lights.add(Light::spot(Vec3::zero(), Vec3::unit_z(), Vec3::one(), 1.0, 0.0, 0.2, 0.2));
+        assert_eq!(torch, lamp);
+        lights.get_mut(sun).intensity = 1.0;
+        assert!(lights.take_changed());
+        let uniforms = lights.to_uniforms();
+        assert_eq!(uniforms.len(), 2);
+        assert_eq!(uniforms[1].kind, 2);
+        assert!(uniforms[1].cos_inner > uniforms[1].cos_outer);
+    }
+
+    #[test]
+    fn uniform_matches_the_shader_layout() {
+        assert_eq!(std::mem::size_of::<LightUniform>(), 64);
+    }
+}
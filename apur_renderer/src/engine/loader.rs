@@ -0,0 +1,207 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use super::model::{ColorSpace, Model, ModelData, ModelLoadError, TextureData, TextureRequest};
+use super::vfs::Vfs;
+
+enum Job {
+    /// `collider_scale` also builds the collision mesh, scaled by it.
+    Model { id: usize, name: String, collider_scale: Option<f32> },
+    Texture { id: usize, request: TextureRequest, color_space: ColorSpace },
+}
+
+/// Work finished by the loader threads, `id` is whatever the job was queued with.
+pub enum Loaded {
+    /// Parsed model, its textures are still in `ModelData::pending_textures`. The collision
+    /// mesh is there if it was asked for.
+    Model { id: usize, name: String, result: Result<Box<ModelData>, ModelLoadError> },
+    /// A decoded texture with its full mip chain, or why it couldn't be decoded. `path` is
+    /// what it's cached under, see `TextureRequest::cache_key`.
+    Texture {
+        id: usize,
+        index: usize,
+        name: String,
+        path: Option<String>,
+        color_space: ColorSpace,
+        result: Result<Vec<TextureData>, String>,
+    },
+}
+
+/// Parses models and decodes textures on a few background threads, results are picked
+/// up from the main thread with `poll` so the GPU uploads can be spread over frames.
+pub struct AssetLoader {
+    jobs: Option<mpsc::Sender<Job>>,
+    results: mpsc::Receiver<Loaded>,
+    workers: Vec<thread::JoinHandle<()>>,
+    /// Set on drop so the workers skip whatever is still queued.
+    cancelled: Arc<AtomicBool>,
+    queued: usize,
+    finished: usize,
+}
+
+impl AssetLoader {
+    const WORKER_COUNT: usize = 4;
+
+    /// Everything is read through `vfs`.
+    pub fn new(vfs: Arc<Vfs>) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let workers = (0..Self::WORKER_COUNT)
+            .map(|i| {
+                let job_receiver = Arc::clone(&job_receiver);
+                let result_sender = result_sender.clone();
+                let cancelled = Arc::clone(&cancelled);
+                let vfs = Arc::clone(&vfs);
+                thread::Builder::new()
+                    .name(format!("asset loader {}", i))
+                    .spawn(move || loop {
+                        // the lock is only held while waiting, not while working
+                        let job = job_receiver.lock().expect("asset loader queue poisoned").recv();
+                        let loaded = match job {
+                            Ok(job) if !cancelled.load(Ordering::Relaxed) => Self::run(&vfs, job),
+                            // the loader was dropped
+                            _ => break,
+                        };
+                        if result_sender.send(loaded).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("failed to spawn an asset loader thread")
+            })
+            .collect();
+
+        Self { jobs: Some(jobs), results, workers, cancelled, queued: 0, finished: 0 }
+    }
+
+    fn run(vfs: &Vfs, job: Job) -> Loaded {
+        match job {
+            Job::Model { id, name, collider_scale } => {
+                println!("[Info] Loading model: {}", name);
+                let result = Model::load_data(vfs, &name).map(|mut data| {
+                    data.collision_mesh = collider_scale.and_then(|scale| data.build_collision_mesh(scale));
+                    Box::new(data)
+                });
+                Loaded::Model { id, name, result }
+            },
+            Job::Texture { id, request, color_space } => {
+                let result = request.decode(vfs).map(|texture| {
+                    let mips = texture.mips(color_space);
+                    std::iter::once(texture).chain(mips).collect()
+                });
+                let path = request.cache_key();
+                Loaded::Texture { id, index: request.index, name: request.name, path, color_space, result }
+            },
+        }
+    }
+
+    fn queue(&mut self, job: Job) {
+        self.queued += 1;
+        self.jobs
+            .as_ref()
+            .expect("asset loader already shut down")
+            .send(job)
+            .expect("asset loader threads are gone");
+    }
+
+    /// Parses models/<name> like `Model::load_data` does, with its triangles as a collision
+    /// mesh scaled by `collider_scale` when there is one.
+    pub fn load_model(&mut self, id: usize, name: &str, collider_scale: Option<f32>) {
+        self.queue(Job::Model { id, name: name.to_string(), collider_scale });
+    }
+
+    /// Decodes a texture of a parsed model and builds its mips in `color_space`.
+    pub fn load_texture(&mut self, id: usize, request: TextureRequest, color_space: ColorSpace) {
+        self.queue(Job::Texture { id, request, color_space });
+    }
+
+    /// Finished work, at most `max` items so a frame never uploads too much at once.
+    pub fn poll(&mut self, max: usize) -> Vec<Loaded> {
+        let loaded: Vec<_> = self.results.try_iter().take(max).collect();
+        self.finished += loaded.len();
+        loaded
+    }
+
+    /// Finished and queued jobs so far.
+    pub fn get_progress(&self) -> (usize, usize) {
+        (self.finished, self.queued)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.finished == self.queued
+    }
+}
+
+impl Drop for AssetLoader {
+    fn drop(&mut self) {
+        // the workers stop once they're done with their current job
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// Polls until everything queued is back, the workers get a few seconds.
+    fn wait(loader: &mut AssetLoader) -> Vec<Loaded> {
+        let start = Instant::now();
+        let mut loaded = vec![];
+        while !loader.is_idle() {
+            assert!(start.elapsed() < Duration::from_secs(10), "asset loader never finished");
+            loaded.extend(loader.poll(usize::MAX));
+            thread::sleep(Duration::from_millis(1));
+        }
+        loaded
+    }
+
+    #[test]
+    fn models_and_textures_come_back_with_their_ids() {
+        let mut vfs = Vfs::new();
+        // embedded so no mesh cache gets written, the texture is left out on purpose
+        vfs.mount_embedded("models", &[
+            ("plane.obj", include_bytes!("../../res/models/plane.obj")),
+            ("plane.mtl", include_bytes!("../../res/models/plane.mtl")),
+        ]);
+        let mut loader = AssetLoader::new(Arc::new(vfs));
+        loader.load_model(3, "plane", Some(2.0));
+        loader.load_model(4, "missing", None);
+        assert_eq!(loader.get_progress(), (0, 2));
+
+        let mut texture = None;
+        for item in wait(&mut loader) {
+            match item {
+                Loaded::Model { id: 3, name, result } => {
+                    assert_eq!(name, "plane");
+                    let mut data = result.expect("plane.obj should parse");
+                    assert_eq!(data.indices.len(), 6);
+                    let collider = data.collision_mesh.as_ref().expect("a collider was asked for");
+                    assert_eq!(collider.get_triangle_count(), 2);
+                    assert_eq!(collider.get_aabb().max.x(), 2.0);
+                    let color_spaces = data.color_spaces();
+                    texture = data.take_pending_textures().pop().map(|request| (request, color_spaces));
+                },
+                Loaded::Model { id: 4, result, .. } => assert!(result.is_err()),
+                _ => panic!("unexpected result"),
+            }
+        }
+
+        let (request, color_spaces) = texture.expect("plane.obj has a diffuse map");
+        let index = request.index;
+        loader.load_texture(3, request, color_spaces[index]);
+        match &wait(&mut loader)[..] {
+            [Loaded::Texture { id: 3, index: i, color_space: ColorSpace::Srgb, result: Err(_), .. }] => assert_eq!(*i, index),
+            _ => panic!("expected the missing texture to fail to decode"),
+        }
+        assert_eq!(loader.get_progress(), (3, 3));
+    }
+}
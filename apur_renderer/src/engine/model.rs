@@ -1,25 +1,36 @@
-use std::io::Read;
-use std::fs::File;
-use std::collections::HashMap;
-use std::rc::Rc;
-
-#[derive(Clone, Copy, Default, Debug)]
-#[repr(C)]
-pub struct Vertex {
-    pos: [f32; 3],
-    tex_coords: [f32; 2],
-    normal: [f32; 3],
-}
+use glam::Vec3;
+
+mod cache;
+mod data;
+mod error;
+mod gltf_loader;
+mod material;
+mod obj_loader;
+mod tangents;
+
+pub use cache::bake_obj;
+pub use data::{AlphaMode, ColorSpace, LoadReport, ModelData, TextureData, TextureRequest, TextureSource, Vertex};
+pub use error::ModelLoadError;
+pub use material::{DefaultTextures, Material};
+use super::assets::{AssetManager, Handle, TextureHandle};
+use super::vfs::Vfs;
 
 pub struct Mesh {
     indices_offset: u32,
     indices_count: u32,
-    texture_view: Rc<wgpu::TextureView>,
+    material: usize,
+    center: Vec3,
 }
 
-impl Mesh {    
-    pub fn get_texture_view(&self) -> &wgpu::TextureView {
-        &self.texture_view
+impl Mesh {
+    /// Middle of the mesh's bounding box in model space, translucent meshes are sorted by it.
+    pub fn get_center(&self) -> Vec3 {
+        self.center
+    }
+
+    /// Index into `Model::get_materials`.
+    pub fn get_material(&self) -> usize {
+        self.material
     }
 
     pub fn get_indices_offset(&self) -> u32 {
@@ -35,143 +46,108 @@ pub struct Model {
     vertex_buffer: wgpu::Buffer,
     indices_buffer: wgpu::Buffer,
     meshes: Vec<Mesh>,
+    materials: Vec<Material>,
+    /// What the materials were loaded with, placeholders for textures still streaming in.
+    textures: Vec<TextureHandle>,
+    defaults: DefaultTextures,
+    report: LoadReport,
 }
 
 impl Model {
     
-    pub fn load_model(
+    /// Parses models/<name> from the `Vfs` without touching the GPU, so it can run on any
+    /// thread. Names ending in .gltf or .glb go through the glTF loader, anything else is read
+    /// as <name>.obj, from its binary cache when that's still up to date. Textures are left
+    /// in `ModelData::pending_textures`.
+    pub fn load_data(vfs: &Vfs, name: &str) -> Result<ModelData, ModelLoadError> {
+        if name.ends_with(".gltf") || name.ends_with(".glb") {
+            gltf_loader::load(vfs, &format!("models/{}", name))
+        } else {
+            cache::load_obj(vfs, name)
+        }
+    }
+
+    /// Uploads parsed model data to the GPU. Textures with a path are shared through
+    /// `assets`, pending ones should have been taken out first.
+    pub fn from_data(
         device: &wgpu::Device,
         queue: &mut wgpu::Queue,
-        obj_filename: &str,
+        data: ModelData,
+        assets: &mut AssetManager,
     ) -> Self {
-        let (models, mats) = tobj::load_obj(format!("res/models/{}.obj", obj_filename).as_ref()).expect("Failed to load the model");
-        
+        let color_spaces = data.color_spaces();
+        let ModelData { mut vertices, mut indices, meshes: mesh_data, materials: material_data, textures, texture_keys, report, .. } = data;
         let mut cmd_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
-        
-        let textured_models = models
+
+        let textures = textures
+            .iter()
+            .zip(texture_keys)
+            .zip(&color_spaces)
+            .map(|((texture, path), &color_space)| {
+                let key = path.map(|path| (path, color_space));
+                if let Some(shared) = key.as_ref().and_then(|key| assets.get_texture(key)) {
+                    return shared;
+                }
+                let view = AssetManager::create_texture(device, &mut cmd_encoder, texture, &texture.mips(color_space), color_space);
+                match key {
+                    Some(key) => assets.add_texture(key, view),
+                    None => Handle::new(view),
+                }
+            })
+            .collect();
+
+        let materials = material_data
+            .iter()
+            .map(|m| Material::new(device, m))
+            .collect();
+
+        let meshes = mesh_data
             .into_iter()
-            .filter(|m| {
-                let mat_idx = m.mesh.material_id.expect("no material associated");
-                !mats[mat_idx].diffuse_texture.is_empty()
-            });
-        
-        let mut indices = vec![];
-        let mut vertices = vec![];
-        let mut meshes = vec![];
-        
-        let mut texture_cache = HashMap::<String, Rc<wgpu::TextureView>>::new();
-        for m in textured_models {
-            let vs = m.mesh.positions;
-            let ts = m.mesh.texcoords;
-            let ns = m.mesh.normals;
-
-            assert_eq!(vs.len() / 3, ts.len() / 2, "positions and texcoords length not same");
-            assert_eq!(vs.len() / 3, ns.len() / 3, "positions and normals length not same");
-
-            let vertices_len = vertices.len();
-            vertices.extend(vs
-                .chunks(3)
-                .zip(ts.chunks(2))
-                .zip(ns.chunks(3))
-                .map(|((vs, ts), ns)| Vertex {
-                    pos: [vs[0], vs[1], vs[2]],
-                    tex_coords: [ts[0], ts[1]],
-                    normal: [ns[0], ns[1], ns[2]],
-                })
-            );
-
-            let indices_offset = indices.len() as u32;
-            let indices_count = m.mesh.indices.len() as u32;
-            
-            indices.extend(m.mesh.indices.into_iter().map(|idx| idx + vertices_len as u32));
-            
-            let mat_idx = m.mesh.material_id.expect("no material associated");
-            assert!(!mats[mat_idx].diffuse_texture.is_empty(), "diffuse texture path empty");
-            
-            // assumed texture_name includes the "texture/" in path name
-            let texture_name = &mats[mat_idx].diffuse_texture;
-            let texture_view = if texture_cache.contains_key(texture_name) {
-                texture_cache.get(texture_name).unwrap().clone()
-            } else {
-                println!("[Info] Loading texture: {}", texture_name);
-                let texture_image = {
-                    let mut image_file = File::open(format!("res/models/{}", texture_name)).expect("Failed to open texture image");
-                    let mut image_contents = vec![];
-                    let _ = image_file.read_to_end(&mut image_contents);
-                    
-                    let texture_image = image::load_from_memory(&image_contents)
-                        .or_else(|err| {
-                            if texture_name.ends_with(".tga") {
-                                image::load_from_memory_with_format(&image_contents, image::ImageFormat::TGA)
-                            } else {
-                                Err(err)
-                            }
-                        })
-                        .expect(&format!("failed to load a texture image: {}", texture_name));
-                    texture_image.into_rgba()
-                };
-                
-                let texture_extent = wgpu::Extent3d {
-                    width: texture_image.width(),
-                    height: texture_image.height(),
-                    depth: 1,
-                };
-
-                let texture = device.create_texture(&wgpu::TextureDescriptor {
-                    size: texture_extent,
-                    array_layer_count: 1,
-                    mip_level_count: 1,
-                    sample_count: 1,
-                    dimension: wgpu::TextureDimension::D2,
-                    format: wgpu::TextureFormat::Rgba8Unorm,
-                    usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-                });
-            
-                let image_width = texture_image.width();
-                let image_height = texture_image.height();
-                let image_data = texture_image.into_vec();
-                let image_buf = device
-                    .create_buffer_mapped(image_data.len(), wgpu::BufferUsage::COPY_SRC)
-                    .fill_from_slice(&image_data);
-            
-                cmd_encoder.copy_buffer_to_texture(
-                    wgpu::BufferCopyView {
-                        buffer: &image_buf,
-                        offset: 0,
-                        row_pitch: 4 * image_width,
-                        image_height,
-                    },
-                    wgpu::TextureCopyView {
-                        texture: &texture,
-                        mip_level: 0,
-                        array_layer: 0,
-                        origin: wgpu::Origin3d { x: 0f32, y: 0f32, z: 0f32 },
-                    },
-                    texture_extent
+            .map(|m| {
+                let range = m.indices_offset as usize..(m.indices_offset + m.indices_count) as usize;
+                let positions = indices[range].iter().map(|&i| Vec3::from(vertices[i as usize].get_pos()));
+                let (min, max) = positions.fold(
+                    (Vec3::splat(std::f32::MAX), Vec3::splat(std::f32::MIN)),
+                    |(min, max), p| (min.min(p), max.max(p)),
                 );
-
-                let view = Rc::new(texture.create_default_view());
-                texture_cache.insert(texture_name.to_string(), view.clone());
-                view
-            };
-        
-            meshes.push(Mesh {
-                indices_offset,
-                indices_count,
-                texture_view,
-            });
-        }
+                Mesh {
+                    indices_offset: m.indices_offset,
+                    indices_count: m.indices_count,
+                    material: m.material,
+                    center: if m.indices_count == 0 { Vec3::zero() } else { (min + max) * 0.5 },
+                }
+            })
+            .collect();
 
         queue.submit(&[cmd_encoder.finish()]);
 
+        // wgpu doesn't like empty buffers, a degenerate triangle keeps models without meshes valid
+        if vertices.is_empty() {
+            vertices.push(Vertex::default());
+            indices.extend_from_slice(&[0, 0, 0]);
+        }
+
         let indices_buffer = device
             .create_buffer_mapped(indices.len(), wgpu::BufferUsage::INDEX)
             .fill_from_slice(&indices);
         let vertex_buffer = device
             .create_buffer_mapped(vertices.len(), wgpu::BufferUsage::VERTEX)
             .fill_from_slice(&vertices);
-        
-        Self { indices_buffer, vertex_buffer, meshes }
+
+        Self {
+            indices_buffer,
+            vertex_buffer,
+            meshes,
+            materials,
+            textures,
+            defaults: assets.get_defaults().clone(),
+            report,
+        }
+    }
+
+    /// Textures that were replaced and meshes that were left out while loading.
+    pub fn get_load_report(&self) -> &LoadReport {
+        &self.report
     }
 
     pub fn get_indices_buffer(&self) -> &wgpu::Buffer {
@@ -185,4 +161,17 @@ impl Model {
     pub fn get_meshes(&self) -> &[Mesh] {
         &self.meshes
     }
-}
\ No newline at end of file
+
+    pub fn get_materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    /// Textures the materials index into, as they were when the model was uploaded.
+    pub fn get_textures(&self) -> &[TextureHandle] {
+        &self.textures
+    }
+
+    pub fn get_defaults(&self) -> &DefaultTextures {
+        &self.defaults
+    }
+}
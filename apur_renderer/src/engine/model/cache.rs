@@ -0,0 +1,376 @@
+use std::convert::TryInto;
+use std::path::PathBuf;
+
+use super::super::vfs::Vfs;
+use super::data::{AlphaMode, LoadReport, MaterialData, MeshData, ModelData, TextureSource, Vertex};
+use super::error::ModelLoadError;
+use super::obj_loader;
+
+/// Parsed models are cached next to their source as <source>.apm.
+pub const EXTENSION: &str = "apm";
+
+const MAGIC: &[u8; 4] = b"APM\0";
+/// Bumped whenever the layout below or what the loaders produce changes.
+const VERSION: u32 = 2;
+
+/// Loads models/<name>.obj from its cache when that was built from the same obj and
+/// materials, otherwise parses the obj and writes a new cache if the obj is on disk.
+pub fn load_obj(vfs: &Vfs, name: &str) -> Result<ModelData, ModelLoadError> {
+    let path = format!("models/{}.obj", name);
+    let bytes = vfs.read(&path).map_err(|source| ModelLoadError::Io { path: path.clone(), source })?;
+    let hash = source_hash(vfs, &path, &bytes);
+    let cache_path = format!("{}.{}", path, EXTENSION);
+    if let Ok(cached) = vfs.read(&cache_path) {
+        match read(&cached, hash) {
+            Ok(data) => return Ok(data),
+            Err(reason) => println!("[Info] Rebuilding {}: {}", cache_path, reason),
+        }
+    }
+
+    let data = obj_loader::parse(vfs, &path, &bytes)?;
+    // objs in archives are expected to ship with their cache
+    if vfs.disk_path(&path).is_some() {
+        match store(vfs, &path, &data, hash) {
+            Ok(cache) => println!("[Info] Wrote mesh cache: {}", cache.display()),
+            Err(err) => println!("[Error] Failed to write mesh cache for {}: {}", path, err),
+        }
+    }
+    Ok(data)
+}
+
+/// Parses models/<name>.obj and writes its cache whether or not there already is one,
+/// returns where it went.
+pub fn bake_obj(vfs: &Vfs, name: &str) -> Result<PathBuf, String> {
+    let path = format!("models/{}.obj", name);
+    let bytes = vfs.read(&path).map_err(|err| err.to_string())?;
+    let data = obj_loader::parse(vfs, &path, &bytes).map_err(|err| err.to_string())?;
+    store(vfs, &path, &data, source_hash(vfs, &path, &bytes))
+}
+
+/// Writes the cache next to the obj at `path`, which has to be on disk.
+fn store(vfs: &Vfs, path: &str, data: &ModelData, hash: u64) -> Result<PathBuf, String> {
+    let source = vfs.disk_path(path).ok_or("the obj isn't in a directory mount")?;
+    let cache = write(data, hash).ok_or("the model has embedded textures")?;
+    let cache_path = source.with_extension(format!("obj.{}", EXTENSION));
+    std::fs::write(&cache_path, cache).map_err(|err| err.to_string())?;
+    Ok(cache_path)
+}
+
+/// Hash of an obj and every material library it uses, the cache is stale once it changes.
+fn source_hash(vfs: &Vfs, path: &str, bytes: &[u8]) -> u64 {
+    let mut hash = fnv1a(FNV_OFFSET, bytes);
+    for mtl in obj_loader::material_libraries(path, bytes) {
+        // a missing library hashes differently from an empty one
+        hash = match vfs.read(&mtl) {
+            Ok(mtl) => fnv1a(fnv1a(hash, &[1]), &mtl),
+            Err(_) => fnv1a(hash, &[0]),
+        };
+    }
+    hash
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// 64 bit FNV-1a, stable across platforms and compiler versions unlike `DefaultHasher`.
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Serializes a freshly parsed model, `None` if it has textures embedded in the source.
+pub fn write(data: &ModelData, source_hash: u64) -> Option<Vec<u8>> {
+    let mut out = Writer(Vec::with_capacity(data.vertices.len() * std::mem::size_of::<Vertex>() + data.indices.len() * 4));
+    out.0.extend_from_slice(MAGIC);
+    out.u32(VERSION);
+    out.u64(source_hash);
+
+    out.u32(data.vertices.len() as u32);
+    for vertex in &data.vertices {
+        out.f32s(&vertex.get_pos());
+        out.f32s(&vertex.get_tex_coords());
+        out.f32s(&vertex.get_normal());
+        out.f32s(&vertex.get_tangent());
+    }
+    out.u32(data.indices.len() as u32);
+    for &index in &data.indices {
+        out.u32(index);
+    }
+
+    out.u32(data.meshes.len() as u32);
+    for mesh in &data.meshes {
+        out.str(&mesh.name);
+        out.u32(mesh.indices_offset);
+        out.u32(mesh.indices_count);
+        out.u32(mesh.material as u32);
+    }
+
+    out.u32(data.materials.len() as u32);
+    for material in &data.materials {
+        // destructured so a new field can't be forgotten here
+        let MaterialData {
+            name, diffuse_texture, normal_texture, specular_texture, bump_texture, bump_scale,
+            alpha_texture, alpha_mode, alpha_cutoff, base_color_factor, ambient_color, specular_color,
+            shininess, metallic_roughness_texture, metallic_factor, roughness_factor,
+        } = material;
+        out.str(name);
+        for &texture in &[diffuse_texture, normal_texture, specular_texture, bump_texture, alpha_texture, metallic_roughness_texture] {
+            out.u32(texture.map_or(u32::MAX, |idx| idx as u32));
+        }
+        out.u32(match alpha_mode {
+            AlphaMode::Opaque => 0,
+            AlphaMode::Mask => 1,
+            AlphaMode::Blend => 2,
+        });
+        out.f32s(&[*bump_scale, *alpha_cutoff, *shininess, *metallic_factor, *roughness_factor]);
+        out.f32s(base_color_factor);
+        out.f32s(ambient_color);
+        out.f32s(specular_color);
+    }
+
+    // only references are stored, textures are decoded from their own files as usual
+    if data.pending_textures.len() != data.textures.len() {
+        return None;
+    }
+    out.u32(data.pending_textures.len() as u32);
+    for (idx, request) in data.pending_textures.iter().enumerate() {
+        match &request.source {
+            TextureSource::File(path) if request.index == idx => {
+                out.str(&request.name);
+                out.str(path);
+            },
+            _ => return None,
+        }
+    }
+
+    for list in &[&data.report.substituted_textures, &data.report.skipped_meshes] {
+        out.u32(list.len() as u32);
+        for (name, reason) in list.iter() {
+            out.str(name);
+            out.str(reason);
+        }
+    }
+    Some(out.0)
+}
+
+/// Reads back what `write` produced, errors say why the cache can't be used.
+pub fn read(bytes: &[u8], source_hash: u64) -> Result<ModelData, String> {
+    let mut input = Reader(bytes);
+    if input.take(4)? != MAGIC {
+        return Err(String::from("not a mesh cache"));
+    }
+    let version = input.u32()?;
+    if version != VERSION {
+        return Err(format!("written by format version {}, expected {}", version, VERSION));
+    }
+    if input.u64()? != source_hash {
+        return Err(String::from("the source changed"));
+    }
+
+    let mut data = ModelData::default();
+    let vertex_count = input.u32()? as usize;
+    data.vertices.reserve(vertex_count.min(input.0.len() / std::mem::size_of::<Vertex>()));
+    for _ in 0..vertex_count {
+        let mut vertex = Vertex::new(input.f32s()?, input.f32s()?, input.f32s()?);
+        vertex.set_tangent(input.f32s()?);
+        data.vertices.push(vertex);
+    }
+    let index_count = input.u32()? as usize;
+    data.indices = input
+        .take(index_count.checked_mul(4).ok_or("too many indices")?)?
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+
+    for _ in 0..input.u32()? {
+        data.meshes.push(MeshData {
+            name: input.str()?,
+            indices_offset: input.u32()?,
+            indices_count: input.u32()?,
+            material: input.u32()? as usize,
+        });
+    }
+
+    for _ in 0..input.u32()? {
+        let name = input.str()?;
+        let mut texture = || input.u32().map(|idx| if idx == u32::MAX { None } else { Some(idx as usize) });
+        let (diffuse_texture, normal_texture, specular_texture) = (texture()?, texture()?, texture()?);
+        let (bump_texture, alpha_texture, metallic_roughness_texture) = (texture()?, texture()?, texture()?);
+        let alpha_mode = match input.u32()? {
+            0 => AlphaMode::Opaque,
+            1 => AlphaMode::Mask,
+            2 => AlphaMode::Blend,
+            mode => return Err(format!("unknown alpha mode {}", mode)),
+        };
+        let [bump_scale, alpha_cutoff, shininess, metallic_factor, roughness_factor] = input.f32s()?;
+        data.materials.push(MaterialData {
+            name,
+            diffuse_texture,
+            normal_texture,
+            specular_texture,
+            bump_texture,
+            bump_scale,
+            alpha_texture,
+            alpha_mode,
+            alpha_cutoff,
+            base_color_factor: input.f32s()?,
+            ambient_color: input.f32s()?,
+            specular_color: input.f32s()?,
+            shininess,
+            metallic_roughness_texture,
+            metallic_factor,
+            roughness_factor,
+        });
+    }
+
+    for _ in 0..input.u32()? {
+        let name = input.str()?;
+        let path = input.str()?;
+        data.request_texture(&name, TextureSource::File(path));
+    }
+
+    let mut report = LoadReport::default();
+    for list in &mut [&mut report.substituted_textures, &mut report.skipped_meshes] {
+        for _ in 0..input.u32()? {
+            list.push((input.str()?, input.str()?));
+        }
+    }
+    data.report = report;
+
+    if !input.0.is_empty() {
+        return Err(String::from("trailing bytes"));
+    }
+    // the renderer trusts these, a corrupt cache mustn't index out of bounds
+    let texture_count = data.textures.len();
+    let texture_out_of_range = |m: &MaterialData| {
+        [m.diffuse_texture, m.normal_texture, m.specular_texture, m.bump_texture, m.alpha_texture, m.metallic_roughness_texture]
+            .iter()
+            .any(|&texture| texture.is_some_and(|idx| idx >= texture_count))
+    };
+    if data.materials.iter().any(texture_out_of_range) {
+        return Err(String::from("textures out of range"));
+    }
+    let vertex_count = data.vertices.len();
+    if data.indices.iter().any(|&i| i as usize >= vertex_count)
+        || data.meshes.iter().any(|m| {
+            m.material >= data.materials.len() || m.indices_offset as usize + m.indices_count as usize > data.indices.len()
+        })
+    {
+        return Err(String::from("indices out of range"));
+    }
+    Ok(data)
+}
+
+/// Little endian writer for the cache.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.0.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+}
+
+/// The rest of the cache still to be read.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.0.len() {
+            return Err(String::from("truncated"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32s<const N: usize>(&mut self) -> Result<[f32; N], String> {
+        let mut values = [0.0; N];
+        for value in &mut values {
+            *value = f32::from_le_bytes(self.take(4)?.try_into().unwrap());
+        }
+        Ok(values)
+    }
+
+    fn str(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| String::from("invalid string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_obj(name: &str) -> (ModelData, Vec<u8>) {
+        let mut vfs = Vfs::new();
+        vfs.mount_dir("", concat!(env!("CARGO_MANIFEST_DIR"), "/res"));
+        let path = format!("models/{}.obj", name);
+        let bytes = vfs.read(&path).unwrap();
+        let hash = source_hash(&vfs, &path, &bytes);
+        let data = obj_loader::parse(&vfs, &path, &bytes).unwrap();
+        let cache = write(&data, hash).unwrap();
+        (data, cache)
+    }
+
+    #[test]
+    fn cached_model_matches_the_parsed_one() {
+        let (parsed, cache) = parse_obj("cube");
+        let hash = u64::from_le_bytes(cache[8..16].try_into().unwrap());
+        let cached = read(&cache, hash).unwrap();
+
+        assert_eq!(cached.vertices, parsed.vertices);
+        assert_eq!(cached.indices, parsed.indices);
+        assert_eq!(cached.meshes.len(), parsed.meshes.len());
+        for (a, b) in cached.meshes.iter().zip(&parsed.meshes) {
+            assert_eq!((&a.name, a.indices_offset, a.indices_count, a.material), (&b.name, b.indices_offset, b.indices_count, b.material));
+        }
+        assert_eq!(cached.materials.len(), parsed.materials.len());
+        for (a, b) in cached.materials.iter().zip(&parsed.materials) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.diffuse_texture, b.diffuse_texture);
+            assert_eq!(a.alpha_mode, b.alpha_mode);
+            assert_eq!(a.base_color_factor, b.base_color_factor);
+            assert_eq!(a.specular_color, b.specular_color);
+        }
+        assert_eq!(cached.texture_keys, parsed.texture_keys);
+    }
+
+    #[test]
+    fn stale_or_broken_caches_are_rejected() {
+        let (_, cache) = parse_obj("plane");
+        let hash = u64::from_le_bytes(cache[8..16].try_into().unwrap());
+        assert!(read(&cache, hash).is_ok());
+        assert_eq!(read(&cache, hash ^ 1).err().unwrap(), "the source changed");
+        assert!(read(&cache[..cache.len() - 1], hash).is_err());
+        assert!(read(b"APM\0", hash).is_err());
+
+        let mut old = cache.clone();
+        old[4] = 0;
+        assert!(read(&old, hash).is_err());
+    }
+}
@@ -0,0 +1,494 @@
+use std::borrow::Cow;
+
+use apur_physics::TriMesh;
+use glam::Vec3;
+
+use super::super::vfs::Vfs;
+use super::error::ModelLoadError;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+#[repr(C)]
+pub struct Vertex {
+    pos: [f32; 3],
+    tex_coords: [f32; 2],
+    normal: [f32; 3],
+    /// Direction of increasing u, w is the sign of the bitangent as in MikkTSpace.
+    tangent: [f32; 4],
+}
+
+impl Vertex {
+    pub fn new(pos: [f32; 3], tex_coords: [f32; 2], normal: [f32; 3]) -> Self {
+        Self { pos, tex_coords, normal, tangent: [0.0, 0.0, 0.0, 1.0] }
+    }
+
+    pub fn get_pos(&self) -> [f32; 3] {
+        self.pos
+    }
+
+    pub fn get_tex_coords(&self) -> [f32; 2] {
+        self.tex_coords
+    }
+
+    pub fn get_normal(&self) -> [f32; 3] {
+        self.normal
+    }
+
+    pub fn get_tangent(&self) -> [f32; 4] {
+        self.tangent
+    }
+
+    pub fn set_tangent(&mut self, tangent: [f32; 4]) {
+        self.tangent = tangent;
+    }
+
+    /// Direction of increasing v, rebuilt from the normal and tangent the way the shader does it.
+    pub fn get_bitangent(&self) -> [f32; 3] {
+        let [x, y, z, w] = self.tangent;
+        let b = Vec3::from(self.normal).cross(Vec3::new(x, y, z)) * w;
+        [b.x(), b.y(), b.z()]
+    }
+}
+
+/// Decoded RGBA8 image, ready to be copied into a texture.
+pub struct TextureData {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl TextureData {
+    const CHECKER_SIZE: u32 = 64;
+    const CHECKER_CELL: u32 = 8;
+
+    /// 1x1 texture of a single color.
+    pub fn solid(name: &str, rgba: [u8; 4]) -> Self {
+        Self { name: name.to_string(), width: 1, height: 1, pixels: rgba.to_vec() }
+    }
+
+    /// Stands in for a texture that is still being decoded, gray for colors and a flat
+    /// normal for data so normal maps don't tilt anything in the meantime.
+    pub fn placeholder(name: &str, color_space: ColorSpace) -> Self {
+        match color_space {
+            ColorSpace::Srgb => Self::solid(name, [128, 128, 128, 255]),
+            ColorSpace::Linear => Self::solid(name, [128, 128, 255, 255]),
+        }
+    }
+
+    /// Magenta and black squares standing in for a texture that couldn't be loaded.
+    pub fn checkerboard(name: &str) -> Self {
+        let size = Self::CHECKER_SIZE;
+        let pixels = (0..size * size)
+            .flat_map(|i| {
+                let (x, y) = (i % size / Self::CHECKER_CELL, i / size / Self::CHECKER_CELL);
+                if (x + y) % 2 == 0 { vec![255, 0, 255, 255] } else { vec![0, 0, 0, 255] }
+            })
+            .collect();
+        Self { name: name.to_string(), width: size, height: size, pixels }
+    }
+
+    /// Number of levels in a full mip chain down to 1x1.
+    pub fn mip_level_count(&self) -> u32 {
+        32 - self.width.max(self.height).max(1).leading_zeros()
+    }
+
+    /// Every level below this one, halving the size each time until 1x1.
+    pub fn mips(&self, color_space: ColorSpace) -> Vec<TextureData> {
+        let mut mips: Vec<TextureData> = vec![];
+        for _ in 1..self.mip_level_count() {
+            let next = mips.last().unwrap_or(self).downsample(color_space);
+            mips.push(next);
+        }
+        mips
+    }
+
+    /// Half size copy where each pixel averages the block it covers, odd sizes give some
+    /// pixels a wider block instead of dropping a row. Colors are averaged in linear space
+    /// and weighted by alpha so the color of transparent texels doesn't bleed into cutout edges.
+    fn downsample(&self, color_space: ColorSpace) -> TextureData {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut pixels = Vec::with_capacity((4 * width * height) as usize);
+        for y in 0..height {
+            let rows = y * self.height / height..(y + 1) * self.height / height;
+            for x in 0..width {
+                let columns = x * self.width / width..(x + 1) * self.width / width;
+                let (mut weighted, mut plain, mut alpha, mut count) = ([0.0f32; 3], [0.0f32; 3], 0.0, 0.0);
+                for sy in rows.clone() {
+                    for sx in columns.clone() {
+                        let i = (4 * (sy * self.width + sx)) as usize;
+                        let a = f32::from(self.pixels[i + 3]) / 255.0;
+                        for c in 0..3 {
+                            let value = color_space.decode(self.pixels[i + c]);
+                            weighted[c] += value * a;
+                            plain[c] += value;
+                        }
+                        alpha += a;
+                        count += 1.0;
+                    }
+                }
+                for c in 0..3 {
+                    let value = if alpha > 0.0 { weighted[c] / alpha } else { plain[c] / count };
+                    pixels.push(color_space.encode(value));
+                }
+                pixels.push((alpha / count * 255.0).round() as u8);
+            }
+        }
+        TextureData { name: self.name.clone(), width, height, pixels }
+    }
+}
+
+/// How the color channels of a texture are stored, colors are sRGB while normals,
+/// heights and masks are plain data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    /// Linear value of a stored 8 bit channel.
+    pub fn decode(self, value: u8) -> f32 {
+        let value = f32::from(value) / 255.0;
+        match self {
+            ColorSpace::Srgb if value <= 0.04045 => value / 12.92,
+            ColorSpace::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+            ColorSpace::Linear => value,
+        }
+    }
+
+    fn encode(self, value: f32) -> u8 {
+        let value = match self {
+            ColorSpace::Srgb if value <= 0.003_130_8 => value * 12.92,
+            ColorSpace::Srgb => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+            ColorSpace::Linear => value,
+        };
+        (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+}
+
+/// Where the encoded bytes of a texture come from.
+pub enum TextureSource {
+    /// Path in the `Vfs`.
+    File(String),
+    Bytes(Vec<u8>),
+}
+
+/// A texture a model refers to that hasn't been decoded yet, it's up to whoever loads
+/// the model when and on which thread that happens.
+pub struct TextureRequest {
+    /// Index into `ModelData::textures`, which holds a placeholder until then.
+    pub index: usize,
+    pub name: String,
+    pub source: TextureSource,
+}
+
+impl TextureRequest {
+    /// Path the texture is shared under, images embedded in a model belong to that model alone.
+    pub fn cache_key(&self) -> Option<String> {
+        match &self.source {
+            TextureSource::File(path) => Some(path.clone()),
+            TextureSource::Bytes(_) => None,
+        }
+    }
+
+    /// Reads and decodes the image, errors are meant for the `LoadReport`.
+    pub fn decode(&self, vfs: &Vfs) -> Result<TextureData, String> {
+        println!("[Info] Loading texture: {}", self.name);
+        let bytes = match &self.source {
+            TextureSource::File(path) => Cow::Owned(vfs.read(path).map_err(|err| err.to_string())?),
+            TextureSource::Bytes(bytes) => Cow::Borrowed(bytes),
+        };
+        let image = image::load_from_memory(&bytes).or_else(|err| {
+            // tga has no magic number so it can't be guessed from the contents
+            if self.name.ends_with(".tga") {
+                image::load_from_memory_with_format(&bytes, image::ImageFormat::TGA)
+            } else {
+                Err(err)
+            }
+        });
+        let image = image.map_err(|err| err.to_string())?.into_rgba();
+        Ok(TextureData { name: self.name.clone(), width: image.width(), height: image.height(), pixels: image.into_vec() })
+    }
+}
+
+/// How a material's coverage is used, opaque and masked meshes are drawn first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Fragments with coverage below `MaterialData::alpha_cutoff` are discarded.
+    Mask,
+    /// Blended over whatever is behind it, drawn back to front after everything else.
+    Blend,
+}
+
+pub struct MaterialData {
+    pub name: String,
+    /// Texture fields index into `ModelData::textures`.
+    pub diffuse_texture: Option<usize>,
+    /// Tangent space normals.
+    pub normal_texture: Option<usize>,
+    /// Multiplies `specular_color`.
+    pub specular_texture: Option<usize>,
+    /// Height map, only used when there is no normal map.
+    pub bump_texture: Option<usize>,
+    /// Strength of the bump or normal map.
+    pub bump_scale: f32,
+    /// Dissolve map, coverage is read from the red channel.
+    pub alpha_texture: Option<usize>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    /// Kd and dissolve for OBJ, only used when there is no diffuse texture.
+    pub base_color_factor: [f32; 4],
+    /// Ka, black is treated as white since most exporters write it that way.
+    pub ambient_color: [f32; 3],
+    /// Ks and Ns for Blinn-Phong.
+    pub specular_color: [f32; 3],
+    pub shininess: f32,
+    /// Metalness in the blue channel and roughness in the green one, as glTF lays it out.
+    /// Multiplies the factors, which OBJ derives from Ks and Ns.
+    pub metallic_roughness_texture: Option<usize>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+}
+
+impl Default for MaterialData {
+    fn default() -> Self {
+        Self {
+            name: String::from("default"),
+            diffuse_texture: None,
+            normal_texture: None,
+            specular_texture: None,
+            bump_texture: None,
+            bump_scale: 1.0,
+            alpha_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            base_color_factor: [0.8, 0.8, 0.8, 1.0],
+            ambient_color: [0.0; 3],
+            specular_color: [0.0; 3],
+            shininess: 1.0,
+            metallic_roughness_texture: None,
+            metallic_factor: 0.0,
+            roughness_factor: 1.0,
+        }
+    }
+}
+
+/// Range of `ModelData::indices` drawn with one material.
+pub struct MeshData {
+    pub name: String,
+    pub indices_offset: u32,
+    pub indices_count: u32,
+    /// Index into `ModelData::materials`.
+    pub material: usize,
+}
+
+/// What was left out or replaced while loading a model that still loaded fine.
+#[derive(Default, Debug)]
+pub struct LoadReport {
+    /// Texture name and why the checkerboard was used instead.
+    pub substituted_textures: Vec<(String, String)>,
+    /// Mesh name and why it won't be drawn.
+    pub skipped_meshes: Vec<(String, String)>,
+}
+
+impl LoadReport {
+    pub fn is_empty(&self) -> bool {
+        self.substituted_textures.is_empty() && self.skipped_meshes.is_empty()
+    }
+
+    pub fn substitute_texture(&mut self, name: &str, reason: String) -> TextureData {
+        self.substituted_textures.push((name.to_string(), reason));
+        TextureData::checkerboard(name)
+    }
+
+    pub fn skip_mesh(&mut self, name: &str, reason: &str) {
+        self.skipped_meshes.push((name.to_string(), reason.to_string()));
+    }
+}
+
+/// Everything needed to build a `Model`, parsed without touching the GPU.
+#[derive(Default)]
+pub struct ModelData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+    pub textures: Vec<TextureData>,
+    /// Path each texture is cached under once uploaded, see `TextureRequest::cache_key`.
+    pub texture_keys: Vec<Option<String>>,
+    /// Textures still to be decoded.
+    pub pending_textures: Vec<TextureRequest>,
+    /// Only built for models the physics world collides with, see `build_collision_mesh`.
+    pub collision_mesh: Option<TriMesh>,
+    pub report: LoadReport,
+}
+
+impl ModelData {
+    /// Appends vertices and their triangle list, the indices are local to `vertices`.
+    pub fn push_mesh(&mut self, name: &str, vertices: Vec<Vertex>, indices: &[u32], material: usize) {
+        let base = self.vertices.len() as u32;
+        self.meshes.push(MeshData {
+            name: name.to_string(),
+            indices_offset: self.indices.len() as u32,
+            indices_count: indices.len() as u32,
+            material,
+        });
+        self.indices.extend(indices.iter().map(|&i| i + base));
+        self.vertices.extend(vertices);
+    }
+
+    /// Adds a texture that gets decoded later, the returned index is valid right away.
+    pub fn request_texture(&mut self, name: &str, source: TextureSource) -> usize {
+        let index = self.textures.len();
+        let request = TextureRequest { index, name: name.to_string(), source };
+        self.push_texture(TextureData::placeholder(name, ColorSpace::Srgb));
+        self.texture_keys[index] = request.cache_key();
+        self.pending_textures.push(request);
+        index
+    }
+
+    /// Adds a texture that is already decoded, it isn't shared with other models.
+    pub fn push_texture(&mut self, texture: TextureData) -> usize {
+        self.textures.push(texture);
+        self.texture_keys.push(None);
+        self.textures.len() - 1
+    }
+
+    /// Hands out the pending textures, leaving placeholders that suit how each one is used.
+    /// The placeholders aren't cached under the texture's path.
+    pub fn take_pending_textures(&mut self) -> Vec<TextureRequest> {
+        let color_spaces = self.color_spaces();
+        for request in &self.pending_textures {
+            self.textures[request.index] = TextureData::placeholder(&request.name, color_spaces[request.index]);
+            self.texture_keys[request.index] = None;
+        }
+        std::mem::take(&mut self.pending_textures)
+    }
+
+    /// Diffuse and specular maps hold colors, every other texture is sampled as plain data.
+    pub fn color_spaces(&self) -> Vec<ColorSpace> {
+        let mut color_spaces = vec![ColorSpace::Linear; self.textures.len()];
+        for material in &self.materials {
+            for &idx in material.diffuse_texture.iter().chain(&material.specular_texture) {
+                color_spaces[idx] = ColorSpace::Srgb;
+            }
+        }
+        color_spaces
+    }
+
+    /// Every triangle scaled by `scale`, as static collision geometry. None when no triangle
+    /// has any area, there would be nothing to stand on. Builds a BVH so it belongs on a loader thread.
+    pub fn build_collision_mesh(&self, scale: f32) -> Option<TriMesh> {
+        let positions: Vec<Vec3> = self.vertices.iter().map(|v| Vec3::from(v.get_pos()) * scale).collect();
+        let has_area = self.indices.chunks_exact(3).any(|tri| {
+            let (a, b, c) = (positions[tri[0] as usize], positions[tri[1] as usize], positions[tri[2] as usize]);
+            (b - a).cross(c - a).length_squared() > 0.0
+        });
+        if has_area { Some(TriMesh::new(positions, &self.indices)) } else { None }
+    }
+
+    /// Index of a material nothing was assigned to, added on first use.
+    pub fn default_material(&mut self) -> usize {
+        match self.materials.iter().position(|m| m.name == "default") {
+            Some(idx) => idx,
+            None => {
+                self.materials.push(MaterialData::default());
+                self.materials.len() - 1
+            },
+        }
+    }
+}
+
+/// Makes sure every index of a mesh refers to one of its `vertex_count` vertices.
+pub fn check_indices(mesh: &str, indices: &[u32], vertex_count: usize) -> Result<(), ModelLoadError> {
+    match indices.iter().find(|&&i| i as usize >= vertex_count) {
+        Some(&index) => Err(ModelLoadError::IndexOutOfRange { mesh: mesh.to_string(), index, vertex_count }),
+        None => Ok(()),
+    }
+}
+
+/// Area weighted vertex normals for meshes exported without any.
+pub fn smooth_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let position = |i: u32| Vec3::from(positions[i as usize]);
+    let mut normals = vec![Vec3::zero(); positions.len()];
+    for tri in indices.chunks_exact(3) {
+        let (a, b, c) = (position(tri[0]), position(tri[1]), position(tri[2]));
+        // the cross product length is twice the area, which gives the weighting for free
+        let face_normal = (b - a).cross(c - a);
+        for &i in tri {
+            normals[i as usize] += face_normal;
+        }
+    }
+    normals
+        .into_iter()
+        .map(|n| {
+            let n = if n.length_squared() > 0.0 { n.normalize() } else { Vec3::unit_y() };
+            [n.x(), n.y(), n.z()]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_chain_goes_down_to_one_pixel() {
+        let texture = TextureData { name: String::from("odd"), width: 5, height: 3, pixels: vec![255; 4 * 5 * 3] };
+        assert_eq!(texture.mip_level_count(), 3);
+        let sizes: Vec<_> = texture.mips(ColorSpace::Linear).iter().map(|m| (m.width, m.height)).collect();
+        assert_eq!(sizes, vec![(2, 1), (1, 1)]);
+        assert!(texture.mips(ColorSpace::Linear).iter().all(|m| m.pixels.iter().all(|&p| p == 255)));
+        assert_eq!(TextureData::checkerboard("checker").mip_level_count(), 7);
+    }
+
+    #[test]
+    fn transparent_texels_keep_their_color_out_of_mips() {
+        // an opaque red texel next to transparent black ones
+        let pixels = vec![255, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let texture = TextureData { name: String::from("cutout"), width: 2, height: 2, pixels };
+        assert_eq!(texture.mips(ColorSpace::Linear)[0].pixels, vec![255, 0, 0, 64]);
+    }
+
+    #[test]
+    fn srgb_mips_are_averaged_in_linear_space() {
+        // black and white stripes average to a linear half gray, not to 128
+        let pixels = vec![0, 0, 0, 255, 255, 255, 255, 255];
+        let texture = TextureData { name: String::from("stripes"), width: 2, height: 1, pixels };
+        assert_eq!(texture.mips(ColorSpace::Srgb)[0].pixels, vec![188, 188, 188, 255]);
+        assert_eq!(texture.mips(ColorSpace::Linear)[0].pixels, vec![128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn only_texture_files_are_shared() {
+        let mut data = ModelData::default();
+        let file = data.request_texture("wall.png", TextureSource::File(String::from("models/textures/wall.png")));
+        let embedded = data.request_texture("image 0", TextureSource::Bytes(vec![]));
+        let decoded = data.push_texture(TextureData::checkerboard("broken"));
+        assert_eq!(data.texture_keys[file].as_deref(), Some("models/textures/wall.png"));
+        assert_eq!(data.texture_keys[embedded], None);
+        assert_eq!(data.texture_keys[decoded], None);
+
+        // placeholders must never end up cached under the real texture's path
+        let pending = data.take_pending_textures();
+        assert_eq!(pending[0].cache_key().as_deref(), Some("models/textures/wall.png"));
+        assert!(data.texture_keys.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn levels_without_triangles_have_nothing_to_collide_with() {
+        let mut data = ModelData::default();
+        assert!(data.build_collision_mesh(1.0).is_none());
+
+        let vertex = |x: f32, y: f32| Vertex::new([x, y, 0.0], [0.0; 2], [0.0, 0.0, 1.0]);
+        // a line folded into a triangle, like the stand-in for models without meshes
+        data.push_mesh("sliver", vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(2.0, 0.0)], &[0, 1, 2], 0);
+        assert!(data.build_collision_mesh(1.0).is_none());
+
+        data.push_mesh("floor", vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 1.0)], &[0, 1, 2], 0);
+        let mesh = data.build_collision_mesh(0.5).expect("the floor has an area");
+        assert_eq!(mesh.get_triangle_count(), 2);
+        assert_eq!(mesh.get_aabb().max.x(), 1.0);
+    }
+}
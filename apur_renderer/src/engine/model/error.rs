@@ -0,0 +1,50 @@
+use std::fmt;
+
+/// Reasons a model can't be loaded at all. Broken textures aren't errors, they get
+/// replaced by a checkerboard and listed in the `LoadReport` instead.
+#[derive(Debug)]
+pub enum ModelLoadError {
+    Io { path: String, source: std::io::Error },
+    Obj { path: String, source: tobj::LoadError },
+    Gltf { path: String, source: gltf::Error },
+    /// A buffer uri that is neither a file nor valid base64 data.
+    BadUri { uri: String },
+    /// A .glb buffer refers to the binary chunk but the file has none.
+    MissingBlob,
+    /// Per vertex attributes of a mesh don't have the same number of elements.
+    MismatchedAttributes { mesh: String, attribute: &'static str, expected: usize, found: usize },
+    /// An index points past the end of the mesh's vertices.
+    IndexOutOfRange { mesh: String, index: u32, vertex_count: usize },
+    MissingMaterial { mesh: String, material: usize },
+}
+
+impl fmt::Display for ModelLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelLoadError::Io { path, source } => write!(f, "couldn't read {}: {}", path, source),
+            ModelLoadError::Obj { path, source } => write!(f, "couldn't parse {}: {}", path, source),
+            ModelLoadError::Gltf { path, source } => write!(f, "couldn't parse {}: {}", path, source),
+            ModelLoadError::BadUri { uri } => write!(f, "unsupported buffer uri: {}", uri),
+            ModelLoadError::MissingBlob => write!(f, "glb binary chunk is missing"),
+            ModelLoadError::MismatchedAttributes { mesh, attribute, expected, found } => write!(
+                f, "mesh {} has {} {} but {} positions", mesh, found, attribute, expected
+            ),
+            ModelLoadError::IndexOutOfRange { mesh, index, vertex_count } => write!(
+                f, "mesh {} uses vertex {} but only has {}", mesh, index, vertex_count
+            ),
+            ModelLoadError::MissingMaterial { mesh, material } => write!(
+                f, "mesh {} uses material {} which doesn't exist", mesh, material
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ModelLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ModelLoadError::Io { source, .. } => Some(source),
+            ModelLoadError::Gltf { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use glam::{Mat3, Mat4, Vec3};
+
+use super::super::vfs::{self, Vfs};
+use super::data::{check_indices, smooth_normals, AlphaMode, MaterialData, ModelData, TextureSource, Vertex};
+use super::error::ModelLoadError;
+use super::tangents::generate_tangents;
+
+/// Reads a .gltf or .glb file, buffers and images may be external, embedded or base64 data uris.
+/// Images are left pending in `ModelData::pending_textures`.
+pub fn load(vfs: &Vfs, path: &str) -> Result<ModelData, ModelLoadError> {
+    let bytes = vfs.read(path).map_err(|source| ModelLoadError::Io { path: path.to_string(), source })?;
+    load_slice(vfs, &bytes, vfs::parent(path), path)
+}
+
+/// Same as `load` for a document already in memory, relative uris are resolved against the
+/// virtual directory `base`. `name` only shows up in errors.
+pub fn load_slice(vfs: &Vfs, bytes: &[u8], base: &str, name: &str) -> Result<ModelData, ModelLoadError> {
+    let gltf::Gltf { document, mut blob } = gltf::Gltf::from_slice(bytes)
+        .map_err(|source| ModelLoadError::Gltf { path: name.to_string(), source })?;
+    let buffers = document
+        .buffers()
+        .map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Bin => blob.take().ok_or(ModelLoadError::MissingBlob),
+            gltf::buffer::Source::Uri(uri) => read_uri(vfs, base, uri),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut data = build(&document, &buffers)?;
+    for image in document.images() {
+        let name = image.name().map(str::to_string).unwrap_or_else(|| format!("image {}", image.index()));
+        let source = match image.source() {
+            gltf::image::Source::View { view, .. } => {
+                let start = view.offset();
+                buffers[view.buffer().index()]
+                    .get(start..start + view.length())
+                    .map(|bytes| TextureSource::Bytes(bytes.to_vec()))
+                    .ok_or_else(|| String::from("buffer view out of range"))
+            },
+            gltf::image::Source::Uri { uri, .. } if uri.starts_with("data:") => {
+                read_uri(vfs, base, uri).map(TextureSource::Bytes).map_err(|err| err.to_string())
+            },
+            gltf::image::Source::Uri { uri, .. } => Ok(TextureSource::File(vfs::join(base, uri))),
+        };
+        match source {
+            Ok(source) => {
+                data.request_texture(&name, source);
+            },
+            Err(reason) => {
+                let texture = data.report.substitute_texture(&name, reason);
+                data.push_texture(texture);
+            },
+        }
+    }
+
+    let image_count = data.textures.len();
+    for material in &mut data.materials {
+        // textures pointing at images that aren't there are dropped rather than failing the model
+        material.diffuse_texture = material.diffuse_texture.filter(|&i| i < image_count);
+        material.normal_texture = material.normal_texture.filter(|&i| i < image_count);
+        material.metallic_roughness_texture = material.metallic_roughness_texture.filter(|&i| i < image_count);
+    }
+    Ok(data)
+}
+
+/// Contents of a buffer or image uri, either inline base64 data or a file next to the model.
+fn read_uri(vfs: &Vfs, base: &str, uri: &str) -> Result<Vec<u8>, ModelLoadError> {
+    if uri.starts_with("data:") {
+        let bad_uri = || ModelLoadError::BadUri { uri: uri.chars().take(64).collect() };
+        let comma = uri.find(',').ok_or_else(bad_uri)?;
+        base64::decode(&uri[comma + 1..]).map_err(|_| bad_uri())
+    } else {
+        let path = vfs::join(base, uri);
+        vfs.read(&path).map_err(|source| ModelLoadError::Io { path, source })
+    }
+}
+
+/// Flattens the node hierarchy of the default scene into one vertex and index list,
+/// meshes used by several nodes are copied for each of them.
+fn build(document: &gltf::Document, buffers: &[Vec<u8>]) -> Result<ModelData, ModelLoadError> {
+    let mut data = ModelData {
+        materials: document.materials().map(material_data).collect(),
+        ..ModelData::default()
+    };
+
+    let scene = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene,
+        None => return Ok(data),
+    };
+    let mut stack: Vec<(gltf::Node, Mat4)> = scene.nodes().map(|n| (n, Mat4::identity())).collect();
+    while let Some((node, parent_world)) = stack.pop() {
+        let world = parent_world * Mat4::from_cols_array_2d(&node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                let name = mesh.name().or_else(|| node.name()).unwrap_or("unnamed");
+                push_primitive(&mut data, buffers, &primitive, world, name)?;
+            }
+        }
+        stack.extend(node.children().map(|c| (c, world)));
+    }
+    Ok(data)
+}
+
+fn push_primitive(
+    data: &mut ModelData,
+    buffers: &[Vec<u8>],
+    primitive: &gltf::Primitive,
+    world: Mat4,
+    name: &str,
+) -> Result<(), ModelLoadError> {
+    if primitive.mode() != gltf::mesh::Mode::Triangles {
+        data.report.skip_mesh(name, "not a triangle list");
+        return Ok(());
+    }
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()][..]));
+    let positions: Vec<[f32; 3]> = match reader.read_positions() {
+        Some(positions) => positions.collect(),
+        None => {
+            data.report.skip_mesh(name, "no positions");
+            return Ok(());
+        },
+    };
+    let mut indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    check_indices(name, &indices, positions.len())?;
+    let normals: Vec<[f32; 3]> = match reader.read_normals() {
+        Some(normals) => normals.collect(),
+        None => smooth_normals(&positions, &indices),
+    };
+    if normals.len() != positions.len() {
+        return Err(mismatch(name, "normals", positions.len(), normals.len()));
+    }
+
+    let material = primitive.material();
+    let base_color = material.pbr_metallic_roughness().base_color_texture();
+    let tex_set = base_color.as_ref().map_or(0, |info| {
+        info.texture_transform().and_then(|t| t.tex_coord()).unwrap_or_else(|| info.tex_coord())
+    });
+    let uv_transform = base_color.as_ref().and_then(|info| info.texture_transform()).map(|t| {
+        uv_transform(t.offset(), t.rotation(), t.scale())
+    });
+    let tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(tex_set) {
+        Some(tex_coords) => tex_coords.into_f32().collect(),
+        None => vec![[0.0, 0.0]; positions.len()],
+    };
+    if tex_coords.len() != positions.len() {
+        return Err(mismatch(name, "texcoords", positions.len(), tex_coords.len()));
+    }
+
+    let normal_matrix = Mat3::from_cols(world.x_axis().truncate(), world.y_axis().truncate(), world.z_axis().truncate())
+        .inverse()
+        .transpose();
+    let mut vertices: Vec<Vertex> = positions
+        .iter()
+        .zip(&normals)
+        .zip(&tex_coords)
+        .map(|((&pos, &normal), &uv)| {
+            let pos = world.transform_point3(Vec3::from(pos));
+            let normal = (normal_matrix * Vec3::from(normal)).normalize();
+            let uv = match uv_transform {
+                Some(m) => {
+                    let uv = m * Vec3::new(uv[0], uv[1], 1.0);
+                    [uv.x(), uv.y()]
+                },
+                None => uv,
+            };
+            Vertex::new([pos.x(), pos.y(), pos.z()], uv, [normal.x(), normal.y(), normal.z()])
+        })
+        .collect();
+
+    // mirroring transforms turn the triangles inside out
+    if world.determinant() < 0.0 {
+        for tri in indices.chunks_mut(3) {
+            tri.swap(1, 2);
+        }
+    }
+    // generated rather than read from the file, after the uv transform and in world space,
+    // so they follow the same uv convention as the OBJ tangents
+    generate_tangents(&mut vertices, &indices);
+
+    let material = match material.index() {
+        Some(idx) => idx,
+        None => data.default_material(),
+    };
+    data.push_mesh(name, vertices, &indices, material);
+    Ok(())
+}
+
+fn mismatch(mesh: &str, attribute: &'static str, expected: usize, found: usize) -> ModelLoadError {
+    ModelLoadError::MismatchedAttributes { mesh: mesh.to_string(), attribute, expected, found }
+}
+
+fn material_data(material: gltf::Material) -> MaterialData {
+    let pbr = material.pbr_metallic_roughness();
+    let base_color = pbr.base_color_factor();
+    let metallic = pbr.metallic_factor();
+    // what the Blinn-Phong fallback shades with
+    let specular = |c: f32| 0.04 + (c - 0.04) * metallic;
+    let roughness = pbr.roughness_factor().max(0.05);
+    MaterialData {
+        name: material.name().unwrap_or("unnamed").to_string(),
+        diffuse_texture: pbr.base_color_texture().map(|info| info.texture().source().index()),
+        normal_texture: material.normal_texture().map(|info| info.texture().source().index()),
+        bump_scale: material.normal_texture().map_or(1.0, |info| info.scale()),
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        base_color_factor: base_color,
+        ambient_color: [0.0; 3],
+        specular_color: [specular(base_color[0]), specular(base_color[1]), specular(base_color[2])],
+        shininess: (2.0 / roughness.powi(4) - 2.0).clamp(1.0, 1000.0),
+        metallic_roughness_texture: pbr.metallic_roughness_texture().map(|info| info.texture().source().index()),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        ..MaterialData::default()
+    }
+}
+
+/// KHR_texture_transform as a 2d affine matrix, scale is applied first, then rotation, then offset.
+fn uv_transform(offset: [f32; 2], rotation: f32, scale: [f32; 2]) -> Mat3 {
+    let (sin, cos) = rotation.sin_cos();
+    Mat3::from_cols(
+        Vec3::new(cos * scale[0], -sin * scale[0], 0.0),
+        Vec3::new(sin * scale[1], cos * scale[1], 0.0),
+        Vec3::new(offset[0], offset[1], 1.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a single triangle with 3 positions, 3 uvs and u16 indices packed into one base64 buffer
+    fn triangle_gltf(nodes: &str, texture_transform: &str) -> String {
+        let mut bin = vec![];
+        for f in &[0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bin.extend_from_slice(&f.to_le_bytes());
+        }
+        for f in &[0.0f32, 0.0, 1.0, 0.0, 0.0, 1.0] {
+            bin.extend_from_slice(&f.to_le_bytes());
+        }
+        for i in &[0u16, 1, 2, 0] {
+            bin.extend_from_slice(&i.to_le_bytes());
+        }
+        format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "scene": 0,
+            "scenes": [{{ "nodes": [0] }}],
+            "nodes": {nodes},
+            "meshes": [{{ "name": "tri", "primitives": [{{
+                "attributes": {{ "POSITION": 0, "TEXCOORD_0": 1 }},
+                "indices": 2,
+                "material": 0
+            }}] }}],
+            "materials": [{{
+                "name": "red",
+                "pbrMetallicRoughness": {{
+                    "baseColorFactor": [1.0, 0.0, 0.0, 1.0],
+                    "baseColorTexture": {{ "index": 0 {texture_transform} }},
+                    "metallicFactor": 0.25
+                }}
+            }}],
+            "textures": [{{ "source": 0 }}],
+            "images": [{{ "uri": "data:image/png;base64,{png}" }}],
+            "buffers": [{{ "byteLength": {len}, "uri": "data:application/octet-stream;base64,{bin}" }}],
+            "bufferViews": [
+                {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 24 }},
+                {{ "buffer": 0, "byteOffset": 60, "byteLength": 6 }}
+            ],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                   "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] }},
+                {{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }},
+                {{ "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+            ]
+        }}"#,
+            nodes = nodes,
+            texture_transform = texture_transform,
+            png = PIXEL_PNG,
+            len = bin.len(),
+            bin = base64::encode(&bin),
+        )
+    }
+
+    // 1x1 opaque white png
+    const PIXEL_PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAAC0lEQVR4nGP4DwQACfsD/fteaysAAAAASUVORK5CYII=";
+
+    fn load_test(gltf: String) -> Result<ModelData, ModelLoadError> {
+        let vfs = Vfs::new();
+        let mut data = load_slice(&vfs, gltf.as_bytes(), "", "test")?;
+        // decoded in place like the loader threads would, broken ones become checkerboards
+        for request in std::mem::take(&mut data.pending_textures) {
+            data.textures[request.index] = match request.decode(&vfs) {
+                Ok(texture) => texture,
+                Err(reason) => data.report.substitute_texture(&request.name, reason),
+            };
+        }
+        Ok(data)
+    }
+
+    fn approx(a: [f32; 3], b: [f32; 3]) -> bool {
+        (Vec3::from(a) - Vec3::from(b)).length() < 1e-5
+    }
+
+    #[test]
+    fn loads_embedded_triangle_and_material() {
+        let data = load_test(triangle_gltf(r#"[{ "mesh": 0 }]"#, "")).unwrap();
+        assert_eq!(data.vertices.len(), 3);
+        assert_eq!(data.indices, vec![0, 1, 2]);
+        assert_eq!(data.meshes.len(), 1);
+        assert_eq!(data.meshes[0].name, "tri");
+
+        // normals weren't in the file so they come from the triangle
+        assert!(approx(data.vertices[0].get_normal(), [0.0, 0.0, 1.0]));
+        assert_eq!(data.vertices[1].get_tex_coords(), [1.0, 0.0]);
+
+        let material = &data.materials[data.meshes[0].material];
+        assert_eq!(material.name, "red");
+        assert_eq!(material.base_color_factor, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(material.metallic_factor, 0.25);
+        assert_eq!(material.diffuse_texture, Some(0));
+        assert_eq!(material.alpha_mode, AlphaMode::Opaque);
+        assert_eq!(data.textures[0].width, 1);
+        assert_eq!(data.textures[0].pixels, vec![255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn node_hierarchy_is_baked_into_vertices() {
+        let nodes = r#"[
+            { "translation": [0.0, 0.0, 5.0], "children": [1] },
+            { "mesh": 0, "scale": [2.0, 2.0, 2.0] },
+            { "mesh": 0, "scale": [-1.0, 1.0, 1.0] }
+        ]"#;
+        let data = load_test(triangle_gltf(nodes, "")).unwrap();
+        // node 2 isn't part of the scene
+        assert_eq!(data.vertices.len(), 3);
+        assert!(approx(data.vertices[1].get_pos(), [2.0, 0.0, 5.0]));
+        assert!(approx(data.vertices[2].get_pos(), [0.0, 2.0, 5.0]));
+
+        let mirrored = load_test(triangle_gltf(r#"[{ "mesh": 0, "scale": [-1.0, 1.0, 1.0] }]"#, "")).unwrap();
+        assert_eq!(mirrored.indices, vec![0, 2, 1]);
+        assert!(approx(mirrored.vertices[0].get_normal(), [0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn texture_transform_is_applied_to_uvs() {
+        let transform = r#", "extensions": { "KHR_texture_transform": {
+            "offset": [0.5, 0.0], "scale": [2.0, 2.0]
+        } }"#;
+        let data = load_test(triangle_gltf(r#"[{ "mesh": 0 }]"#, transform)).unwrap();
+        assert_eq!(data.vertices[0].get_tex_coords(), [0.5, 0.0]);
+        assert_eq!(data.vertices[1].get_tex_coords(), [2.5, 0.0]);
+        assert_eq!(data.vertices[2].get_tex_coords(), [0.5, 2.0]);
+
+        // rotating by a quarter turn takes u onto -v
+        let m = uv_transform([0.0, 0.0], std::f32::consts::FRAC_PI_2, [1.0, 1.0]);
+        assert!(approx((m * Vec3::new(1.0, 0.0, 1.0)).into(), [0.0, -1.0, 1.0]));
+    }
+
+    #[test]
+    fn alpha_mode_and_cutoff_are_read() {
+        let gltf = triangle_gltf(r#"[{ "mesh": 0 }]"#, "").replace(r#""name": "red","#, r#""name": "red", "alphaMode": "MASK", "alphaCutoff": 0.25,"#);
+        let data = load_test(gltf).unwrap();
+        assert_eq!(data.materials[0].alpha_mode, AlphaMode::Mask);
+        assert_eq!(data.materials[0].alpha_cutoff, 0.25);
+
+        let gltf = triangle_gltf(r#"[{ "mesh": 0 }]"#, "").replace(r#""name": "red","#, r#""name": "red", "alphaMode": "BLEND","#);
+        assert_eq!(load_test(gltf).unwrap().materials[0].alpha_mode, AlphaMode::Blend);
+    }
+
+    #[test]
+    fn broken_images_fall_back_to_a_checkerboard() {
+        let gltf = triangle_gltf(r#"[{ "mesh": 0 }]"#, "").replace(&format!("data:image/png;base64,{}", PIXEL_PNG), "missing.png");
+        let data = load_test(gltf).unwrap();
+        assert_eq!(data.textures[0].width, 64);
+        assert_eq!(&data.textures[0].pixels[..4], &[255, 0, 255, 255]);
+        assert_eq!(data.report.substituted_textures.len(), 1);
+        assert_eq!(data.report.substituted_textures[0].0, "image 0");
+    }
+
+    #[test]
+    fn out_of_range_indices_are_an_error() {
+        let gltf = triangle_gltf(r#"[{ "mesh": 0 }]"#, "").replace(r#""count": 3, "type": "VEC3""#, r#""count": 2, "type": "VEC3""#);
+        match load_test(gltf) {
+            Err(ModelLoadError::IndexOutOfRange { index, vertex_count, .. }) => assert_eq!((index, vertex_count), (2, 2)),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("loaded a mesh with bad indices"),
+        }
+    }
+}
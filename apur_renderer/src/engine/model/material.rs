@@ -0,0 +1,120 @@
+use super::super::assets::TextureHandle;
+use super::data::{AlphaMode, MaterialData};
+
+/// Material constants as the fragment shader sees them, std140 layout.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct MaterialUniform {
+    diffuse: [f32; 4],
+    ambient: [f32; 4],
+    specular: [f32; 4],
+    /// 1.0 for each of diffuse, normal, specular and bump when that map is bound.
+    maps: [f32; 4],
+    alpha_map: f32,
+    shininess: f32,
+    bump_scale: f32,
+    /// Coverage below this is discarded, zero unless the material is masked.
+    alpha_cutoff: f32,
+    metallic: f32,
+    roughness: f32,
+    /// 1.0 when the metallic-roughness map is bound.
+    metallic_roughness_map: f32,
+    _padding: f32,
+}
+
+impl MaterialUniform {
+    fn new(material: &MaterialData) -> Self {
+        let flag = |texture: Option<usize>| if texture.is_some() { 1.0 } else { 0.0 };
+        let [r, g, b] = material.ambient_color;
+        let ambient = if r == 0.0 && g == 0.0 && b == 0.0 { [1.0; 3] } else { material.ambient_color };
+        let [sr, sg, sb] = material.specular_color;
+        Self {
+            diffuse: material.base_color_factor,
+            ambient: [ambient[0], ambient[1], ambient[2], 1.0],
+            specular: [sr, sg, sb, 1.0],
+            maps: [
+                flag(material.diffuse_texture),
+                flag(material.normal_texture),
+                flag(material.specular_texture),
+                flag(material.bump_texture),
+            ],
+            alpha_map: flag(material.alpha_texture),
+            shininess: material.shininess.max(1.0),
+            bump_scale: material.bump_scale,
+            alpha_cutoff: if material.alpha_mode == AlphaMode::Mask { material.alpha_cutoff } else { 0.0 },
+            metallic: material.metallic_factor,
+            roughness: material.roughness_factor,
+            metallic_roughness_map: flag(material.metallic_roughness_texture),
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Textures bound in place of maps a material doesn't have, the shader ignores them
+/// but the bind group still needs something in every slot.
+#[derive(Clone)]
+pub struct DefaultTextures {
+    pub white: TextureHandle,
+    pub flat_normal: TextureHandle,
+}
+
+/// Maps and constants of a material, uploaded to the GPU. The maps are indices into the
+/// model's textures, which get swapped out while they stream in.
+pub struct Material {
+    diffuse: Option<usize>,
+    normal: Option<usize>,
+    specular: Option<usize>,
+    bump: Option<usize>,
+    alpha: Option<usize>,
+    metallic_roughness: Option<usize>,
+    alpha_mode: AlphaMode,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl Material {
+    pub const UNIFORM_SIZE: u64 = std::mem::size_of::<MaterialUniform>() as u64;
+
+    pub fn new(device: &wgpu::Device, data: &MaterialData) -> Self {
+        let uniform_buffer = device
+            .create_buffer_mapped(1, wgpu::BufferUsage::UNIFORM)
+            .fill_from_slice(&[MaterialUniform::new(data)]);
+
+        Self {
+            diffuse: data.diffuse_texture,
+            normal: data.normal_texture,
+            specular: data.specular_texture,
+            bump: data.bump_texture,
+            alpha: data.alpha_texture,
+            metallic_roughness: data.metallic_roughness_texture,
+            alpha_mode: data.alpha_mode,
+            uniform_buffer,
+        }
+    }
+
+    /// Diffuse, normal, specular, bump, alpha and metallic-roughness views to bind, `textures`
+    /// are the model's textures in the order of `ModelData::textures`.
+    pub fn get_views<'a>(&self, textures: &'a [TextureHandle], defaults: &'a DefaultTextures) -> [&'a wgpu::TextureView; 6] {
+        let pick = |texture: Option<usize>, default: &'a TextureHandle| -> &'a wgpu::TextureView {
+            match texture {
+                Some(idx) => &textures[idx],
+                None => default,
+            }
+        };
+        [
+            pick(self.diffuse, &defaults.white),
+            pick(self.normal, &defaults.flat_normal),
+            pick(self.specular, &defaults.white),
+            pick(self.bump, &defaults.white),
+            pick(self.alpha, &defaults.white),
+            pick(self.metallic_roughness, &defaults.white),
+        ]
+    }
+
+    pub fn get_alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    pub fn get_uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use super::super::vfs::{self, Vfs};
+use super::data::{check_indices, smooth_normals, AlphaMode, MaterialData, ModelData, TextureSource, Vertex};
+use super::error::ModelLoadError;
+use super::tangents::generate_tangents;
+
+/// Material libraries an obj refers to, as paths in the `Vfs`.
+pub fn material_libraries(path: &str, bytes: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix("mtllib "))
+        .map(|mtl| vfs::join(vfs::parent(path), mtl.trim()))
+        .collect()
+}
+
+/// Parses an obj read from `path` in the `Vfs` along with its materials, textures are left pending.
+pub fn parse(vfs: &Vfs, path: &str, bytes: &[u8]) -> Result<ModelData, ModelLoadError> {
+    let dir = vfs::parent(path);
+    let (models, mats) = tobj::load_obj_buf(&mut &bytes[..], |mtl_path| {
+        let mtl_path = vfs::join(dir, &mtl_path.to_string_lossy());
+        let mtl = vfs.read(&mtl_path).map_err(|_| tobj::LoadError::OpenFileFailed)?;
+        tobj::load_mtl_buf(&mut &mtl[..])
+    }).map_err(|source| ModelLoadError::Obj { path: path.to_string(), source })?;
+
+    let mut data = ModelData::default();
+    let mut texture_cache = HashMap::<String, usize>::new();
+    for mat in &mats {
+        let mut texture = |value: &str| texture_index(value, dir, &mut data, &mut texture_cache);
+        let param = |keys: &[&str]| keys.iter().find_map(|k| mat.unknown_param.get(*k)).map(String::as_str);
+
+        // the crytek sponza keeps its normal maps under map_Disp
+        let normal_texture = param(&["norm", "map_Kn", "map_Disp"]).and_then(&mut texture);
+        let bump = param(&["map_bump", "map_Bump", "bump"]);
+        let bump_texture = bump.and_then(&mut texture);
+        let bump_scale = bump.and_then(bump_multiplier).unwrap_or(1.0);
+        let alpha_texture = texture(&mat.dissolve_texture);
+        // a dissolve map on its own is a cutout, like the sponza foliage and chains
+        let alpha_mode = if mat.dissolve < 1.0 {
+            AlphaMode::Blend
+        } else if alpha_texture.is_some() {
+            AlphaMode::Mask
+        } else {
+            AlphaMode::Opaque
+        };
+
+        let material = MaterialData {
+            name: mat.name.clone(),
+            diffuse_texture: texture(&mat.diffuse_texture),
+            normal_texture,
+            specular_texture: texture(&mat.specular_texture),
+            bump_texture,
+            bump_scale,
+            alpha_texture,
+            alpha_mode,
+            base_color_factor: [mat.diffuse[0], mat.diffuse[1], mat.diffuse[2], mat.dissolve],
+            ambient_color: mat.ambient,
+            specular_color: mat.specular,
+            shininess: mat.shininess,
+            // the PBR extension of mtl says it outright, otherwise it's guessed from Blinn-Phong
+            metallic_factor: param(&["Pm"]).and_then(|v| v.trim().parse().ok()).unwrap_or_else(|| metalness(mat.specular, mat.diffuse)),
+            roughness_factor: param(&["Pr"]).and_then(|v| v.trim().parse().ok()).unwrap_or_else(|| roughness(mat.shininess)),
+            ..MaterialData::default()
+        };
+        data.materials.push(material);
+    }
+
+    for m in models {
+        let positions: Vec<[f32; 3]> = m.mesh.positions.chunks_exact(3).map(|p| [p[0], p[1], p[2]]).collect();
+        check_indices(&m.name, &m.mesh.indices, positions.len())?;
+
+        let ts = &m.mesh.texcoords;
+        if !ts.is_empty() && ts.len() / 2 != positions.len() {
+            return Err(mismatch(&m.name, "texcoords", positions.len(), ts.len() / 2));
+        }
+        let normals = if m.mesh.normals.is_empty() {
+            smooth_normals(&positions, &m.mesh.indices)
+        } else {
+            m.mesh.normals.chunks_exact(3).map(|n| [n[0], n[1], n[2]]).collect()
+        };
+        if normals.len() != positions.len() {
+            return Err(mismatch(&m.name, "normals", positions.len(), normals.len()));
+        }
+
+        let mut vertices: Vec<Vertex> = positions
+            .iter()
+            .zip(&normals)
+            .enumerate()
+            .map(|(i, (&pos, &normal))| {
+                let tex_coords = if ts.is_empty() { [0.0, 0.0] } else { [ts[2 * i], ts[2 * i + 1]] };
+                Vertex::new(pos, tex_coords, normal)
+            })
+            .collect();
+        generate_tangents(&mut vertices, &m.mesh.indices);
+
+        let material = match m.mesh.material_id {
+            Some(mat_idx) if mat_idx < mats.len() => mat_idx,
+            Some(mat_idx) => return Err(ModelLoadError::MissingMaterial { mesh: m.name, material: mat_idx }),
+            None => data.default_material(),
+        };
+        data.push_mesh(&m.name, vertices, &m.mesh.indices, material);
+    }
+
+    Ok(data)
+}
+
+/// Requests the texture a map statement points at once per model, returns its index.
+/// Paths are relative to `dir`, the directory of the .obj.
+fn texture_index(value: &str, dir: &str, data: &mut ModelData, cache: &mut HashMap<String, usize>) -> Option<usize> {
+    let texture_name = texture_path(value)?;
+    if let Some(&idx) = cache.get(&texture_name) {
+        return Some(idx);
+    }
+    let idx = data.request_texture(&texture_name, TextureSource::File(vfs::join(dir, &texture_name)));
+    cache.insert(texture_name, idx);
+    Some(idx)
+}
+
+/// File name of a map statement without its options, e.g. `-bm 0.5 textures/bump.png`.
+/// Assumed to include the "textures/" in path name, some exporters write windows paths.
+fn texture_path(value: &str) -> Option<String> {
+    let mut rest = value.trim();
+    // every option we expect to see takes a single argument
+    while rest.starts_with('-') {
+        rest = rest.splitn(3, char::is_whitespace).nth(2).unwrap_or("").trim_start();
+    }
+    if rest.is_empty() {
+        None
+    } else {
+        Some(rest.replace('\\', "/"))
+    }
+}
+
+/// The `-bm` option of a bump map statement.
+fn bump_multiplier(value: &str) -> Option<f32> {
+    let mut tokens = value.split_whitespace();
+    tokens.position(|t| t == "-bm")?;
+    tokens.next()?.parse().ok()
+}
+
+/// GGX roughness with about the highlight of a Blinn-Phong exponent, the inverse of what the
+/// glTF loader does for the other direction.
+fn roughness(shininess: f32) -> f32 {
+    (2.0 / (shininess.max(0.0) + 2.0)).powf(0.25)
+}
+
+/// Metalness guessed from how much a specular color outshines the diffuse one. Dielectrics
+/// mostly have a dim gray Ks next to their Kd, metals a bright Ks and next to no diffuse.
+fn metalness(specular: [f32; 3], diffuse: [f32; 3]) -> f32 {
+    let luminance = |[r, g, b]: [f32; 3]| 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let (specular, diffuse) = (luminance(specular), luminance(diffuse));
+    if specular <= 0.04 {
+        return 0.0;
+    }
+    ((specular - diffuse) / specular).clamp(0.0, 1.0)
+}
+
+fn mismatch(mesh: &str, attribute: &'static str, expected: usize, found: usize) -> ModelLoadError {
+    ModelLoadError::MismatchedAttributes { mesh: mesh.to_string(), attribute, expected, found }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pbr_parameters_are_derived_from_blinn_phong() {
+        assert!((roughness(0.0) - 1.0).abs() < 1e-6);
+        assert!(roughness(1000.0) < 0.25);
+        // shininess as the gltf loader writes it comes back as the same roughness
+        let gltf_shininess = 2.0 / 0.5f32.powi(4) - 2.0;
+        assert!((roughness(gltf_shininess) - 0.5).abs() < 1e-5);
+
+        assert_eq!(metalness([0.0; 3], [0.8; 3]), 0.0);
+        assert_eq!(metalness([0.5; 3], [0.64; 3]), 0.0);
+        assert_eq!(metalness([1.0; 3], [0.0; 3]), 1.0);
+        assert!(metalness([0.9; 3], [0.1; 3]) > 0.85);
+    }
+}
@@ -0,0 +1,150 @@
+use glam::{Vec2, Vec3};
+
+use super::data::Vertex;
+
+/// Per vertex tangents the way MikkTSpace builds them: each triangle's tangent is projected
+/// onto the plane of a corner's normal and weighted by the angle at that corner, the bitangent
+/// only contributes its sign. Vertices on a uv mirror seam have to be split for the sign to hold,
+/// which the loaders already do since the uvs differ.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents = vec![Vec3::zero(); vertices.len()];
+    let mut bitangents = vec![Vec3::zero(); vertices.len()];
+    for tri in indices.chunks_exact(3) {
+        let corner = |k: usize| {
+            let vertex = &vertices[tri[k] as usize];
+            (Vec3::from(vertex.get_pos()), Vec2::from(vertex.get_tex_coords()))
+        };
+        let corners = [corner(0), corner(1), corner(2)];
+        let (e1, e2) = (corners[1].0 - corners[0].0, corners[2].0 - corners[0].0);
+        let (d1, d2) = (corners[1].1 - corners[0].1, corners[2].1 - corners[0].1);
+        let det = d1.x() * d2.y() - d2.x() * d1.y();
+        if det == 0.0 {
+            // no uv area, nothing to say about the direction of u
+            continue;
+        }
+        let face_tangent = (e1 * d2.y() - e2 * d1.y()) / det;
+        let face_bitangent = (e2 * d1.x() - e1 * d2.x()) / det;
+
+        for (k, &i) in tri.iter().enumerate() {
+            let normal = Vec3::from(vertices[i as usize].get_normal());
+            let angle = corner_angle(corners[k].0, corners[(k + 1) % 3].0, corners[(k + 2) % 3].0);
+            if let Some(t) = project(face_tangent, normal) {
+                tangents[i as usize] += t * angle;
+            }
+            if let Some(b) = project(face_bitangent, normal) {
+                bitangents[i as usize] += b * angle;
+            }
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let normal = Vec3::from(vertex.get_normal());
+        let t = project(tangent, normal).unwrap_or_else(|| perpendicular(normal));
+        let w = if normal.cross(t).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+        vertex.set_tangent([t.x(), t.y(), t.z(), w]);
+    }
+}
+
+/// `v` without its component along `normal`, normalized.
+fn project(v: Vec3, normal: Vec3) -> Option<Vec3> {
+    let projected = v - normal * normal.dot(v);
+    if projected.length_squared() > 1e-12 {
+        Some(projected.normalize())
+    } else {
+        None
+    }
+}
+
+/// Some tangent for vertices without usable uvs, the normal map can't be right there anyway.
+fn perpendicular(normal: Vec3) -> Vec3 {
+    let axis = if normal.x().abs() < 0.9 { Vec3::unit_x() } else { Vec3::unit_y() };
+    project(axis, normal).unwrap_or_else(Vec3::unit_x)
+}
+
+fn corner_angle(at: Vec3, a: Vec3, b: Vec3) -> f32 {
+    let (ea, eb) = (a - at, b - at);
+    if ea.length_squared() == 0.0 || eb.length_squared() == 0.0 {
+        return 0.0;
+    }
+    ea.normalize().dot(eb.normalize()).clamp(-1.0, 1.0).acos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::model::{obj_loader, ModelData};
+    use crate::engine::vfs::Vfs;
+
+    fn load_obj(name: &str) -> ModelData {
+        let mut vfs = Vfs::new();
+        vfs.mount_dir("", concat!(env!("CARGO_MANIFEST_DIR"), "/res"));
+        let path = format!("models/{}.obj", name);
+        obj_loader::parse(&vfs, &path, &vfs.read(&path).unwrap()).unwrap()
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    /// Tangents and bitangents have to point along increasing u and v of every triangle.
+    fn assert_follows_uvs(vertices: &[Vertex], indices: &[u32]) {
+        for tri in indices.chunks_exact(3) {
+            let vertex = |k: usize| &vertices[tri[k] as usize];
+            let (p0, p1, p2) = (Vec3::from(vertex(0).get_pos()), Vec3::from(vertex(1).get_pos()), Vec3::from(vertex(2).get_pos()));
+            let (uv0, uv1, uv2) = (Vec2::from(vertex(0).get_tex_coords()), Vec2::from(vertex(1).get_tex_coords()), Vec2::from(vertex(2).get_tex_coords()));
+            let (d1, d2) = (uv1 - uv0, uv2 - uv0);
+            let det = d1.x() * d2.y() - d2.x() * d1.y();
+            let dp_du = ((p1 - p0) * d2.y() - (p2 - p0) * d1.y()) / det;
+            let dp_dv = ((p2 - p0) * d1.x() - (p1 - p0) * d2.x()) / det;
+
+            for &i in tri {
+                let v = &vertices[i as usize];
+                let [x, y, z, w] = v.get_tangent();
+                let (t, b, n) = (Vec3::new(x, y, z), Vec3::from(v.get_bitangent()), Vec3::from(v.get_normal()));
+                assert!(w == 1.0 || w == -1.0);
+                assert!((t.length() - 1.0).abs() < 1e-4);
+                assert!(t.dot(n).abs() < 1e-4);
+                assert_close(t, dp_du.normalize());
+                assert_close(b, dp_dv.normalize());
+            }
+        }
+    }
+
+    #[test]
+    fn plane_tangents_follow_the_uvs() {
+        let data = load_obj("plane");
+        assert_follows_uvs(&data.vertices, &data.indices);
+        for vertex in &data.vertices {
+            let [x, y, z, w] = vertex.get_tangent();
+            assert_close(Vec3::new(x, y, z), Vec3::unit_x());
+            assert_eq!(w, 1.0);
+        }
+    }
+
+    #[test]
+    fn cube_tangents_follow_the_uvs() {
+        let data = load_obj("cube");
+        assert_eq!(data.vertices.len(), 24);
+        assert_follows_uvs(&data.vertices, &data.indices);
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_the_bitangent() {
+        // the plane with u running backwards
+        let normal = [0.0, 0.0, 1.0];
+        let mut vertices = vec![
+            Vertex::new([-1.0, -1.0, 0.0], [1.0, 0.0], normal),
+            Vertex::new([1.0, -1.0, 0.0], [0.0, 0.0], normal),
+            Vertex::new([1.0, 1.0, 0.0], [0.0, 1.0], normal),
+            Vertex::new([-1.0, 1.0, 0.0], [1.0, 1.0], normal),
+        ];
+        let indices = [0, 1, 2, 0, 2, 3];
+        generate_tangents(&mut vertices, &indices);
+        assert_follows_uvs(&vertices, &indices);
+        for vertex in &vertices {
+            let [x, y, z, w] = vertex.get_tangent();
+            assert_close(Vec3::new(x, y, z), -Vec3::unit_x());
+            assert_eq!(w, -1.0);
+        }
+    }
+}
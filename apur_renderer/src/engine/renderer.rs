@@ -4,32 +4,79 @@ mod skybox;
 
 pub use skybox::SkyBoxRenderer;
 
-use super::model::{Vertex, Model};
+use super::assets::{AssetManager, Handle, TextureHandle};
+use super::light::LightUniform;
+use super::model::{AlphaMode, Material, Mesh, Model, Vertex};
+
+/// A loaded model with its texture bind groups and the transforms it's drawn with.
+struct ModelEntry {
+    model: Handle<Model>,
+    /// What the materials are bound with, textures still streaming in are replaced here.
+    textures: Vec<TextureHandle>,
+    material_binds: Vec<wgpu::BindGroup>,
+    /// Model matrix of every placed instance of the model.
+    instance_buffer: wgpu::Buffer,
+    /// Kept around to sort translucent meshes by distance.
+    instances: Vec<Mat4>,
+}
+
+impl ModelEntry {
+    fn instance_count(&self) -> u32 {
+        self.instances.len() as u32
+    }
+}
+
+/// How the main shader lights surfaces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShadingModel {
+    /// Metallic-roughness Cook-Torrance.
+    CookTorrance,
+    /// The old Blinn-Phong look, from Kd, Ks and Ns.
+    BlinnPhong,
+}
+
+impl ShadingModel {
+    /// What the shader's `Shading.model` compares against.
+    fn uniform(self) -> [u32; 4] {
+        match self {
+            ShadingModel::CookTorrance => [0; 4],
+            ShadingModel::BlinnPhong => [1, 0, 0, 0],
+        }
+    }
+}
 
 pub struct RenderData {
-    model: Model,
+    models: Vec<ModelEntry>,
     bind_group: wgpu::BindGroup,
-    texture_binds: Vec<wgpu::BindGroup>,
     uniforms_buffer: wgpu::Buffer,
-    light_ubo: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    /// The light count followed by every light, grown whenever more come in than fit.
+    light_buffer: wgpu::Buffer,
+    light_capacity: usize,
+    shading_ubo: wgpu::Buffer,
+    shading_model: ShadingModel,
 }
 
 impl RenderData {
+    // room for this many lights before the light buffer has to grow
+    const MIN_LIGHT_CAPACITY: usize = 16;
+
     pub fn new(
         device: &wgpu::Device,
-        model: Model,
         view_trans: Mat4,
         proj_trans: Mat4,
         bind_group_layout: &wgpu::BindGroupLayout,
-        texture_bind_group_layout: &wgpu::BindGroupLayout,
     ) -> Self {
+        // trilinear only, wgpu 0.4's SamplerDescriptor has no anisotropy setting (later releases
+        // add `anisotropy_clamp`), so anisotropic filtering waits for a wgpu upgrade and grazing
+        // angles stay a bit blurry until then
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::Repeat,
             address_mode_v: wgpu::AddressMode::Repeat,
             address_mode_w: wgpu::AddressMode::Repeat,
-            mag_filter: wgpu::FilterMode::Nearest,
+            mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
             lod_min_clamp: -100.0,
             lod_max_clamp: 100.0,
             compare_function: wgpu::CompareFunction::Always,
@@ -39,47 +86,222 @@ impl RenderData {
             .create_buffer_mapped(2, wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_WRITE)
             .fill_from_slice(&[view_trans, proj_trans]);
         
-        let light_ubo = device
-            .create_buffer_mapped(1, wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_WRITE)
-            .fill_from_slice(&[Vec3::new(0.0, 1.0, 1.0)]);
+        // empty until the engine uploads its lights with the first frame
+        let light_capacity = Self::MIN_LIGHT_CAPACITY;
+        let light_buffer = Self::create_light_buffer(device, light_capacity);
+
+        let shading_model = ShadingModel::CookTorrance;
+        let shading_ubo = device
+            .create_buffer_mapped(4, wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST)
+            .fill_from_slice(&shading_model.uniform());
+
+        let bind_group = Self::create_bind_group(
+            device,
+            bind_group_layout,
+            &uniforms_buffer,
+            &sampler,
+            &light_buffer,
+            light_capacity,
+            &shading_ubo,
+        );
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: bind_group_layout,
+        Self {
+            models: vec![],
+            bind_group,
+            uniforms_buffer,
+            sampler,
+            light_buffer,
+            light_capacity,
+            shading_ubo,
+            shading_model,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniforms_buffer: &wgpu::Buffer,
+        sampler: &wgpu::Sampler,
+        light_buffer: &wgpu::Buffer,
+        light_capacity: usize,
+        shading_ubo: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
             bindings: &[
                 wgpu::Binding {
                     binding: 0,
                     resource: wgpu::BindingResource::Buffer {
-                        buffer: &uniforms_buffer,
+                        buffer: uniforms_buffer,
                         range: 0 .. 2 * 64,
                     },
                 },
                 wgpu::Binding {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(sampler),
                 },
                 wgpu::Binding {
                     binding: 2,
                     resource: wgpu::BindingResource::Buffer {
-                        buffer: &light_ubo,
+                        buffer: light_buffer,
+                        range: 0 .. Self::light_buffer_size(light_capacity),
+                    },
+                },
+                wgpu::Binding {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: shading_ubo,
                         range: 0 .. 16,
                     },
                 },
             ]
-        });
+        })
+    }
+
+    /// A 16 byte header with the light count, then room for `capacity` lights.
+    fn light_buffer_size(capacity: usize) -> u64 {
+        (16 + capacity * std::mem::size_of::<LightUniform>()) as u64
+    }
+
+    fn create_light_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        let words = Self::light_buffer_size(capacity) as usize / 4;
+        device
+            .create_buffer_mapped(words, wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST)
+            .fill_from_slice(&vec![0u32; words])
+    }
 
-        let texture_binds = model.get_meshes().iter().map(|m| {
+    /// Replaces every light the scene is lit by, the buffer holding them grows to fit.
+    pub fn update_lights(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        lights: &[LightUniform],
+    ) {
+        if lights.len() > self.light_capacity {
+            self.light_capacity = lights.len().next_power_of_two();
+            self.light_buffer = Self::create_light_buffer(device, self.light_capacity);
+            self.bind_group = Self::create_bind_group(
+                device,
+                bind_group_layout,
+                &self.uniforms_buffer,
+                &self.sampler,
+                &self.light_buffer,
+                self.light_capacity,
+                &self.shading_ubo,
+            );
+        }
+
+        let count = device
+            .create_buffer_mapped(4, wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&[lights.len() as u32, 0, 0, 0]);
+        encoder.copy_buffer_to_buffer(&count, 0, &self.light_buffer, 0, 16);
+        if !lights.is_empty() {
+            let temp_buffer = device
+                .create_buffer_mapped(lights.len(), wgpu::BufferUsage::COPY_SRC)
+                .fill_from_slice(lights);
+            let size = (lights.len() * std::mem::size_of::<LightUniform>()) as u64;
+            encoder.copy_buffer_to_buffer(&temp_buffer, 0, &self.light_buffer, 16, size);
+        }
+    }
+
+    /// Adds a model with no instances yet, the returned index is what scene nodes refer to.
+    pub fn add_model(
+        &mut self,
+        device: &wgpu::Device,
+        model: Handle<Model>,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> usize {
+        let textures = model.get_textures().to_vec();
+        let material_binds = Self::bind_materials(device, &model, &textures, texture_bind_group_layout);
+        let instance_buffer = Self::create_instance_buffer(device, &[]);
+        self.models.push(ModelEntry { model, textures, material_binds, instance_buffer, instances: vec![] });
+        self.models.len() - 1
+    }
+
+    /// Puts a different model in an existing slot, its instances stay where they are.
+    pub fn replace_model(
+        &mut self,
+        device: &wgpu::Device,
+        index: usize,
+        model: Handle<Model>,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        let entry = &mut self.models[index];
+        entry.textures = model.get_textures().to_vec();
+        entry.material_binds = Self::bind_materials(device, &model, &entry.textures, texture_bind_group_layout);
+        entry.model = model;
+    }
+
+    /// Swaps in a texture of a model that finished loading, `texture` indexes `Model::get_textures`.
+    pub fn replace_texture(
+        &mut self,
+        device: &wgpu::Device,
+        model: usize,
+        texture: usize,
+        view: TextureHandle,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
+        let entry = &mut self.models[model];
+        entry.textures[texture] = view;
+        entry.material_binds = Self::bind_materials(device, &entry.model, &entry.textures, texture_bind_group_layout);
+    }
+
+    /// Slot already showing the model, if any.
+    pub fn find_model(&self, model: &Handle<Model>) -> Option<usize> {
+        self.models.iter().position(|entry| Handle::ptr_eq(&entry.model, model))
+    }
+
+    fn bind_materials(
+        device: &wgpu::Device,
+        model: &Model,
+        textures: &[TextureHandle],
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Vec<wgpu::BindGroup> {
+        model.get_materials().iter().map(|m| {
+            let texture = |binding, view| wgpu::Binding {
+                binding,
+                resource: wgpu::BindingResource::TextureView(view),
+            };
+            let [diffuse, normal, specular, bump, alpha, metallic_roughness] = m.get_views(textures, model.get_defaults());
             device.create_bind_group(&wgpu::BindGroupDescriptor {
                 layout: texture_bind_group_layout,
                 bindings: &[
+                    texture(0, diffuse),
                     wgpu::Binding {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(m.get_texture_view()),
+                        binding: 1,
+                        resource: wgpu::BindingResource::Buffer {
+                            buffer: m.get_uniform_buffer(),
+                            range: 0 .. Material::UNIFORM_SIZE,
+                        },
                     },
+                    texture(2, normal),
+                    texture(3, specular),
+                    texture(4, bump),
+                    texture(5, alpha),
+                    texture(6, metallic_roughness),
                 ]
             })
-        }).collect();
+        }).collect()
+    }
+
+    pub fn get_model_count(&self) -> usize {
+        self.models.len()
+    }
 
-        Self { model, texture_binds, bind_group, uniforms_buffer, light_ubo }
+    /// Replaces the instance transforms of a model, called whenever the scene graph moved something.
+    pub fn update_instances(&mut self, device: &wgpu::Device, model: usize, instances: &[Mat4]) {
+        let entry = &mut self.models[model];
+        entry.instance_buffer = Self::create_instance_buffer(device, instances);
+        entry.instances = instances.to_vec();
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, instances: &[Mat4]) -> wgpu::Buffer {
+        // wgpu doesn't like empty buffers so keep at least one matrix around
+        let instances = if instances.is_empty() { &[Mat4::identity()][..] } else { instances };
+        device
+            .create_buffer_mapped(instances.len(), wgpu::BufferUsage::VERTEX)
+            .fill_from_slice(instances)
     }
 
     pub fn update_view(&mut self, mut view_trans: Mat4) {
@@ -92,23 +314,34 @@ impl RenderData {
     pub fn get_uniforms_buffer(&self) -> &wgpu::Buffer {
         &self.uniforms_buffer
     }
+
+    pub fn get_shading_model(&self) -> ShadingModel {
+        self.shading_model
+    }
+
+    pub fn set_shading_model(&mut self, device: &wgpu::Device, queue: &mut wgpu::Queue, model: ShadingModel) {
+        self.shading_model = model;
+        let mut cmd_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        let temp_buffer = device
+            .create_buffer_mapped(4, wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&model.uniform());
+        cmd_encoder.copy_buffer_to_buffer(&temp_buffer, 0, &self.shading_ubo, 0, 16);
+        queue.submit(&[cmd_encoder.finish()]);
+    }
 }
 
 pub struct Renderer {
     bind_group_layout: wgpu::BindGroupLayout,
     texture_bind_group_layout: wgpu::BindGroupLayout,
+    environment_bind_group_layout: wgpu::BindGroupLayout,
     pipeline: wgpu::RenderPipeline,
+    blend_pipeline: wgpu::RenderPipeline,
 }
 
 impl Renderer {
-    pub fn new(device: &wgpu::Device) -> Self {
-        let vs_source = include_bytes!("../../res/shaders/shader.vert.spv");
-        let vs_module = device.create_shader_module(&wgpu::read_spirv(
-            std::io::Cursor::new(&vs_source[..])).expect("failed to read vertex shader spir-v"));
-
-        let fs_source = include_bytes!("../../res/shaders/shader.frag.spv");
-        let fs_module = device.create_shader_module(&wgpu::read_spirv(
-            std::io::Cursor::new(&fs_source[..])).expect("failed to read fragment shader spir-v"));
+    pub fn new(device: &wgpu::Device, assets: &mut AssetManager) -> Self {
+        let vs_module = assets.shader(device, "shader.vert.spv");
+        let fs_module = assets.shader(device, "shader.frag.spv");
             
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             bindings: &[
@@ -122,39 +355,119 @@ impl Renderer {
                     visibility: wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::Sampler,
                 },
+                // every light, see RenderData::update_lights
                 wgpu::BindGroupLayoutBinding {
                     binding: 2,
                     visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::StorageBuffer { dynamic: false, readonly: true },
+                },
+                // which shading model is used
+                wgpu::BindGroupLayoutBinding {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::UniformBuffer { dynamic: false },
                 },
             ]
         });
 
+        let texture_binding = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2,
+            },
+        };
         let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                texture_binding(0),
+                wgpu::BindGroupLayoutBinding {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+                // normal, specular, bump, alpha and metallic-roughness maps
+                texture_binding(2),
+                texture_binding(3),
+                texture_binding(4),
+                texture_binding(5),
+                texture_binding(6),
+            ]
+        });
+
+        // image based lighting, see SkyBoxRenderer::get_environment_bind_group
+        let cube_binding = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::Cube,
+            },
+        };
+        let environment_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             bindings: &[
                 wgpu::BindGroupLayoutBinding {
                     binding: 0,
                     visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::SampledTexture {
-                        multisampled: false,
-                        dimension: wgpu::TextureViewDimension::D2,
-                    },
+                    ty: wgpu::BindingType::Sampler,
+                },
+                // irradiance, prefiltered specular and the brdf lookup table
+                cube_binding(1),
+                cube_binding(2),
+                texture_binding(3),
+                wgpu::BindGroupLayoutBinding {
+                    binding: 4,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
                 },
             ]
         });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            bind_group_layouts: &[&bind_group_layout, &texture_bind_group_layout],
+            bind_group_layouts: &[&bind_group_layout, &texture_bind_group_layout, &environment_bind_group_layout],
         });
         
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            layout: &pipeline_layout,
+        // opaque and alpha masked meshes write depth, translucent ones are blended on top
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, &vs_module, &fs_module, false);
+        let blend_pipeline = Self::create_pipeline(device, &pipeline_layout, &vs_module, &fs_module, true);
+
+        Self { bind_group_layout, texture_bind_group_layout, environment_bind_group_layout, pipeline, blend_pipeline }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        vs_module: &wgpu::ShaderModule,
+        fs_module: &wgpu::ShaderModule,
+        translucent: bool,
+    ) -> wgpu::RenderPipeline {
+        let (color_blend, alpha_blend, write_mask) = if translucent {
+            (
+                wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                wgpu::ColorWrite::ALL,
+            )
+        } else {
+            // coverage only matters for blending, keep it out of the frame
+            (wgpu::BlendDescriptor::REPLACE, wgpu::BlendDescriptor::REPLACE, wgpu::ColorWrite::COLOR)
+        };
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout,
             vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &vs_module,
+                module: vs_module,
                 entry_point: "main",
             },
             fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &fs_module,
+                module: fs_module,
                 entry_point: "main",
             }),
             rasterization_state: Some(wgpu::RasterizationStateDescriptor {
@@ -167,13 +480,13 @@ impl Renderer {
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
             color_states: &[wgpu::ColorStateDescriptor {
                 format: wgpu::TextureFormat::Bgra8UnormSrgb,
-                color_blend: wgpu::BlendDescriptor::REPLACE,
-                alpha_blend: wgpu::BlendDescriptor::REPLACE,
-                write_mask: wgpu::ColorWrite::ALL,
+                color_blend,
+                alpha_blend,
+                write_mask,
             }],
             depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
                 format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
+                depth_write_enabled: !translucent,
                 depth_compare: wgpu::CompareFunction::LessEqual,
                 stencil_front: wgpu::StencilStateFaceDescriptor::default(),
                 stencil_back: wgpu::StencilStateFaceDescriptor::default(),
@@ -200,14 +513,42 @@ impl Renderer {
                         format: wgpu::VertexFormat::Float3,
                         shader_location: 2,
                     },
+                    wgpu::VertexAttributeDescriptor {
+                        offset: 32,
+                        format: wgpu::VertexFormat::Float4,
+                        shader_location: 7,
+                    },
+                ],
+            }, wgpu::VertexBufferDescriptor {
+                stride: std::mem::size_of::<Mat4>() as u64,
+                step_mode: wgpu::InputStepMode::Instance,
+                attributes: &[
+                    wgpu::VertexAttributeDescriptor {
+                        offset: 0,
+                        format: wgpu::VertexFormat::Float4,
+                        shader_location: 3,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        offset: 16,
+                        format: wgpu::VertexFormat::Float4,
+                        shader_location: 4,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        offset: 32,
+                        format: wgpu::VertexFormat::Float4,
+                        shader_location: 5,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        offset: 48,
+                        format: wgpu::VertexFormat::Float4,
+                        shader_location: 6,
+                    },
                 ],
             }],
             sample_count: 1,
             sample_mask: !0,
             alpha_to_coverage_enabled: false,
-        });
-            
-        Self { bind_group_layout, texture_bind_group_layout, pipeline }
+        })
     }
 
     pub fn get_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
@@ -218,15 +559,22 @@ impl Renderer {
         &self.texture_bind_group_layout
     }
 
+    pub fn get_environment_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.environment_bind_group_layout
+    }
+
     pub fn render(
         &self,
         frame: &wgpu::SwapChainOutput,
         cmd_encoder: &mut wgpu::CommandEncoder,
         depth_texture_view: &wgpu::TextureView,
         render_data: &RenderData,
+        skybox: &SkyBoxRenderer,
+        camera_position: Vec3,
     ) {
         
-        const CLEAR_COLOR: wgpu::Color = wgpu::Color { r: 0.4, g: 0.1, b: 0.1, a: 1.0 };
+        // anything the scene and the skybox don't cover stays black
+        const CLEAR_COLOR: wgpu::Color = wgpu::Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 };
         
         let mut rpass = cmd_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
@@ -248,13 +596,53 @@ impl Renderer {
         });
         rpass.set_pipeline(&self.pipeline);
         rpass.set_bind_group(0, &render_data.bind_group, &[]);
-        rpass.set_vertex_buffers(0, &[(render_data.model.get_vertex_buffer(), 0)]);
-        rpass.set_index_buffer(render_data.model.get_indices_buffer(), 0);
+        rpass.set_bind_group(2, skybox.get_environment_bind_group(), &[]);
 
-        let meshes = render_data.model.get_meshes().iter();
-        for (mesh, texture_bind_group) in meshes.zip(render_data.texture_binds.iter()) {
-            rpass.set_bind_group(1, texture_bind_group, &[]);
-            rpass.draw_indexed(mesh.get_indices_offset()..mesh.get_indices_offset()+mesh.get_indices_count(), 0, 0..1);
+        // every opaque or masked mesh is drawn once for all instances of its model
+        let is_translucent = |entry: &ModelEntry, mesh: &Mesh| {
+            entry.model.get_materials()[mesh.get_material()].get_alpha_mode() == AlphaMode::Blend
+        };
+        for entry in render_data.models.iter().filter(|e| !e.instances.is_empty()) {
+            rpass.set_vertex_buffers(0, &[
+                (entry.model.get_vertex_buffer(), 0),
+                (&entry.instance_buffer, 0),
+            ]);
+            rpass.set_index_buffer(entry.model.get_indices_buffer(), 0);
+
+            for mesh in entry.model.get_meshes().iter().filter(|m| !is_translucent(entry, m)) {
+                rpass.set_bind_group(1, &entry.material_binds[mesh.get_material()], &[]);
+                rpass.draw_indexed(mesh.get_indices_offset()..mesh.get_indices_offset()+mesh.get_indices_count(), 0, 0..entry.instance_count());
+            }
+        }
+
+        // after the opaque meshes so it only fills the pixels they left, before the translucent
+        // ones so they blend over it
+        skybox.render(&mut rpass);
+
+        // translucent meshes go one instance at a time, farthest from the camera first
+        let mut translucent = vec![];
+        for entry in &render_data.models {
+            for mesh in entry.model.get_meshes().iter().filter(|m| is_translucent(entry, m)) {
+                for (instance, transform) in entry.instances.iter().enumerate() {
+                    let distance = (transform.transform_point3(mesh.get_center()) - camera_position).length_squared();
+                    translucent.push((distance, entry, mesh, instance as u32));
+                }
+            }
+        }
+        translucent.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        rpass.set_pipeline(&self.blend_pipeline);
+        // the skybox replaced the scene bind groups
+        rpass.set_bind_group(0, &render_data.bind_group, &[]);
+        rpass.set_bind_group(2, skybox.get_environment_bind_group(), &[]);
+        for (_, entry, mesh, instance) in translucent {
+            rpass.set_vertex_buffers(0, &[
+                (entry.model.get_vertex_buffer(), 0),
+                (&entry.instance_buffer, 0),
+            ]);
+            rpass.set_index_buffer(entry.model.get_indices_buffer(), 0);
+            rpass.set_bind_group(1, &entry.material_binds[mesh.get_material()], &[]);
+            rpass.draw_indexed(mesh.get_indices_offset()..mesh.get_indices_offset()+mesh.get_indices_count(), 0, instance..instance + 1);
         }
     }
 }
@@ -1,32 +1,36 @@
 use glam::{Mat4};
 
-use std::io::Read;
-use std::fs::File;
-
-use super::super::model::Vertex;
+use super::super::assets::{AssetManager, EnvironmentMaps, Handle};
 
 pub struct SkyBoxRenderer {
+    /// Kept so the maps stay loaded as long as the skybox is drawn.
+    _environment: Handle<EnvironmentMaps>,
     background_plane: wgpu::Buffer,
     transforms_buffer: wgpu::Buffer,
+    exposure_buffer: wgpu::Buffer,
     cubemap_bind_group: wgpu::BindGroup,
+    /// What the scene is lit with, laid out by `Renderer::get_environment_bind_group_layout`.
+    environment_bind_group: wgpu::BindGroup,
     pipeline: wgpu::RenderPipeline,
 }
 
 impl SkyBoxRenderer {
+    /// `cubemap_name` is loaded through `assets`, see `AssetManager::environment`. `exposure`
+    /// scales the colors of the sky and the light it casts on the scene.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
+        environment_bind_group_layout: &wgpu::BindGroupLayout,
         cubemap_name: &str,
         queue: &mut wgpu::Queue,
+        assets: &mut AssetManager,
         view_trans: Mat4,
         proj_trans: Mat4,
+        exposure: f32,
     ) -> Self {
-        let vs_source = include_bytes!("../../../res/shaders/skybox.vert.spv");
-        let vs_module = device.create_shader_module(&wgpu::read_spirv(
-            std::io::Cursor::new(&vs_source[..])).expect("failed to read vertex shader spir-v"));
-
-        let fs_source = include_bytes!("../../../res/shaders/skybox.frag.spv");
-        let fs_module = device.create_shader_module(&wgpu::read_spirv(
-            std::io::Cursor::new(&fs_source[..])).expect("failed to read fragment shader spir-v"));
+        let vs_module = assets.shader(device, "skybox.vert.spv");
+        let fs_module = assets.shader(device, "skybox.frag.spv");
+        let environment = assets.environment(device, queue, cubemap_name);
 
         let cubemap_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             bindings: &[
@@ -48,6 +52,11 @@ impl SkyBoxRenderer {
                     visibility: wgpu::ShaderStage::VERTEX,
                     ty: wgpu::BindingType::UniformBuffer { dynamic: false },
                 },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
             ]
         });
 
@@ -79,7 +88,16 @@ impl SkyBoxRenderer {
                 alpha_blend: wgpu::BlendDescriptor::REPLACE,
                 write_mask: wgpu::ColorWrite::ALL,
             }],
-            depth_stencil_state: None,
+            // the cube is pushed to the far plane, so it only shows where nothing else was drawn
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil_front: wgpu::StencilStateFaceDescriptor::default(),
+                stencil_back: wgpu::StencilStateFaceDescriptor::default(),
+                stencil_read_mask: !0,
+                stencil_write_mask: !0,
+            }),
             index_format: wgpu::IndexFormat::Uint32,
             vertex_buffers: &[wgpu::VertexBufferDescriptor {
                 stride: std::mem::size_of::<f32>() as u64 * 3,
@@ -97,85 +115,6 @@ impl SkyBoxRenderer {
             alpha_to_coverage_enabled: false,
         });
         
-        let mut cmd_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
-
-        println!("[Info] Loading cubemap for skybox: {}", cubemap_name);
-        
-        const MAP_SUFFIXES: [&str; 6] = ["_px.jpg", "_nx.jpg", "_py.jpg", "_ny.jpg", "_pz.jpg", "_nz.jpg"];
-        
-        let mut cubemap_width = 0;
-        let mut cubemap_height = 0;
-        let cubemap_maps = MAP_SUFFIXES.iter()
-            .map(|suf| format!("{}{}", cubemap_name, suf))
-            .map(|side_name| {
-                let mut image_file = File::open(format!("res/models/textures/{}", side_name)).expect("Failed to open cubemap image");
-                let mut image_contents = vec![];
-                let _ = image_file.read_to_end(&mut image_contents);
-                
-                let cubemap_image = image::load_from_memory(&image_contents)
-                    .expect("failed to load a cubemap image")
-                    .into_rgba();
-                
-                cubemap_width = cubemap_image.width();
-                cubemap_height = cubemap_image.height();
-
-                cubemap_image
-            })
-            .collect::<Vec<_>>();
-
-        let texture_extent = wgpu::Extent3d {
-            width: cubemap_width,
-            height: cubemap_height,
-            depth: 1,
-        };
-
-        let cubemap_view = {
-            let texture = device.create_texture(&wgpu::TextureDescriptor {
-                size: texture_extent,
-                array_layer_count: 6,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8Unorm,
-                usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-            });
-            
-            let cubemap_data = cubemap_maps.into_iter().map(|m| m.into_vec()).flatten().collect::<Vec<_>>();
-            let cubemap_buf = device
-                .create_buffer_mapped(cubemap_data.len(), wgpu::BufferUsage::COPY_SRC)
-                .fill_from_slice(&cubemap_data);
-        
-            for i in 0..=5 {
-                cmd_encoder.copy_buffer_to_texture(
-                    wgpu::BufferCopyView {
-                        buffer: &cubemap_buf,
-                        offset: (i as u64) * 4 * (cubemap_width as u64) * (cubemap_height as u64),
-                        row_pitch: 4 * cubemap_width,
-                        image_height: cubemap_height,
-                    },
-                    wgpu::TextureCopyView {
-                        texture: &texture,
-                        mip_level: 0,
-                        array_layer: i as u32,
-                        origin: wgpu::Origin3d { x: 0f32, y: 0f32, z: 0f32 },
-                    },
-                    texture_extent
-                );
-            }
-
-            texture.create_view(&wgpu::TextureViewDescriptor {
-                format: wgpu::TextureFormat::Rgba8Unorm,
-                dimension: wgpu::TextureViewDimension::Cube,
-                aspect: wgpu::TextureAspect::default(),
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                array_layer_count: 6,
-            })
-        };
-        
-        queue.submit(&[cmd_encoder.finish()]);
-
         let background_plane_verts: Vec<f32> = vec![
             -1.0,  1.0, -1.0,
             -1.0, -1.0, -1.0,
@@ -228,9 +167,9 @@ impl SkyBoxRenderer {
             address_mode_u: wgpu::AddressMode::Repeat,
             address_mode_v: wgpu::AddressMode::Repeat,
             address_mode_w: wgpu::AddressMode::Repeat,
-            mag_filter: wgpu::FilterMode::Nearest,
+            mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
             lod_min_clamp: -100.0,
             lod_max_clamp: 100.0,
             compare_function: wgpu::CompareFunction::Always,
@@ -240,6 +179,12 @@ impl SkyBoxRenderer {
             .create_buffer_mapped(2, wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_WRITE)
             .fill_from_slice(&[view_trans, proj_trans]);
 
+        // exposure and the mip level of the fully rough specular reflections
+        let specular_max_lod = (EnvironmentMaps::SPECULAR_LEVELS - 1) as f32;
+        let exposure_buffer = device
+            .create_buffer_mapped(4, wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST)
+            .fill_from_slice(&[exposure, specular_max_lod, 0.0, 0.0]);
+
         let cubemap_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &cubemap_bind_group_layout,
             bindings: &[
@@ -249,7 +194,7 @@ impl SkyBoxRenderer {
                 },
                 wgpu::Binding {
                     binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&cubemap_view),
+                    resource: wgpu::BindingResource::TextureView(environment.get_skybox()),
                 },
                 wgpu::Binding {
                     binding: 2,
@@ -258,23 +203,71 @@ impl SkyBoxRenderer {
                         range: 0 .. 2 * 64,
                     },
                 },
+                wgpu::Binding {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &exposure_buffer,
+                        range: 0 .. 16,
+                    },
+                },
             ]
         });
 
-        Self { background_plane, cubemap_bind_group, pipeline, transforms_buffer }
-    }
+        // the lookup table is sampled right up to its edges
+        let clamp_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::Always,
+        });
 
-    pub fn render(&self, frame: &wgpu::SwapChainOutput, cmd_encoder: &mut wgpu::CommandEncoder) {
-        let mut rpass = cmd_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                attachment: &frame.view,
-                resolve_target: None,
-                load_op: wgpu::LoadOp::Clear,
-                store_op: wgpu::StoreOp::Store,
-                clear_color: wgpu::Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 },
-            }],
-            depth_stencil_attachment: None,
+        let environment_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: environment_bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(&clamp_sampler),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(environment.get_irradiance()),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(environment.get_specular()),
+                },
+                wgpu::Binding {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(assets.get_brdf_lut()),
+                },
+                wgpu::Binding {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &exposure_buffer,
+                        range: 0 .. 16,
+                    },
+                },
+            ]
         });
+
+        Self {
+            _environment: environment,
+            background_plane,
+            cubemap_bind_group,
+            environment_bind_group,
+            pipeline,
+            transforms_buffer,
+            exposure_buffer,
+        }
+    }
+
+    /// Draws into a pass that already has the scene's depth, expects the opaque geometry to be drawn.
+    pub fn render(&self, rpass: &mut wgpu::RenderPass) {
         rpass.set_pipeline(&self.pipeline);
         rpass.set_bind_group(0, &self.cubemap_bind_group, &[]);
         rpass.set_vertex_buffers(0, &[(&self.background_plane, 0)]);
@@ -282,6 +275,19 @@ impl SkyBoxRenderer {
         rpass.draw(0..36, 0..1);
     }
 
+    pub fn set_exposure(&self, device: &wgpu::Device, queue: &mut wgpu::Queue, exposure: f32) {
+        let mut cmd_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        let temp_buffer = device
+            .create_buffer_mapped(1, wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&[exposure]);
+        cmd_encoder.copy_buffer_to_buffer(&temp_buffer, 0, &self.exposure_buffer, 0, 4);
+        queue.submit(&[cmd_encoder.finish()]);
+    }
+
+    pub fn get_environment_bind_group(&self) -> &wgpu::BindGroup {
+        &self.environment_bind_group
+    }
+
     pub fn get_transforms_buffer(&self) -> &wgpu::Buffer {
         &self.transforms_buffer
     }
@@ -0,0 +1,277 @@
+use glam::{Mat4, Quat, Vec3};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId(usize);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub fn new(translation: Vec3, rotation: Quat, scale: Vec3) -> Self {
+        Self { translation, rotation, scale }
+    }
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self { translation, ..Self::default() }
+    }
+
+    pub fn to_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::zero(),
+            rotation: Quat::identity(),
+            scale: Vec3::one(),
+        }
+    }
+}
+
+struct Node {
+    local: Transform,
+    world: Mat4,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    /// Local transform changed since the world matrix was last computed.
+    dirty: bool,
+    /// Index of the model drawn at this node, if any.
+    model: Option<usize>,
+}
+
+/// Hierarchy of transforms, world matrices are cached and only recomputed for
+/// the subtrees whose local transforms changed.
+pub struct SceneGraph {
+    nodes: Vec<Option<Node>>,
+    free_slots: Vec<usize>,
+    roots: Vec<NodeId>,
+    /// Some world matrix changed since the last `update`, renderers use this to re-upload.
+    changed: bool,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self { nodes: vec![], free_slots: vec![], roots: vec![], changed: false }
+    }
+
+    pub fn add_node(&mut self, parent: Option<NodeId>, local: Transform, model: Option<usize>) -> NodeId {
+        let node = Node { local, world: Mat4::identity(), parent, children: vec![], dirty: true, model };
+        let id = match self.free_slots.pop() {
+            Some(slot) => {
+                self.nodes[slot] = Some(node);
+                NodeId(slot)
+            },
+            None => {
+                self.nodes.push(Some(node));
+                NodeId(self.nodes.len() - 1)
+            },
+        };
+        match parent {
+            Some(parent) => self.node_mut(parent).children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    /// Removes the node together with all its descendants.
+    pub fn remove_node(&mut self, id: NodeId) {
+        let parent = self.node(id).parent;
+        self.siblings_mut(parent).retain(|&c| c != id);
+
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.nodes[id.0].take() {
+                stack.extend(node.children);
+                self.free_slots.push(id.0);
+            }
+        }
+        self.changed = true;
+    }
+
+    /// Moves the node under a new parent, keeping its local transform.
+    /// Panics if `parent` is the node itself or one of its descendants.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) {
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            assert!(a != id, "a node can't be moved under itself or its descendants");
+            ancestor = self.node(a).parent;
+        }
+        let old = self.node(id).parent;
+        self.siblings_mut(old).retain(|&c| c != id);
+        self.siblings_mut(parent).push(id);
+        let node = self.node_mut(id);
+        node.parent = parent;
+        node.dirty = true;
+    }
+
+    pub fn get_parent(&self, id: NodeId) -> Option<NodeId> {
+        self.node(id).parent
+    }
+
+    pub fn get_children(&self, id: NodeId) -> &[NodeId] {
+        &self.node(id).children
+    }
+
+    pub fn get_local(&self, id: NodeId) -> &Transform {
+        &self.node(id).local
+    }
+
+    pub fn set_local(&mut self, id: NodeId, local: Transform) {
+        let node = self.node_mut(id);
+        node.local = local;
+        node.dirty = true;
+    }
+
+    pub fn set_translation(&mut self, id: NodeId, translation: Vec3) {
+        let mut local = *self.get_local(id);
+        local.translation = translation;
+        self.set_local(id, local);
+    }
+
+    pub fn set_rotation(&mut self, id: NodeId, rotation: Quat) {
+        let mut local = *self.get_local(id);
+        local.rotation = rotation;
+        self.set_local(id, local);
+    }
+
+    pub fn set_scale(&mut self, id: NodeId, scale: Vec3) {
+        let mut local = *self.get_local(id);
+        local.scale = scale;
+        self.set_local(id, local);
+    }
+
+    /// World matrix as of the last `update`.
+    pub fn get_world_matrix(&self, id: NodeId) -> Mat4 {
+        self.node(id).world
+    }
+
+    pub fn get_model(&self, id: NodeId) -> Option<usize> {
+        self.node(id).model
+    }
+
+    /// Recomputes world matrices of dirty nodes and everything below them.
+    /// Returns whether any world matrix changed since the previous call.
+    pub fn update(&mut self) -> bool {
+        let mut stack: Vec<(NodeId, bool)> = self.roots.iter().map(|&r| (r, false)).collect();
+        while let Some((id, parent_changed)) = stack.pop() {
+            let parent_world = self.node(id).parent.map(|p| self.node(p).world);
+            let node = self.node_mut(id);
+            let recompute = node.dirty || parent_changed;
+            if recompute {
+                let local = node.local.to_matrix();
+                node.world = match parent_world {
+                    Some(parent_world) => parent_world * local,
+                    None => local,
+                };
+                node.dirty = false;
+            }
+            stack.extend(node.children.iter().map(|&c| (c, recompute)));
+            self.changed |= recompute;
+        }
+        std::mem::replace(&mut self.changed, false)
+    }
+
+    /// Nodes that draw something, with their model index and world matrix.
+    pub fn renderables(&self) -> impl Iterator<Item = (NodeId, usize, Mat4)> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, n)| n.as_ref().and_then(|n| n.model.map(|m| (NodeId(i), m, n.world))))
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        self.nodes[id.0].as_ref().expect("node was removed")
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.nodes[id.0].as_mut().expect("node was removed")
+    }
+
+    fn siblings_mut(&mut self, parent: Option<NodeId>) -> &mut Vec<NodeId> {
+        match parent {
+            Some(parent) => &mut self.node_mut(parent).children,
+            None => &mut self.roots,
+        }
+    }
+}
+
+impl Default for SceneGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn children_follow_their_parents() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(None, Transform::from_translation(Vec3::new(1.0, 0.0, 0.0)), None);
+        let arm = scene.add_node(Some(root), Transform::new(
+            Vec3::new(0.0, 2.0, 0.0),
+            Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+            Vec3::one(),
+        ), None);
+        let hand = scene.add_node(Some(arm), Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)), Some(0));
+        assert!(scene.update());
+
+        let hand_pos = scene.get_world_matrix(hand).transform_point3(Vec3::zero());
+        assert!(approx(hand_pos, Vec3::new(2.0, 2.0, 0.0)), "{:?}", hand_pos);
+
+        // only moving the root must still move the whole chain
+        scene.set_translation(root, Vec3::new(-1.0, 0.0, 0.0));
+        assert!(scene.update());
+        let hand_pos = scene.get_world_matrix(hand).transform_point3(Vec3::zero());
+        assert!(approx(hand_pos, Vec3::new(0.0, 2.0, 0.0)), "{:?}", hand_pos);
+        assert!(!scene.update());
+    }
+
+    #[test]
+    fn reparenting_and_removal() {
+        let mut scene = SceneGraph::new();
+        let a = scene.add_node(None, Transform::from_translation(Vec3::new(5.0, 0.0, 0.0)), None);
+        let b = scene.add_node(None, Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)), Some(1));
+        scene.update();
+
+        scene.set_parent(b, Some(a));
+        scene.update();
+        assert_eq!(scene.get_parent(b), Some(a));
+        assert!(approx(scene.get_world_matrix(b).transform_point3(Vec3::zero()), Vec3::new(5.0, 0.0, 1.0)));
+        assert_eq!(scene.renderables().count(), 1);
+
+        scene.remove_node(a);
+        assert_eq!(scene.renderables().count(), 0);
+        let c = scene.add_node(None, Transform::default(), None);
+        assert!(c == a || c == b);
+    }
+
+    #[test]
+    #[should_panic]
+    fn node_cant_be_its_own_parent() {
+        let mut scene = SceneGraph::new();
+        let a = scene.add_node(None, Transform::default(), None);
+        scene.set_parent(a, Some(a));
+    }
+
+    #[test]
+    #[should_panic]
+    fn node_cant_move_under_its_descendants() {
+        let mut scene = SceneGraph::new();
+        let a = scene.add_node(None, Transform::default(), None);
+        let b = scene.add_node(Some(a), Transform::default(), None);
+        let c = scene.add_node(Some(b), Transform::default(), None);
+        scene.set_parent(a, Some(c));
+    }
+}
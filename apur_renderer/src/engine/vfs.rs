@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Where the files of a mount come from.
+enum Source {
+    Dir(PathBuf),
+    /// Zip archive, .pak files are zips too.
+    Archive(Mutex<zip::ZipArchive<File>>),
+    Embedded(HashMap<String, &'static [u8]>),
+}
+
+struct Mount {
+    /// Virtual directory the mount shows up under, empty for the root.
+    at: String,
+    source: Source,
+}
+
+/// Read-only files under virtual paths like "models/sponza.obj", backed by directories,
+/// zip archives and files built into the binary. Later mounts shadow earlier ones.
+pub struct Vfs {
+    mounts: Vec<Mount>,
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Self { mounts: vec![] }
+    }
+
+    /// The shaders built into the binary, the res directory over them and any .pak
+    /// archives in it over that, in name order.
+    pub fn with_default_mounts() -> Self {
+        let mut vfs = Self::new();
+        vfs.mount_embedded("shaders", &[
+            ("shader.vert.spv", include_bytes!("../../res/shaders/shader.vert.spv")),
+            ("shader.frag.spv", include_bytes!("../../res/shaders/shader.frag.spv")),
+            ("skybox.vert.spv", include_bytes!("../../res/shaders/skybox.vert.spv")),
+            ("skybox.frag.spv", include_bytes!("../../res/shaders/skybox.frag.spv")),
+        ]);
+
+        let res = Self::find_res_dir();
+        println!("[Info] Mounting asset directory: {}", res.display());
+        let mut paks: Vec<_> = std::fs::read_dir(&res)
+            .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+            .unwrap_or_default();
+        paks.retain(|path| path.extension().is_some_and(|ext| ext == "pak"));
+        paks.sort();
+        vfs.mount_dir("", res);
+        for pak in paks {
+            match vfs.mount_archive("", &pak) {
+                Ok(()) => println!("[Info] Mounted archive: {}", pak.display()),
+                Err(err) => println!("[Error] Failed to mount archive {}: {}", pak.display(), err),
+            }
+        }
+        vfs
+    }
+
+    /// $APUR_RES if set, otherwise the first res directory found next to the executable,
+    /// in the working directory or in the crate the binary was built from.
+    fn find_res_dir() -> PathBuf {
+        if let Some(dir) = std::env::var_os("APUR_RES") {
+            return PathBuf::from(dir);
+        }
+        let next_to_exe = std::env::current_exe().ok().and_then(|exe| Some(exe.parent()?.join("res")));
+        next_to_exe
+            .into_iter()
+            .chain(Some(PathBuf::from("res")))
+            .find(|dir| dir.is_dir())
+            .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/res")))
+    }
+
+    pub fn mount_dir(&mut self, at: &str, dir: impl Into<PathBuf>) {
+        self.mounts.push(Mount { at: normalize(at), source: Source::Dir(dir.into()) });
+    }
+
+    pub fn mount_archive(&mut self, at: &str, archive: impl AsRef<Path>) -> io::Result<()> {
+        let archive = zip::ZipArchive::new(File::open(archive)?).map_err(zip_error)?;
+        self.mounts.push(Mount { at: normalize(at), source: Source::Archive(Mutex::new(archive)) });
+        Ok(())
+    }
+
+    pub fn mount_embedded(&mut self, at: &str, files: &[(&str, &'static [u8])]) {
+        let files = files.iter().map(|&(path, bytes)| (normalize(path), bytes)).collect();
+        self.mounts.push(Mount { at: normalize(at), source: Source::Embedded(files) });
+    }
+
+    /// Where the file `read` would return lives on disk, `None` if it comes from an archive,
+    /// is built into the binary or doesn't exist.
+    pub fn disk_path(&self, path: &str) -> Option<PathBuf> {
+        let path = normalize(path);
+        for mount in self.mounts.iter().rev() {
+            let relative = match strip_dir(&path, &mount.at) {
+                Some(relative) => relative,
+                None => continue,
+            };
+            match &mount.source {
+                Source::Dir(dir) if !relative.starts_with("..") && dir.join(relative).is_file() => {
+                    return Some(dir.join(relative));
+                },
+                Source::Dir(_) => { },
+                Source::Archive(archive) => {
+                    if archive.lock().expect("archive mount poisoned").by_name(relative).is_ok() {
+                        return None;
+                    }
+                },
+                Source::Embedded(files) => {
+                    if files.contains_key(relative) {
+                        return None;
+                    }
+                },
+            }
+        }
+        None
+    }
+
+    /// Contents of the file from the last mount that has it.
+    pub fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let path = normalize(path);
+        for mount in self.mounts.iter().rev() {
+            let relative = match strip_dir(&path, &mount.at) {
+                Some(relative) => relative,
+                None => continue,
+            };
+            let result = match &mount.source {
+                // paths climbing out of the directory don't get to see the rest of the disk
+                Source::Dir(_) if relative.starts_with("..") => continue,
+                Source::Dir(dir) => std::fs::read(dir.join(relative)),
+                Source::Archive(archive) => {
+                    let mut archive = archive.lock().expect("archive mount poisoned");
+                    archive.by_name(relative).map_err(zip_error).and_then(|mut file| {
+                        let mut bytes = Vec::with_capacity(file.size() as usize);
+                        file.read_to_end(&mut bytes).map(|_| bytes)
+                    })
+                },
+                Source::Embedded(files) => match files.get(relative) {
+                    Some(bytes) => Ok(bytes.to_vec()),
+                    None => continue,
+                },
+            };
+            match result {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                result => return result,
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("{} isn't in any mount", path)))
+    }
+}
+
+impl Default for Vfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn zip_error(err: zip::result::ZipError) -> io::Error {
+    match err {
+        zip::result::ZipError::Io(err) => err,
+        zip::result::ZipError::FileNotFound => io::Error::new(io::ErrorKind::NotFound, err),
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+/// Forward slashes, no empty or "." components and ".." folded into its parent where there is one.
+pub fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = vec![];
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => { },
+            ".." if parts.last().is_some_and(|&last| last != "..") => { parts.pop(); },
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// `path` relative to the virtual directory `dir`.
+pub fn join(dir: &str, path: &str) -> String {
+    normalize(&format!("{}/{}", dir, path))
+}
+
+/// Virtual directory a file is in, empty for the root.
+pub fn parent(path: &str) -> &str {
+    path.rfind('/').map_or("", |slash| &path[..slash])
+}
+
+fn strip_dir<'a>(path: &'a str, dir: &str) -> Option<&'a str> {
+    if dir.is_empty() {
+        return Some(path);
+    }
+    let rest = path.strip_prefix(dir)?;
+    rest.strip_prefix('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(normalize("models\\textures/./wall.png"), "models/textures/wall.png");
+        assert_eq!(normalize("/models//sponza.obj"), "models/sponza.obj");
+        assert_eq!(join("models/gltf", "../textures/a.png"), "models/textures/a.png");
+        assert_eq!(normalize("../outside"), "../outside");
+        assert_eq!(parent("models/sponza.obj"), "models");
+        assert_eq!(parent("sponza.obj"), "");
+    }
+
+    #[test]
+    fn later_mounts_shadow_earlier_ones() {
+        let mut vfs = Vfs::new();
+        vfs.mount_embedded("", &[("a.txt", b"first"), ("b.txt", b"only")]);
+        vfs.mount_embedded("", &[("a.txt", b"second")]);
+        vfs.mount_embedded("sub", &[("c.txt", b"nested")]);
+        assert_eq!(vfs.read("a.txt").unwrap(), b"second");
+        assert_eq!(vfs.read("b.txt").unwrap(), b"only");
+        assert_eq!(vfs.read("sub/c.txt").unwrap(), b"nested");
+        assert_eq!(vfs.read("c.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(vfs.read("subway/c.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn directories_and_archives_can_be_mounted() {
+        let mut vfs = Vfs::new();
+        vfs.mount_dir("", concat!(env!("CARGO_MANIFEST_DIR"), "/res"));
+        assert!(vfs.read("models/plane.obj").is_ok());
+        assert_eq!(vfs.read("../Cargo.toml").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let pak = std::env::temp_dir().join(format!("apur_vfs_test_{}.pak", std::process::id()));
+        {
+            let mut writer = zip::ZipWriter::new(File::create(&pak).unwrap());
+            writer.start_file("models/plane.obj", zip::write::FileOptions::default()).unwrap();
+            io::Write::write_all(&mut writer, b"patched").unwrap();
+            writer.finish().unwrap();
+        }
+        vfs.mount_archive("", &pak).unwrap();
+        assert_eq!(vfs.read("models/plane.obj").unwrap(), b"patched");
+        assert!(vfs.read("models/cube.obj").is_ok());
+        std::fs::remove_file(&pak).unwrap();
+    }
+}
@@ -1,7 +1,8 @@
+use std::path::Path;
 use std::time::{Duration, Instant};
 
 use winit::{
-    event::{Event, WindowEvent, DeviceEvent},
+    event::{Event, WindowEvent, DeviceEvent, MouseButton, ElementState},
     event_loop::{EventLoop, ControlFlow},
     window::{WindowBuilder},
     dpi::LogicalSize,
@@ -9,16 +10,33 @@ use winit::{
 
 mod engine;
 
-use engine::{Engine};
+use engine::{bake_models, Engine};
 
-fn handle_window_event(ngn: &mut Engine, event: WindowEvent, close_request: &mut bool, spf: Duration) {
+fn handle_window_event(ngn: &mut Engine, event: WindowEvent, close_request: &mut bool, spf: Duration, cursor: &mut (f64, f64)) {
     match event {
         WindowEvent::CloseRequested => *close_request = true,
+        WindowEvent::CursorMoved { position, .. } => *cursor = (position.x, position.y),
+        WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. } => {
+            match ngn.pick(cursor.0, cursor.1) {
+                Some(hit) => println!("[Info] Picked {:?} at {:?}, {} units away", hit.body, hit.point, hit.distance),
+                None => println!("[Info] Picked nothing"),
+            }
+        },
         WindowEvent::KeyboardInput { input, .. } => {
             match input.scancode {
                 // escape key
                 0x01 => *close_request = true,
-                0x11 | 0x1F => ngn.move_camera(input.scancode == 0x11),
+                // W, A, S, D and space
+                0x11 | 0x1E | 0x1F | 0x20 | 0x39 => ngn.handle_move_key(input.scancode, input.state == ElementState::Pressed),
+                // F1
+                0x3B if input.state == ElementState::Pressed => ngn.toggle_fly_mode(),
+                // F2
+                0x3C if input.state == ElementState::Pressed => ngn.toggle_shading_model(),
+                // F3
+                0x3D if input.state == ElementState::Pressed => ngn.toggle_flashlight(),
+                // [ and ] darken and brighten the sky by half a stop
+                0x1A if input.state == ElementState::Pressed => ngn.set_sky_exposure(ngn.get_sky_exposure() - 0.5),
+                0x1B if input.state == ElementState::Pressed => ngn.set_sky_exposure(ngn.get_sky_exposure() + 0.5),
                 0x21 => println!("FPS: {}", 1.0 / spf.as_secs_f32()),
                 _ => { },
             }
@@ -39,6 +57,23 @@ fn handle_device_event(ngn: &mut Engine, event: DeviceEvent) {
 
 fn main() {
     
+    // `apur_renderer --bake <dir>` writes the mesh caches of a directory of objs and exits
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [flag, dir] = &args[..] {
+        if flag == "--bake" {
+            let failed = bake_models(Path::new(dir));
+            std::process::exit(if failed == 0 { 0 } else { 1 });
+        }
+    }
+
+    // `--skybox <name>` picks the cubemap drawn behind the scene, `--exposure <stops>` brightens it
+    let option = |name: &str| args.windows(2).find(|pair| pair[0] == name).map(|pair| pair[1].clone());
+    let skybox = option("--skybox");
+    let exposure = option("--exposure").map(|stops| stops.parse::<f32>().unwrap_or_else(|_| {
+        println!("[Error] --exposure takes a number of stops, got {}", stops);
+        0.0
+    }));
+
     const WIDTH: u16 = 800;
     const HEIGHT: u16 = 600;
     
@@ -52,21 +87,25 @@ fn main() {
     window.set_cursor_visible(false);
     // window.set_cursor_grab(true).expect("Couldn't lock the cursor...");
     
-    let mut ngn = Engine::new(&window);
+    let mut ngn = Engine::new(&window, skybox.as_deref(), exposure.unwrap_or(0.0));
     let mut close_request = false;
     let mut last_tick = Instant::now();
+    let mut last_update = Instant::now();
+    let mut cursor = (0.0, 0.0);
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         let cur_tick = Instant::now();
 
         match event {
-            Event::WindowEvent { event, ..} => handle_window_event(&mut ngn, event, &mut close_request, cur_tick - last_tick),
+            Event::WindowEvent { event, ..} => handle_window_event(&mut ngn, event, &mut close_request, cur_tick - last_tick, &mut cursor),
             Event::MainEventsCleared => {
                 if close_request {
                     println!("Shutting down...");
                     *control_flow = ControlFlow::Exit;
                 } else {
+                    ngn.update(cur_tick - last_update);
+                    last_update = cur_tick;
                     window.request_redraw();
                 }
             },